@@ -1,16 +1,57 @@
 use crate::{
-    document::Document,
+    cli::{Launch, Target},
+    document::{line_content_len, Document, EditKind, Selection},
     editor::Editor,
-    shortcuts::{detect, ShortcutCommand},
-    ui,
+    encoding::{self, TextEncoding},
+    history,
+    instance::Handoff,
+    keymap::Keymap,
+    line_ending::LineEnding,
+    multi_search::{self, FileSearch, Source, TabSnapshot},
+    recovery::{Journal, Leftover},
+    search::{
+        CompiledSearch, Indexed, MatchIndex, Search, SearchKey, SearchOptions, SearchService,
+    },
+    session::{FindSession, Session, SessionTab},
+    settings::{Backup, Settings, Theme},
+    shortcuts::{self, KeyChord, ShortcutCommand},
+    ui::{
+        self, editor_view::WordWrap, find_in_files::FindInFilesAction, results_panel::ResultsAction,
+    },
     updater::{self, UpdateStatus},
+    watcher::{DiskChange, FileWatcher},
 };
 use arboard::Clipboard;
 use eframe::egui;
-use eframe::egui::text::{CCursor, CCursorRange};
-use regex::Regex;
 use rfd::FileDialog;
-use std::ops::Range;
+use ropey::Rope;
+use std::{
+    cell::Cell,
+    collections::HashSet,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        mpsc::{Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// How many recently opened or saved files the command palette offers.
+const RECENT_FILES: usize = 20;
+/// Add Next Occurrence and Select All Occurrences look for the selected text
+/// exactly as it is.
+const EXACT: SearchOptions = SearchOptions {
+    use_regex: false,
+    match_case: true,
+    whole_word: false,
+};
+/// How long typing has to pause before the recovery journal is written.
+const JOURNAL_IDLE: Duration = Duration::from_secs(1);
+/// How far the journal may fall behind during continuous typing.
+const JOURNAL_MAX_DELAY: Duration = Duration::from_secs(10);
 
 #[derive(Clone, Copy)]
 enum AppCommand {
@@ -20,6 +61,17 @@ enum AppCommand {
     Save,
     SaveAs,
     SaveAll,
+    SaveWithEncoding(TextEncoding),
+    SetBackup(Backup),
+    SetAutosave(Option<u64>),
+    ToggleRestoreSession(bool),
+    ToggleKeepUndoHistory(bool),
+    Preferences,
+    KeyboardShortcuts,
+    CommandPalette,
+    ReopenWithEncoding(TextEncoding),
+    /// Opens an entry of the recent files list.
+    OpenRecent(usize),
     CloseAll,
     CloseTab,
     CloseTabsLeft,
@@ -31,13 +83,23 @@ enum AppCommand {
     Undo,
     Redo,
     Find,
+    FindNext,
+    FindPrevious,
+    FindInTabs,
+    FindInFiles,
     Replace,
+    GoTo,
     Cut,
     Copy,
     Paste,
     SelectAll,
+    AddNextOccurrence,
+    SelectAllOccurrences,
+    ConvertLineEndings(LineEnding),
+    ToggleReadOnly(bool),
     ToggleStatusBar(bool),
     ToggleLineNumbers(bool),
+    SetWordWrap(WordWrap),
     Quit,
     ForceQuit,
     CheckForUpdates,
@@ -52,6 +114,21 @@ impl From<ui::menu::MenuAction> for AppCommand {
             ui::menu::MenuAction::Save => Self::Save,
             ui::menu::MenuAction::SaveAs => Self::SaveAs,
             ui::menu::MenuAction::SaveAll => Self::SaveAll,
+            ui::menu::MenuAction::SaveWithEncoding(encoding) => Self::SaveWithEncoding(encoding),
+            ui::menu::MenuAction::SetBackup(choice) => Self::SetBackup(choice),
+            ui::menu::MenuAction::SetAutosave(secs) => Self::SetAutosave(secs),
+            ui::menu::MenuAction::ToggleRestoreSession(enabled) => {
+                Self::ToggleRestoreSession(enabled)
+            }
+            ui::menu::MenuAction::ToggleKeepUndoHistory(enabled) => {
+                Self::ToggleKeepUndoHistory(enabled)
+            }
+            ui::menu::MenuAction::Preferences => Self::Preferences,
+            ui::menu::MenuAction::KeyboardShortcuts => Self::KeyboardShortcuts,
+            ui::menu::MenuAction::CommandPalette => Self::CommandPalette,
+            ui::menu::MenuAction::ReopenWithEncoding(encoding) => {
+                Self::ReopenWithEncoding(encoding)
+            }
             ui::menu::MenuAction::CloseAll => Self::CloseAll,
             ui::menu::MenuAction::CloseTab => Self::CloseTab,
             ui::menu::MenuAction::CloseTabsLeft => Self::CloseTabsLeft,
@@ -59,13 +136,25 @@ impl From<ui::menu::MenuAction> for AppCommand {
             ui::menu::MenuAction::Undo => Self::Undo,
             ui::menu::MenuAction::Redo => Self::Redo,
             ui::menu::MenuAction::Find => Self::Find,
+            ui::menu::MenuAction::FindNext => Self::FindNext,
+            ui::menu::MenuAction::FindPrevious => Self::FindPrevious,
+            ui::menu::MenuAction::FindInTabs => Self::FindInTabs,
+            ui::menu::MenuAction::FindInFiles => Self::FindInFiles,
             ui::menu::MenuAction::Replace => Self::Replace,
+            ui::menu::MenuAction::GoTo => Self::GoTo,
             ui::menu::MenuAction::Cut => Self::Cut,
             ui::menu::MenuAction::Copy => Self::Copy,
             ui::menu::MenuAction::Paste => Self::Paste,
             ui::menu::MenuAction::SelectAll => Self::SelectAll,
+            ui::menu::MenuAction::AddNextOccurrence => Self::AddNextOccurrence,
+            ui::menu::MenuAction::SelectAllOccurrences => Self::SelectAllOccurrences,
+            ui::menu::MenuAction::ConvertLineEndings(line_ending) => {
+                Self::ConvertLineEndings(line_ending)
+            }
+            ui::menu::MenuAction::ToggleReadOnly(enabled) => Self::ToggleReadOnly(enabled),
             ui::menu::MenuAction::ToggleStatusBar(enabled) => Self::ToggleStatusBar(enabled),
             ui::menu::MenuAction::ToggleLineNumbers(enabled) => Self::ToggleLineNumbers(enabled),
+            ui::menu::MenuAction::SetWordWrap(wrap) => Self::SetWordWrap(wrap),
             ui::menu::MenuAction::Quit => Self::Quit,
             ui::menu::MenuAction::ForceQuit => Self::ForceQuit,
             ui::menu::MenuAction::CheckForUpdates => Self::CheckForUpdates,
@@ -73,33 +162,91 @@ impl From<ui::menu::MenuAction> for AppCommand {
     }
 }
 
-impl From<ShortcutCommand> for AppCommand {
-    fn from(command: ShortcutCommand) -> Self {
-        match command {
-            ShortcutCommand::NewTab => Self::NewTab,
-            ShortcutCommand::Open => Self::Open,
-            ShortcutCommand::Print => Self::Print,
-            ShortcutCommand::Save => Self::Save,
-            ShortcutCommand::SaveAs => Self::SaveAs,
-            ShortcutCommand::CloseTab => Self::CloseTab,
-            ShortcutCommand::NextTab => Self::NextTab,
-            ShortcutCommand::PreviousTab => Self::PreviousTab,
-            ShortcutCommand::SelectTab(index) => Self::SelectTab(index),
-            ShortcutCommand::SelectLastTab => Self::SelectLastTab,
-            ShortcutCommand::Find => Self::Find,
-            ShortcutCommand::Replace => Self::Replace,
-            ShortcutCommand::Quit => Self::Quit,
-        }
-    }
-}
-
-#[derive(Default)]
 struct FindPanel {
     open: bool,
     query: String,
     replace: String,
     use_regex: bool,
+    match_case: bool,
+    whole_word: bool,
+    /// Find Next goes up; Find Previous then goes down.
+    backwards: bool,
+    wrap_around: bool,
+    /// Replace All and Select All only touch matches inside the selections.
+    in_selection: bool,
     error: Option<String>,
+    /// Set when the last search went past one end of the text and carried on
+    /// from the other.
+    notice: Option<String>,
+    compiled: CompiledSearch,
+}
+
+impl Default for FindPanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            query: String::new(),
+            replace: String::new(),
+            use_regex: false,
+            match_case: false,
+            whole_word: false,
+            backwards: false,
+            wrap_around: true,
+            in_selection: false,
+            error: None,
+            notice: None,
+            compiled: CompiledSearch::default(),
+        }
+    }
+}
+
+impl FindPanel {
+    fn options(&self) -> SearchOptions {
+        SearchOptions {
+            use_regex: self.use_regex,
+            match_case: self.match_case,
+            whole_word: self.whole_word,
+        }
+    }
+
+    fn key(&self, doc: &Document) -> SearchKey {
+        SearchKey {
+            doc_id: doc.id(),
+            revision: doc.revision(),
+            query: self.query.clone(),
+            options: self.options(),
+        }
+    }
+
+    /// The compiled query, or records why it cannot be searched for.
+    fn search(&mut self, empty_error: &str) -> Option<Arc<Search>> {
+        if self.query.is_empty() {
+            self.error = Some(empty_error.to_string());
+            return None;
+        }
+        match self.compiled.get(&self.query, self.options()) {
+            Ok(search) => Some(search),
+            Err(error) => {
+                self.error = Some(error);
+                None
+            }
+        }
+    }
+}
+
+/// Tabs a `--wait` launch is waiting on, and which of them were saved.
+struct Waiter {
+    ids: Vec<u64>,
+    saved: HashSet<u64>,
+    /// Takes the answer for a launch handed off from another process; `None`
+    /// for this process's own launch, which ends by closing the window.
+    done: Option<Sender<bool>>,
+}
+
+impl Waiter {
+    fn all_saved(&self) -> bool {
+        self.ids.iter().all(|id| self.saved.contains(id))
+    }
 }
 
 pub struct PlainpadApp {
@@ -111,15 +258,64 @@ pub struct PlainpadApp {
     error_message: Option<String>,
     editor_focused: bool,
     editor_id: Option<egui::Id>,
-    show_status_bar: bool,
-    show_line_numbers: bool,
+    settings: Settings,
+    /// Notices edits to the settings file made outside the Preferences window.
+    settings_watcher: Option<FileWatcher>,
+    preferences: ui::preferences::PreferencesDialog,
+    /// Set while a change made in Preferences is not written yet. Dragging a
+    /// value writes the file once the drag ends.
+    settings_unsaved: bool,
+    keymap: Keymap,
+    keymap_editor: ui::keymap_editor::KeymapEditor,
+    /// The first chord of a two-key shortcut, while the second is awaited.
+    pending_chord: Option<KeyChord>,
+    command_palette: ui::command_palette::CommandPalette,
+    /// Files opened or saved lately, most recent first.
+    recent_files: Vec<PathBuf>,
     find_panel: FindPanel,
+    search_service: SearchService,
+    results: ui::results_panel::ResultsPanel,
+    find_in_files: ui::find_in_files::FindInFilesDialog,
+    /// The search and replacement of the replace preview in the results.
+    file_replace: Option<(Arc<Search>, String)>,
+    go_to: ui::go_to::GoToDialog,
     update_message: Option<String>,
+    watcher: Option<FileWatcher>,
+    watcher_failed: bool,
+    window_focused: bool,
+    external_changes: Vec<PathBuf>,
+    confirm_overwrite: Option<(usize, PathBuf)>,
+    diff_view: Option<(String, ui::diff::DiffLines)>,
+    journal: Option<Journal>,
+    leftovers: Vec<Leftover>,
+    autosave_due: bool,
+    revisions: Vec<(u64, u64)>,
+    last_edit: Instant,
+    last_journal_write: Instant,
+    /// Off for `--new-window` launches, which must not replace the session
+    /// of the main window.
+    keep_session: bool,
+    /// Files handed over by later launches, when this is the instance they
+    /// find.
+    requests: Option<Receiver<Handoff>>,
+    waiters: Vec<Waiter>,
+    /// Where this process's own `--wait` launch leaves its answer for `main`.
+    wait_result: Option<Rc<Cell<bool>>>,
 }
 
 impl PlainpadApp {
-    pub fn new() -> Self {
-        Self {
+    pub fn new(
+        launch: Launch,
+        requests: Option<Receiver<Handoff>>,
+        wait_result: Option<Rc<Cell<bool>>>,
+    ) -> Self {
+        let journal = Journal::start().ok();
+        let leftovers = journal.as_ref().map(Journal::leftovers).unwrap_or_default();
+        let mut session = Session::load();
+        let recent_files = std::mem::take(&mut session.recent);
+        let (settings, problems) = Settings::load();
+        let (keymap, keymap_problems) = Keymap::load();
+        let mut app = Self {
             editor: Editor::new(),
             confirm_close: None,
             pending_close: Vec::new(),
@@ -128,11 +324,397 @@ impl PlainpadApp {
             error_message: None,
             editor_focused: false,
             editor_id: None,
-            show_status_bar: true,
-            show_line_numbers: false,
+            settings,
+            settings_watcher: None,
+            preferences: ui::preferences::PreferencesDialog::default(),
+            settings_unsaved: false,
+            keymap,
+            keymap_editor: ui::keymap_editor::KeymapEditor::default(),
+            pending_chord: None,
+            command_palette: ui::command_palette::CommandPalette::default(),
+            recent_files,
             find_panel: FindPanel::default(),
+            search_service: SearchService::new(),
+            results: ui::results_panel::ResultsPanel::default(),
+            find_in_files: ui::find_in_files::FindInFilesDialog::default(),
+            file_replace: None,
+            go_to: ui::go_to::GoToDialog::default(),
             update_message: None,
+            watcher: None,
+            watcher_failed: false,
+            window_focused: true,
+            external_changes: Vec::new(),
+            confirm_overwrite: None,
+            diff_view: None,
+            journal,
+            leftovers,
+            autosave_due: false,
+            revisions: Vec::new(),
+            last_edit: Instant::now(),
+            last_journal_write: Instant::now(),
+            keep_session: !launch.new_window && !launch.wait,
+            requests,
+            waiters: Vec::new(),
+            wait_result,
+        };
+        if app.keep_session && app.settings.files.reopen_tabs {
+            app.apply_session(session);
+        }
+        app.report_file_problems("settings", problems);
+        app.report_file_problems("keymap", keymap_problems);
+        let ids = app.open_targets(launch.targets, launch.encoding, launch.read_only);
+        if launch.wait && !ids.is_empty() {
+            app.waiters.push(Waiter {
+                ids,
+                saved: HashSet::new(),
+                done: None,
+            });
+        }
+        app
+    }
+
+    /// Opens what later launches handed over and brings the window forward.
+    fn open_requests(&mut self, ctx: &egui::Context) {
+        let Some(requests) = &self.requests else {
+            return;
+        };
+        let received = requests.try_iter().collect::<Vec<_>>();
+        if received.is_empty() {
+            return;
+        }
+        for Handoff { request, done } in received {
+            let encoding = request.encoding();
+            let ids = self.open_targets(request.targets, encoding, request.read_only);
+            let Some(done) = done else {
+                continue;
+            };
+            // Nothing opened means nothing can be saved; answer right away
+            // instead of waiting on no tabs, which would count as saved.
+            if ids.is_empty() {
+                let _ = done.send(false);
+            } else {
+                self.waiters.push(Waiter {
+                    ids,
+                    saved: HashSet::new(),
+                    done: Some(done),
+                });
+            }
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+        ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(
+            egui::UserAttentionType::Informational,
+        ));
+    }
+
+    /// Opens files and piped text from the command line, each in its own tab,
+    /// and returns the ids of their tabs. A file that is already open is
+    /// switched to instead, and a file that does not exist yet opens empty and
+    /// is created on save.
+    fn open_targets(
+        &mut self,
+        targets: Vec<Target>,
+        encoding: Option<TextEncoding>,
+        read_only: bool,
+    ) -> Vec<u64> {
+        let mut ids = Vec::new();
+        let mut failed = Vec::new();
+        for target in targets {
+            let doc = match target {
+                Target::Stdin(bytes) => self.editor.open_bytes(bytes, encoding),
+                Target::File { path, position } => {
+                    if let Some(index) = self.document_index(&path) {
+                        self.editor.set_active(index);
+                    } else {
+                        let doc = match Document::from_path(path.clone(), encoding) {
+                            Ok(mut doc) => {
+                                if self.settings.files.keep_undo_history {
+                                    history::load(&mut doc);
+                                }
+                                doc
+                            }
+                            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                                Document::new_at(path.clone())
+                            }
+                            Err(err) => {
+                                failed.push(format!("{}: {err}", path.display()));
+                                continue;
+                            }
+                        };
+                        self.editor.add_document(doc);
+                    }
+                    self.remember_recent(path);
+                    let Some(doc) = self.editor.current_mut() else {
+                        continue;
+                    };
+                    if let Some((line, column)) = position {
+                        doc.go_to(line, column);
+                    }
+                    doc
+                }
+            };
+            if read_only {
+                doc.set_read_only(true);
+            }
+            ids.push(doc.id());
+        }
+
+        if !failed.is_empty() {
+            self.error_message = Some(format!("Failed to open file: {}", failed.join("\n")));
+        } else {
+            self.warn_on_decode_errors();
+        }
+        ids
+    }
+
+    /// Answers `--wait` launches whose tabs are all closed now.
+    fn update_waiters(&mut self, ctx: &egui::Context) {
+        if self.waiters.is_empty() {
+            return;
+        }
+        let open = self
+            .editor
+            .documents()
+            .iter()
+            .map(Document::id)
+            .collect::<HashSet<_>>();
+        let (finished, waiting) = std::mem::take(&mut self.waiters)
+            .into_iter()
+            .partition::<Vec<_>, _>(|waiter| waiter.ids.iter().all(|id| !open.contains(id)));
+        self.waiters = waiting;
+        for waiter in finished {
+            if waiter.done.is_none() {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            self.finish_waiter(waiter);
+        }
+    }
+
+    fn finish_waiter(&self, waiter: Waiter) {
+        let saved = waiter.all_saved();
+        match waiter.done {
+            Some(done) => {
+                let _ = done.send(saved);
+            }
+            None => {
+                if let Some(result) = &self.wait_result {
+                    result.set(saved);
+                }
+            }
+        }
+    }
+
+    /// Reopens the tabs of the last session. Files that were deleted or can
+    /// no longer be read are left out and listed in one message.
+    fn apply_session(&mut self, session: Session) {
+        let mut missing = Vec::new();
+        let mut active = None;
+        for (index, tab) in session.tabs.into_iter().enumerate() {
+            match Document::from_path(tab.path.clone(), None) {
+                Ok(mut doc) => {
+                    if self.settings.files.keep_undo_history {
+                        history::load(&mut doc);
+                    }
+                    doc.set_selection(tab.selection);
+                    let view = doc.view_mut();
+                    view.top_line = tab.top_line;
+                    view.scroll_x = tab.scroll_x;
+                    self.editor.add_document(doc);
+                    if index <= session.active {
+                        active = Some(self.editor.active_index());
+                    }
+                }
+                Err(_) => missing.push(tab.path.display().to_string()),
+            }
+        }
+        if let Some(index) = active {
+            self.editor.set_active(index);
+        }
+
+        self.find_panel.open = session.find.open;
+        self.find_panel.query = session.find.query;
+        self.find_panel.replace = session.find.replace;
+        self.find_panel.use_regex = session.find.use_regex;
+        self.find_panel.match_case = session.find.match_case;
+        self.find_panel.whole_word = session.find.whole_word;
+        self.find_panel.backwards = session.find.backwards;
+        self.find_panel.wrap_around = session.find.wrap_around;
+        self.results.on_right = session.find.results_on_right;
+
+        if !missing.is_empty() {
+            self.error_message = Some(format!(
+                "These files from the last session were deleted or cannot be read:\n{}",
+                missing.join("\n")
+            ));
+        }
+    }
+
+    /// Records the open files for the next launch, or an empty session when
+    /// reopening tabs is turned off.
+    fn save_session(&self) {
+        if !self.keep_session {
+            return;
+        }
+        if !self.settings.files.reopen_tabs {
+            let session = Session {
+                recent: self.recent_files.clone(),
+                ..Session::default()
+            };
+            let _ = session.save();
+            return;
         }
+        let documents = self.editor.documents();
+        let is_saved_file = |doc: &&Document| doc.path().is_some() && !doc.is_missing_on_disk();
+        let session = Session {
+            tabs: documents
+                .iter()
+                .filter(is_saved_file)
+                .filter_map(|doc| {
+                    Some(SessionTab {
+                        path: doc.path()?.clone(),
+                        selection: doc.selection(),
+                        top_line: doc.view().top_line,
+                        scroll_x: doc.view().scroll_x,
+                    })
+                })
+                .collect(),
+            active: documents[..self.editor.active_index()]
+                .iter()
+                .filter(is_saved_file)
+                .count(),
+            find: FindSession {
+                open: self.find_panel.open,
+                query: self.find_panel.query.clone(),
+                replace: self.find_panel.replace.clone(),
+                use_regex: self.find_panel.use_regex,
+                match_case: self.find_panel.match_case,
+                whole_word: self.find_panel.whole_word,
+                backwards: self.find_panel.backwards,
+                wrap_around: self.find_panel.wrap_around,
+                results_on_right: self.results.on_right,
+            },
+            recent: self.recent_files.clone(),
+        };
+        let _ = session.save();
+    }
+
+    /// What a shortcut or palette command does now. Toggles flip the current
+    /// state.
+    fn shortcut_action(&self, command: ShortcutCommand) -> AppCommand {
+        match command {
+            ShortcutCommand::NewTab => AppCommand::NewTab,
+            ShortcutCommand::Open => AppCommand::Open,
+            ShortcutCommand::Print => AppCommand::Print,
+            ShortcutCommand::Save => AppCommand::Save,
+            ShortcutCommand::SaveAs => AppCommand::SaveAs,
+            ShortcutCommand::SaveAll => AppCommand::SaveAll,
+            ShortcutCommand::SetBackup(choice) => AppCommand::SetBackup(choice),
+            ShortcutCommand::SetAutosave(secs) => AppCommand::SetAutosave(secs),
+            ShortcutCommand::ToggleRestoreSession => {
+                AppCommand::ToggleRestoreSession(!self.settings.files.reopen_tabs)
+            }
+            ShortcutCommand::CloseTab => AppCommand::CloseTab,
+            ShortcutCommand::CloseAll => AppCommand::CloseAll,
+            ShortcutCommand::CloseTabsLeft => AppCommand::CloseTabsLeft,
+            ShortcutCommand::CloseTabsRight => AppCommand::CloseTabsRight,
+            ShortcutCommand::Quit => AppCommand::Quit,
+            ShortcutCommand::ForceQuit => AppCommand::ForceQuit,
+            ShortcutCommand::Undo => AppCommand::Undo,
+            ShortcutCommand::Redo => AppCommand::Redo,
+            ShortcutCommand::Cut => AppCommand::Cut,
+            ShortcutCommand::Copy => AppCommand::Copy,
+            ShortcutCommand::Paste => AppCommand::Paste,
+            ShortcutCommand::SelectAll => AppCommand::SelectAll,
+            ShortcutCommand::AddNextOccurrence => AppCommand::AddNextOccurrence,
+            ShortcutCommand::SelectAllOccurrences => AppCommand::SelectAllOccurrences,
+            ShortcutCommand::Find => AppCommand::Find,
+            ShortcutCommand::FindNext => AppCommand::FindNext,
+            ShortcutCommand::FindPrevious => AppCommand::FindPrevious,
+            ShortcutCommand::FindInTabs => AppCommand::FindInTabs,
+            ShortcutCommand::FindInFiles => AppCommand::FindInFiles,
+            ShortcutCommand::Replace => AppCommand::Replace,
+            ShortcutCommand::GoTo => AppCommand::GoTo,
+            ShortcutCommand::ConvertLineEndings(line_ending) => {
+                AppCommand::ConvertLineEndings(line_ending)
+            }
+            ShortcutCommand::ToggleReadOnly => AppCommand::ToggleReadOnly(
+                !self.editor.current().is_some_and(Document::is_read_only),
+            ),
+            ShortcutCommand::ToggleKeepUndoHistory => {
+                AppCommand::ToggleKeepUndoHistory(!self.settings.files.keep_undo_history)
+            }
+            ShortcutCommand::Preferences => AppCommand::Preferences,
+            ShortcutCommand::KeyboardShortcuts => AppCommand::KeyboardShortcuts,
+            ShortcutCommand::CommandPalette => AppCommand::CommandPalette,
+            ShortcutCommand::ToggleStatusBar => {
+                AppCommand::ToggleStatusBar(!self.settings.view.status_bar)
+            }
+            ShortcutCommand::ToggleLineNumbers => {
+                AppCommand::ToggleLineNumbers(!self.settings.view.line_numbers)
+            }
+            ShortcutCommand::SetWordWrap(wrap) => AppCommand::SetWordWrap(wrap),
+            ShortcutCommand::NextTab => AppCommand::NextTab,
+            ShortcutCommand::PreviousTab => AppCommand::PreviousTab,
+            ShortcutCommand::SelectTab(index) => AppCommand::SelectTab(index),
+            ShortcutCommand::SelectLastTab => AppCommand::SelectLastTab,
+            ShortcutCommand::CheckForUpdates => AppCommand::CheckForUpdates,
+        }
+    }
+
+    /// Everything the command palette offers: each command with its keys,
+    /// saving or reopening with each encoding, the open tabs and the recent
+    /// files.
+    fn palette_items(&self) -> Vec<ui::command_palette::PaletteItem<AppCommand>> {
+        use ui::command_palette::PaletteItem;
+
+        let mut items = ShortcutCommand::ALL
+            .into_iter()
+            .map(|command| PaletteItem {
+                label: format!("{}: {}", command.category(), command.label()),
+                detail: self.keymap.accelerator(command).unwrap_or_default(),
+                command: self.shortcut_action(command),
+            })
+            .collect::<Vec<_>>();
+        let can_reopen = self
+            .editor
+            .current()
+            .is_some_and(|doc| doc.path().is_some());
+        for choice in encoding::choices() {
+            items.push(PaletteItem {
+                label: format!("File: Save with Encoding: {}", choice.label()),
+                detail: String::new(),
+                command: AppCommand::SaveWithEncoding(choice),
+            });
+            if can_reopen {
+                items.push(PaletteItem {
+                    label: format!("File: Reopen with Encoding: {}", choice.label()),
+                    detail: String::new(),
+                    command: AppCommand::ReopenWithEncoding(choice),
+                });
+            }
+        }
+        for (index, doc) in self.editor.documents().iter().enumerate() {
+            items.push(PaletteItem {
+                label: format!("Tab: {}", doc.title()),
+                detail: doc
+                    .path()
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default(),
+                command: AppCommand::SelectTab(index),
+            });
+        }
+        for (index, path) in self.recent_files.iter().enumerate() {
+            let name = path.file_name().unwrap_or(path.as_os_str());
+            items.push(PaletteItem {
+                label: format!("Open Recent: {}", name.to_string_lossy()),
+                detail: path
+                    .parent()
+                    .map(|dir| dir.display().to_string())
+                    .unwrap_or_default(),
+                command: AppCommand::OpenRecent(index),
+            });
+        }
+        items
     }
 
     fn handle_command(&mut self, ctx: &egui::Context, command: AppCommand) {
@@ -143,6 +725,24 @@ impl PlainpadApp {
             AppCommand::Save => self.save_current(),
             AppCommand::SaveAs => self.save_as_current(),
             AppCommand::SaveAll => self.save_all_non_empty(),
+            AppCommand::SaveWithEncoding(encoding) => self.save_with_encoding(encoding),
+            AppCommand::SetBackup(choice) => self.set_backup(choice),
+            AppCommand::SetAutosave(secs) => {
+                self.change_settings(|settings| {
+                    settings.files.autosave_seconds = secs.unwrap_or(0)
+                });
+            }
+            AppCommand::ToggleRestoreSession(enabled) => {
+                self.change_settings(|settings| settings.files.reopen_tabs = enabled);
+            }
+            AppCommand::ToggleKeepUndoHistory(enabled) => {
+                self.change_settings(|settings| settings.files.keep_undo_history = enabled);
+            }
+            AppCommand::Preferences => self.preferences.open = true,
+            AppCommand::KeyboardShortcuts => self.keymap_editor.open = true,
+            AppCommand::CommandPalette => self.command_palette.show(),
+            AppCommand::ReopenWithEncoding(encoding) => self.reopen_with_encoding(encoding),
+            AppCommand::OpenRecent(index) => self.open_recent(index),
             AppCommand::CloseAll => self.close_all_tabs(),
             AppCommand::CloseTab => {
                 let index = self.editor.active_index();
@@ -154,16 +754,66 @@ impl PlainpadApp {
             AppCommand::PreviousTab => self.editor.previous_tab(),
             AppCommand::SelectTab(index) => self.editor.set_active(index),
             AppCommand::SelectLastTab => self.select_last_tab(),
-            AppCommand::Undo => self.send_edit_key(ctx, egui::Key::Z, false),
-            AppCommand::Redo => self.send_edit_key(ctx, egui::Key::Y, false),
+            AppCommand::Undo => {
+                if let Some(doc) = self.editor.current_mut() {
+                    doc.undo();
+                }
+                self.focus_editor(ctx);
+            }
+            AppCommand::Redo => {
+                if let Some(doc) = self.editor.current_mut() {
+                    doc.redo();
+                }
+                self.focus_editor(ctx);
+            }
             AppCommand::Find => self.open_find_panel(),
+            AppCommand::FindNext => self.find_next(ctx),
+            AppCommand::FindPrevious => self.find_previous(ctx),
+            AppCommand::FindInTabs => self.find_in_tabs(ctx),
+            AppCommand::FindInFiles => {
+                let folder = self
+                    .editor
+                    .current()
+                    .and_then(Document::path)
+                    .and_then(|path| path.parent());
+                self.find_in_files.show(folder);
+            }
             AppCommand::Replace => self.open_replace_panel(),
-            AppCommand::Cut => self.send_edit_event(ctx, egui::Event::Cut),
-            AppCommand::Copy => self.send_edit_event(ctx, egui::Event::Copy),
+            AppCommand::GoTo => self.go_to.show(),
+            AppCommand::Cut => self.copy_selection(ctx, true),
+            AppCommand::Copy => self.copy_selection(ctx, false),
             AppCommand::Paste => self.paste_from_clipboard(ctx),
-            AppCommand::SelectAll => self.send_edit_key(ctx, egui::Key::A, false),
-            AppCommand::ToggleStatusBar(enabled) => self.show_status_bar = enabled,
-            AppCommand::ToggleLineNumbers(enabled) => self.show_line_numbers = enabled,
+            AppCommand::SelectAll => {
+                if let Some(doc) = self.editor.current_mut() {
+                    let len = doc.rope().len_chars();
+                    doc.set_selection(Selection {
+                        anchor: 0,
+                        head: len,
+                    });
+                }
+                self.focus_editor(ctx);
+            }
+            AppCommand::AddNextOccurrence => self.add_next_occurrence(ctx),
+            AppCommand::SelectAllOccurrences => self.select_all_occurrences(ctx),
+            AppCommand::ConvertLineEndings(line_ending) => {
+                if let Some(doc) = self.editor.current_mut() {
+                    doc.set_line_ending(line_ending);
+                }
+            }
+            AppCommand::ToggleReadOnly(enabled) => {
+                if let Some(doc) = self.editor.current_mut() {
+                    doc.set_read_only(enabled);
+                }
+            }
+            AppCommand::ToggleStatusBar(enabled) => {
+                self.change_settings(|settings| settings.view.status_bar = enabled);
+            }
+            AppCommand::ToggleLineNumbers(enabled) => {
+                self.change_settings(|settings| settings.view.line_numbers = enabled);
+            }
+            AppCommand::SetWordWrap(wrap) => {
+                self.change_settings(|settings| settings.set_word_wrap(wrap));
+            }
             AppCommand::Quit => self.request_quit(ctx),
             AppCommand::ForceQuit => self.force_quit(ctx),
             AppCommand::CheckForUpdates => self.check_for_updates(),
@@ -186,63 +836,188 @@ impl PlainpadApp {
         }
     }
 
-    fn send_edit_key(&self, ctx: &egui::Context, key: egui::Key, shift: bool) {
-        let Some(editor_id) = self.editor_id else {
+    fn focus_editor(&self, ctx: &egui::Context) {
+        if let Some(editor_id) = self.editor_id {
+            ctx.memory_mut(|memory| memory.request_focus(editor_id));
+        }
+    }
+
+    fn copy_selection(&mut self, ctx: &egui::Context, cut: bool) {
+        self.focus_editor(ctx);
+        let Some(doc) = self.editor.current_mut() else {
             return;
         };
+        let text = doc.selected_text();
+        if text.is_empty() {
+            return;
+        }
+        ui::editor_view::remember_copy(ctx, doc, &text);
+        if let Err(err) = Clipboard::new().and_then(|mut clipboard| clipboard.set_text(text)) {
+            self.error_message = Some(format!("Failed to copy to the clipboard: {err}"));
+            return;
+        }
+        if cut {
+            doc.insert("", EditKind::Other);
+        }
+    }
 
-        ctx.memory_mut(|memory| memory.request_focus(editor_id));
+    fn paste_from_clipboard(&mut self, ctx: &egui::Context) {
+        self.focus_editor(ctx);
+        let clipboard_text = Clipboard::new()
+            .and_then(|mut clipboard| clipboard.get_text())
+            .ok();
 
-        let modifiers = egui::Modifiers {
-            command: true,
-            shift,
-            ..Default::default()
+        let Some(text) = clipboard_text else {
+            self.error_message = Some("Failed to read clipboard contents.".to_string());
+            return;
         };
+        if let Some(doc) = self.editor.current_mut() {
+            ui::editor_view::paste(ctx, doc, &text);
+        }
+    }
 
-        ctx.input_mut(|input| {
-            input.events.push(egui::Event::Key {
-                key,
-                physical_key: None,
-                pressed: true,
-                modifiers,
-                repeat: false,
-            });
-            input.events.push(egui::Event::Key {
-                key,
-                physical_key: None,
-                pressed: false,
-                modifiers,
-                repeat: false,
+    /// With nothing selected, selects the word at the cursor. Otherwise adds
+    /// a cursor at the next place the selected text occurs, wrapping around
+    /// at the end and skipping places that are already selected.
+    fn add_next_occurrence(&mut self, ctx: &egui::Context) {
+        self.focus_editor(ctx);
+        let Some(doc) = self.editor.current_mut() else {
+            return;
+        };
+        let had_selection = !doc.selection().is_empty();
+        let Some(query) = occurrence_query(doc) else {
+            return;
+        };
+        if !had_selection {
+            return;
+        }
+        let Ok(search) = Search::new(&query, EXACT) else {
+            return;
+        };
+        let found = {
+            let rope = doc.rope();
+            let selected = doc
+                .selections()
+                .iter()
+                .map(Selection::range)
+                .collect::<Vec<_>>();
+            let after = doc.selection().range().end;
+            let mut start = after;
+            let mut wrapped = false;
+            loop {
+                match search.find_in_rope(rope, start, false) {
+                    Some(range) => {
+                        if !selected.contains(&range) {
+                            break Some(range);
+                        }
+                        start = range.end;
+                    }
+                    _ if !wrapped => {
+                        wrapped = true;
+                        start = 0;
+                    }
+                    _ => break None,
+                }
+                if wrapped && start >= after {
+                    break None;
+                }
+            }
+        };
+        if let Some(range) = found {
+            doc.add_selection(Selection {
+                anchor: range.start,
+                head: range.end,
             });
-        });
+            doc.view_mut().reveal_cursor = true;
+        }
     }
 
-    fn send_edit_event(&self, ctx: &egui::Context, event: egui::Event) {
-        let Some(editor_id) = self.editor_id else {
+    /// Puts a cursor on every place the selected text, or the word at the
+    /// cursor, occurs.
+    fn select_all_occurrences(&mut self, ctx: &egui::Context) {
+        let Some(query) = self.editor.current_mut().and_then(occurrence_query) else {
             return;
         };
-
-        ctx.memory_mut(|memory| memory.request_focus(editor_id));
-
-        ctx.input_mut(|input| input.events.push(event));
+        match Search::new(&query, EXACT) {
+            Ok(search) => {
+                self.select_matches(ctx, &search, false, false);
+            }
+            Err(error) => self.error_message = Some(error),
+        }
     }
 
-    fn paste_from_clipboard(&mut self, ctx: &egui::Context) {
-        let Some(editor_id) = self.editor_id else {
+    /// Puts a cursor on every match of the find panel's search.
+    fn select_all_matches(&mut self, ctx: &egui::Context) {
+        let Some(search) = self.find_panel.search("Enter search text to find matches.") else {
             return;
         };
+        self.find_panel.notice = None;
+        let in_selection = self.find_panel.in_selection;
+        if in_selection && !self.has_selection() {
+            self.find_panel.error = Some("Select the text to search in first.".to_string());
+            return;
+        }
+        self.find_panel.error = match self.select_matches(ctx, &search, in_selection, true) {
+            0 => Some("No matches found.".to_string()),
+            _ => None,
+        };
+    }
 
-        ctx.memory_mut(|memory| memory.request_focus(editor_id));
-
-        let clipboard_text = Clipboard::new()
-            .and_then(|mut clipboard| clipboard.get_text())
-            .ok();
+    /// Selects every match of `search`, or with `in_selection` those inside
+    /// the current selections, keeping the one at or after the primary cursor
+    /// as the primary selection. `search` is the find panel's when
+    /// `from_panel`, so its finished match index can answer. Returns how many
+    /// there were.
+    fn select_matches(
+        &mut self,
+        ctx: &egui::Context,
+        search: &Search,
+        in_selection: bool,
+        from_panel: bool,
+    ) -> usize {
+        self.focus_editor(ctx);
+        let Some(doc) = self.editor.current() else {
+            return 0;
+        };
+        let indexed = from_panel
+            .then(|| self.search_service.indexed(&self.find_panel.key(doc)))
+            .flatten();
+        let scope = in_selection.then(|| selection_ranges(doc));
+        let ranges = all_matches(indexed, search, doc.rope())
+            .into_iter()
+            .filter(|range| within(scope.as_deref(), range))
+            .map(|range| Selection {
+                anchor: range.start,
+                head: range.end,
+            })
+            .collect::<Vec<_>>();
+        let Some(doc) = self.editor.current_mut() else {
+            return 0;
+        };
+        let count = ranges.len();
+        if count > 0 {
+            let cursor = doc.selection().range().start;
+            let primary = ranges
+                .iter()
+                .position(|selection| selection.range().end > cursor)
+                .unwrap_or(0);
+            doc.set_selections(ranges, primary);
+            doc.view_mut().reveal_cursor = true;
+        }
+        count
+    }
 
-        if let Some(text) = clipboard_text {
-            ctx.input_mut(|input| input.events.push(egui::Event::Paste(text)));
-        } else {
-            self.error_message = Some("Failed to read clipboard contents.".to_string());
+    /// Closes a tab without asking, keeping its undo history if that is on.
+    fn close_tab(&mut self, index: usize) {
+        if let (true, Some(doc)) = (
+            self.settings.files.keep_undo_history,
+            self.editor.documents().get(index),
+        ) {
+            if let Err(err) = history::store(doc) {
+                self.error_message = Some(format!("Failed to keep undo history: {err}"));
+            }
         }
+        self.editor.close_document(index);
     }
 
     fn request_close(&mut self, index: usize) {
@@ -252,7 +1027,7 @@ impl PlainpadApp {
                 return;
             }
         }
-        self.editor.close_document(index);
+        self.close_tab(index);
         self.confirm_close = None;
         self.close_next_pending();
     }
@@ -321,178 +1096,411 @@ impl PlainpadApp {
         self.find_panel.error = None;
     }
 
-    fn selection_char_range(&self, ctx: &egui::Context) -> Option<Range<usize>> {
-        let editor_id = self.editor_id?;
-        let state = egui::text_edit::TextEditState::load(ctx, editor_id)?;
-        let range = state.cursor.char_range()?;
-        let [start, end] = range.sorted();
-        Some(start.index..end.index)
+    fn selection_char_range(&self) -> Option<Range<usize>> {
+        self.editor.current().map(|doc| doc.selection().range())
     }
 
-    fn select_char_range(&self, ctx: &egui::Context, range: Range<usize>) {
-        let Some(editor_id) = self.editor_id else {
-            return;
-        };
-        if let Some(mut state) = egui::text_edit::TextEditState::load(ctx, editor_id) {
-            let selection = CCursorRange::two(CCursor::new(range.start), CCursor::new(range.end));
-            state.cursor.set_char_range(Some(selection));
-            state.store(ctx, editor_id);
-            ctx.memory_mut(|memory| memory.request_focus(editor_id));
+    fn select_char_range(&mut self, ctx: &egui::Context, range: Range<usize>) {
+        if let Some(doc) = self.editor.current_mut() {
+            doc.select(range);
         }
+        self.focus_editor(ctx);
+    }
+
+    fn has_selection(&self) -> bool {
+        self.editor.current().is_some_and(|doc| {
+            doc.selections()
+                .iter()
+                .any(|selection| !selection.is_empty())
+        })
     }
 
+    /// Finds in the panel's direction.
     fn find_next(&mut self, ctx: &egui::Context) {
-        let Some(doc) = self.editor.current() else {
+        self.find(ctx, self.find_panel.backwards);
+    }
+
+    /// Finds against the panel's direction.
+    fn find_previous(&mut self, ctx: &egui::Context) {
+        self.find(ctx, !self.find_panel.backwards);
+    }
+
+    /// Selects the next match after the selection, or with `backwards` the
+    /// one before it. With wrap-around a search that runs off one end goes
+    /// on from the other and says so.
+    fn find(&mut self, ctx: &egui::Context, backwards: bool) {
+        if self.editor.current().is_none() {
+            return;
+        }
+        let Some(search) = self.find_panel.search("Enter search text to find matches.") else {
+            self.find_panel.open = true;
             return;
         };
-        let query = self.find_panel.query.clone();
-        if query.is_empty() {
-            self.find_panel.error = Some("Enter search text to find matches.".to_string());
+        let Some(doc) = self.editor.current() else {
             return;
-        }
-        let text = doc.text();
-        let selection_end = self
-            .selection_char_range(ctx)
-            .map(|range| range.end)
-            .unwrap_or(0);
-        let start_byte = byte_index_from_char(text, selection_end);
-        let match_range = match self.find_match_range(text, &query, start_byte) {
-            Ok(Some(range)) => Some(range),
-            Ok(None) => self.find_match_range(text, &query, 0).ok().flatten(),
-            Err(error) => {
-                self.find_panel.error = Some(error);
-                return;
-            }
         };
-
-        if let Some(range) = match_range {
-            self.select_char_range(ctx, range);
-            self.find_panel.error = None;
+        let rope = doc.rope();
+        let selection = doc.selection().range();
+        let from = if backwards {
+            selection.start
         } else {
-            self.find_panel.error = Some("No matches found.".to_string());
+            selection.end
+        };
+        // The match index answers when it has got far enough; otherwise the
+        // rope is searched from `from` until a window has a match.
+        let indexed = self.search_service.indexed(&self.find_panel.key(doc));
+        let find_from = |from: usize| {
+            indexed
+                .and_then(|indexed| indexed.find(from, backwards))
+                .unwrap_or_else(|| search.find_in_rope(rope, from, backwards))
+        };
+        let mut wrapped = false;
+        let mut found = find_from(from.min(rope.len_chars()));
+        if found.is_none() && self.find_panel.wrap_around {
+            found = find_from(if backwards { rope.len_chars() } else { 0 });
+            wrapped = found.is_some();
+        }
+
+        self.find_panel.notice = wrapped.then(|| {
+            if backwards {
+                "Passed the start; continued from the end.".to_string()
+            } else {
+                "Passed the end; continued from the start.".to_string()
+            }
+        });
+        match found {
+            Some(range) => {
+                self.select_char_range(ctx, range);
+                self.find_panel.error = None;
+            }
+            None if self.find_panel.wrap_around => {
+                self.find_panel.error = Some("No matches found.".to_string());
+            }
+            None if backwards => {
+                self.find_panel.error = Some("No more matches above the cursor.".to_string());
+            }
+            None => {
+                self.find_panel.error = Some("No more matches below the cursor.".to_string());
+            }
+        }
+        // F3 works with the panel closed; open it to show what happened.
+        if self.find_panel.error.is_some() || self.find_panel.notice.is_some() {
+            self.find_panel.open = true;
         }
     }
 
     fn replace_current(&mut self, ctx: &egui::Context) {
-        let query = self.find_panel.query.clone();
-        if query.is_empty() {
-            self.find_panel.error = Some("Enter search text to replace.".to_string());
+        let Some(search) = self.find_panel.search("Enter search text to replace.") else {
             return;
-        }
-        if self.try_replace_selection(ctx, &query) {
-            if self.find_panel.error.is_none() {
-                self.find_next(ctx);
-            }
+        };
+        if self.try_replace_selection(ctx, &search) {
+            self.find_next(ctx);
             return;
         }
 
         self.find_next(ctx);
 
-        if self.try_replace_selection(ctx, &query) && self.find_panel.error.is_none() {
+        if self.find_panel.error.is_none() && self.try_replace_selection(ctx, &search) {
             self.find_next(ctx);
         }
     }
 
+    /// Replaces every match, or with "In selection" those inside the current
+    /// selections.
     fn replace_all(&mut self) {
-        let Some(doc) = self.editor.current_mut() else {
+        if self.editor.current().is_none() {
+            return;
+        }
+        let Some(search) = self.find_panel.search("Enter search text to replace.") else {
             return;
         };
-        let query = self.find_panel.query.clone();
-        if query.is_empty() {
-            self.find_panel.error = Some("Enter search text to replace.".to_string());
+        self.find_panel.notice = None;
+        let in_selection = self.find_panel.in_selection;
+        if in_selection && !self.has_selection() {
+            self.find_panel.error = Some("Select the text to replace in first.".to_string());
             return;
         }
-        let use_regex = self.find_panel.use_regex;
-        let replacement = self.find_panel.replace.clone();
-        let replaced = match replace_all_matches(use_regex, doc.text(), &query, &replacement) {
-            Ok(text) => text,
-            Err(error) => {
-                self.find_panel.error = Some(error);
-                return;
-            }
+        let Some(doc) = self.editor.current() else {
+            return;
         };
-
-        if replaced != doc.text() {
-            *doc.text_mut() = replaced;
-            doc.sync_rope();
+        let indexed = self.search_service.indexed(&self.find_panel.key(doc));
+        let scope = in_selection.then(|| selection_ranges(doc));
+        let ranges = all_matches(indexed, &search, doc.rope())
+            .into_iter()
+            .filter(|range| within(scope.as_deref(), range))
+            .collect();
+        let edits = replacement_edits(&search, doc.rope(), ranges, &self.find_panel.replace);
+        if let Some(doc) = self.editor.current_mut() {
+            doc.replace_ranges(edits);
         }
         self.find_panel.error = None;
     }
 
-    fn try_replace_selection(&mut self, ctx: &egui::Context, query: &str) -> bool {
-        let selection = match self.selection_char_range(ctx) {
-            Some(range) if range.start != range.end => range,
-            _ => return false,
+    /// Lists every match of the find panel's search in every tab in a
+    /// results panel.
+    fn find_in_tabs(&mut self, ctx: &egui::Context) {
+        let Some(search) = self.find_panel.search("Enter search text to find matches.") else {
+            self.find_panel.open = true;
+            return;
         };
-        let Some(doc_text) = self.editor.current().map(Document::text) else {
-            return false;
+        let tabs = self
+            .editor
+            .documents()
+            .iter()
+            .map(|doc| TabSnapshot {
+                id: doc.id(),
+                title: doc.title(),
+                rope: doc.rope().clone(),
+            })
+            .collect();
+        let title = format!("Find in Open Tabs: \"{}\"", self.find_panel.query);
+        let run = multi_search::search_tabs(ctx, tabs, search);
+        self.results.start(title, run);
+    }
+
+    /// Replaces every match in every tab. Each tab changes in one undo step
+    /// of its own; read-only tabs are left alone.
+    fn replace_all_in_tabs(&mut self, ctx: &egui::Context) {
+        let Some(search) = self.find_panel.search("Enter search text to replace.") else {
+            return;
         };
-        let selection_text = slice_char_range(doc_text, &selection);
-        let replacement = match self.replace_match(query, selection_text) {
-            Ok(Some(replacement)) => replacement,
-            Ok(None) => return false,
-            Err(error) => {
-                self.find_panel.error = Some(error);
-                return true;
+        let replacement = self.find_panel.replace.clone();
+        let mut replaced = 0;
+        let mut tabs = 0;
+        let mut read_only = 0;
+        for index in 0..self.editor.documents().len() {
+            let doc = &self.editor.documents()[index];
+            let indexed = self.search_service.indexed(&self.find_panel.key(doc));
+            let ranges = all_matches(indexed, &search, doc.rope());
+            let edits = replacement_edits(&search, doc.rope(), ranges, &replacement);
+            if edits.is_empty() {
+                continue;
             }
-        };
+            if doc.is_read_only() {
+                read_only += 1;
+                continue;
+            }
+            replaced += edits.len();
+            tabs += 1;
+            if let Some(doc) = self.editor.document_mut(index) {
+                doc.replace_ranges(edits);
+            }
+        }
+
+        let match_noun = if replaced == 1 { "match" } else { "matches" };
+        let tab_noun = if tabs == 1 { "tab" } else { "tabs" };
+        let mut notice = format!("Replaced {replaced} {match_noun} in {tabs} {tab_noun}.");
+        if read_only > 0 {
+            notice.push_str(&format!(" Skipped {read_only} read-only."));
+        }
+        self.find_panel.error = None;
+        self.find_panel.notice = Some(notice);
+        if self.results.open {
+            self.find_in_tabs(ctx);
+        }
+    }
 
+    /// Goes to a result clicked in the results panel and selects it,
+    /// opening its file first when it is not open yet.
+    fn open_result(
+        &mut self,
+        ctx: &egui::Context,
+        source: Source,
+        line: usize,
+        column: usize,
+        len: usize,
+    ) {
+        match source {
+            Source::Tab(id) => {
+                let Some(index) = self
+                    .editor
+                    .documents()
+                    .iter()
+                    .position(|doc| doc.id() == id)
+                else {
+                    self.error_message = Some("That tab has been closed.".to_string());
+                    return;
+                };
+                self.editor.set_active(index);
+            }
+            Source::File(path) => {
+                let target = Target::File {
+                    path,
+                    position: None,
+                };
+                if self.open_targets(vec![target], None, false).is_empty() {
+                    return;
+                }
+            }
+        }
         if let Some(doc) = self.editor.current_mut() {
-            let start_byte = byte_index_from_char(doc.text(), selection.start);
-            let end_byte = byte_index_from_char(doc.text(), selection.end);
-            doc.text_mut()
-                .replace_range(start_byte..end_byte, &replacement);
-            doc.sync_rope();
+            let rope = doc.rope();
+            let line = line.min(rope.len_lines() - 1);
+            let line_start = rope.line_to_char(line);
+            let line_len = line_content_len(rope.line(line));
+            let start = line_start + column.min(line_len);
+            let end = (start + len).min(line_start + line_len);
+            doc.select(start..end);
         }
+        self.focus_editor(ctx);
+    }
 
-        let end_char = selection.start + replacement.chars().count();
-        self.select_char_range(ctx, selection.start..end_char);
-        self.find_panel.error = None;
-        true
+    /// Searches the folder picked in the Find in Files dialog, or with
+    /// `replace` previews replacing the matches there.
+    fn find_in_files(&mut self, ctx: &egui::Context, replace: bool) {
+        let dialog = &mut self.find_in_files;
+        let root = PathBuf::from(dialog.root.trim());
+        if dialog.root.trim().is_empty() {
+            dialog.error = Some("Choose a folder to search.".to_string());
+            return;
+        }
+        if !root.is_dir() {
+            dialog.error = Some(format!("{} is not a folder.", root.display()));
+            return;
+        }
+        let globs = multi_search::glob_set(&dialog.include)
+            .and_then(|include| Ok((include, multi_search::glob_set(&dialog.exclude)?)));
+        let (include, exclude) = match globs {
+            Ok(globs) => globs,
+            Err(err) => {
+                dialog.error = Some(err);
+                return;
+            }
+        };
+        let files = FileSearch {
+            root,
+            include,
+            exclude,
+            max_size: dialog.max_size_mb * 1024 * 1024,
+            skip_binary: dialog.skip_binary,
+            use_gitignore: dialog.use_gitignore,
+        };
+        let Some(search) = self.find_panel.search("Enter search text to find matches.") else {
+            self.find_in_files.error = self.find_panel.error.take();
+            return;
+        };
+        self.find_in_files.error = None;
+        self.find_in_files.open = false;
+
+        let query = &self.find_panel.query;
+        let replacement = self.find_panel.replace.clone();
+        let folder = files.root.display().to_string();
+        if replace {
+            let title = format!("Replace in Files: \"{query}\" with \"{replacement}\" in {folder}");
+            self.file_replace = Some((search.clone(), replacement.clone()));
+            let run = multi_search::search_files(ctx, files, search, Some(replacement));
+            self.results.start_preview(title, run);
+        } else {
+            let title = format!("Find in Files: \"{query}\" in {folder}");
+            self.file_replace = None;
+            let run = multi_search::search_files(ctx, files, search, None);
+            self.results.start(title, run);
+        }
     }
 
-    fn find_match_range(
-        &self,
-        text: &str,
-        query: &str,
-        start_byte: usize,
-    ) -> Result<Option<Range<usize>>, String> {
-        if self.find_panel.use_regex {
-            let regex = Regex::new(query).map_err(|err| err.to_string())?;
-            if let Some(found) = regex.find_at(text, start_byte) {
-                return Ok(Some(byte_range_to_char_range(text, found.range())));
-            }
-            return Ok(None);
-        }
-
-        let found = text[start_byte..].find(query);
-        Ok(found.map(|offset| {
-            let start = start_byte + offset;
-            let end = start + query.len();
-            byte_range_to_char_range(text, start..end)
-        }))
-    }
-
-    fn replace_match(&self, query: &str, selection: &str) -> Result<Option<String>, String> {
-        if self.find_panel.use_regex {
-            let regex = Regex::new(query).map_err(|err| err.to_string())?;
-            if let Some(found) = regex.find(selection) {
-                if found.start() == 0 && found.end() == selection.len() {
-                    return Ok(Some(
-                        regex
-                            .replace(selection, self.find_panel.replace.as_str())
-                            .into(),
+    /// Writes the replacements previewed in the results to the files left
+    /// checked. Files open in a tab are changed in the tab, as one undo step,
+    /// and left for the user to save.
+    fn replace_in_files(&mut self, groups: Vec<(Source, Vec<Range<usize>>)>) {
+        let Some((search, replacement)) = self.file_replace.take() else {
+            return;
+        };
+        let save_options = self.settings.save_options();
+        let mut replaced = 0;
+        let mut files = 0;
+        let mut in_tabs = 0;
+        let mut failed = Vec::new();
+        for (source, ranges) in groups {
+            let Source::File(path) = source else {
+                continue;
+            };
+            if let Some(index) = self.document_index(&path) {
+                let Some(doc) = self.editor.document_mut(index) else {
+                    continue;
+                };
+                if doc.is_read_only() {
+                    failed.push(format!("{}: the tab is read-only", path.display()));
+                    continue;
+                }
+                let Some(edits) =
+                    multi_search::previewed_edits(&search, doc.rope(), &ranges, &replacement)
+                else {
+                    failed.push(format!(
+                        "{}: the tab changed since the preview",
+                        path.display()
                     ));
+                    continue;
+                };
+                if !edits.is_empty() {
+                    replaced += edits.len();
+                    in_tabs += 1;
+                    doc.replace_ranges(edits);
+                }
+                continue;
+            }
+            match multi_search::replace_in_file(
+                &path,
+                &search,
+                &ranges,
+                &replacement,
+                &save_options,
+            ) {
+                Ok(0) => {}
+                Ok(count) => {
+                    replaced += count;
+                    files += 1;
                 }
+                Err(err) => failed.push(format!("{}: {err}", path.display())),
             }
-            return Ok(None);
         }
 
-        if selection == query {
-            return Ok(Some(self.find_panel.replace.clone()));
+        let match_noun = if replaced == 1 { "match" } else { "matches" };
+        let file_noun = if files + in_tabs == 1 {
+            "file"
+        } else {
+            "files"
+        };
+        let mut notice = format!(
+            "Replaced {replaced} {match_noun} in {} {file_noun}.",
+            files + in_tabs
+        );
+        if in_tabs > 0 {
+            notice.push_str(&format!(
+                " {in_tabs} open in tabs were changed there and still need saving."
+            ));
         }
+        self.results.show_notice(notice);
+        if !failed.is_empty() {
+            self.error_message = Some(format!(
+                "Failed to replace in files:\n{}",
+                failed.join("\n")
+            ));
+        }
+    }
 
-        Ok(None)
+    /// Replaces the selection when it is exactly a match of `search`.
+    fn try_replace_selection(&mut self, ctx: &egui::Context, search: &Search) -> bool {
+        let selection = match self.selection_char_range() {
+            Some(range) if range.start != range.end => range,
+            _ => return false,
+        };
+        let Some(doc) = self.editor.current() else {
+            return false;
+        };
+        let Some(replacement) =
+            search.replace_match(doc.rope(), selection.clone(), &self.find_panel.replace)
+        else {
+            return false;
+        };
+
+        if let Some(doc) = self.editor.current_mut() {
+            doc.replace_range(selection.clone(), &replacement);
+        }
+
+        let end_char = selection.start + replacement.chars().count();
+        self.select_char_range(ctx, selection.start..end_char);
+        self.find_panel.error = None;
+        true
     }
 
     fn request_quit(&mut self, ctx: &egui::Context) {
@@ -551,8 +1559,7 @@ impl PlainpadApp {
                 return false;
             };
 
-            if let Err(err) = self.editor.save_document(index, path) {
-                self.error_message = Some(format!("Failed to save file: {err}"));
+            if !self.save_document_at(index, path) {
                 return false;
             }
         }
@@ -562,12 +1569,238 @@ impl PlainpadApp {
 
     fn open_file_dialog(&mut self) {
         if let Some(path) = FileDialog::new().pick_file() {
-            if let Err(err) = self.editor.open_document(path) {
+            if let Err(err) = self.editor.open_document(path.clone()) {
                 self.error_message = Some(format!("Failed to open file: {err}"));
+                return;
+            }
+            self.remember_recent(path);
+            if let (true, Some(doc)) = (
+                self.settings.files.keep_undo_history,
+                self.editor.current_mut(),
+            ) {
+                history::load(doc);
             }
+            self.warn_on_decode_errors();
         }
     }
 
+    /// Moves `path` to the top of the recent files.
+    fn remember_recent(&mut self, path: PathBuf) {
+        self.recent_files.retain(|recent| *recent != path);
+        self.recent_files.insert(0, path);
+        self.recent_files.truncate(RECENT_FILES);
+    }
+
+    /// Opens a recent file, or drops it from the list if it is gone.
+    fn open_recent(&mut self, index: usize) {
+        let Some(path) = self.recent_files.get(index).cloned() else {
+            return;
+        };
+        if !path.exists() {
+            self.recent_files.remove(index);
+            self.error_message = Some(format!(
+                "Failed to open file: {} no longer exists",
+                path.display()
+            ));
+            return;
+        }
+        self.open_targets(
+            vec![Target::File {
+                path,
+                position: None,
+            }],
+            None,
+            false,
+        );
+    }
+
+    fn warn_on_decode_errors(&mut self) {
+        if let Some(doc) = self.editor.current() {
+            if doc.had_decode_errors() {
+                self.error_message = Some(format!(
+                    "Some bytes in {} are not valid {} and were replaced. \
+                     Try File > Reopen with Encoding, or saving will keep the replacements.",
+                    doc.title(),
+                    doc.encoding().label()
+                ));
+            }
+        }
+    }
+
+    fn reopen_with_encoding(&mut self, encoding: TextEncoding) {
+        let index = self.editor.active_index();
+        if self.editor.current().is_some_and(Document::is_dirty) {
+            self.error_message =
+                Some("Save or undo your changes before reopening with another encoding.".into());
+            return;
+        }
+        if let Err(err) = self.editor.reopen_with_encoding(index, encoding) {
+            self.error_message = Some(format!("Failed to reopen file: {err}"));
+            return;
+        }
+        self.warn_on_decode_errors();
+    }
+
+    fn set_backup(&mut self, choice: Backup) {
+        let folder = if choice == Backup::Timestamped {
+            let Some(dir) = FileDialog::new()
+                .set_title("Choose a folder for backup copies")
+                .pick_folder()
+            else {
+                return;
+            };
+            Some(dir)
+        } else {
+            None
+        };
+        self.change_settings(|settings| {
+            settings.files.backup = choice;
+            if folder.is_some() {
+                settings.files.backup_folder = folder;
+            }
+        });
+    }
+
+    /// Applies a change to the settings and writes them to the settings file.
+    fn change_settings(&mut self, change: impl FnOnce(&mut Settings)) {
+        let mut settings = self.settings.clone();
+        change(&mut settings);
+        self.apply_settings(settings);
+        self.store_settings();
+    }
+
+    fn store_settings(&mut self) {
+        self.settings_unsaved = false;
+        if let Err(err) = self.settings.save() {
+            self.error_message = Some(format!("Failed to save settings: {err}"));
+        }
+    }
+
+    fn apply_settings(&mut self, settings: Settings) {
+        if self.settings.files.keep_undo_history && !settings.files.keep_undo_history {
+            history::clear();
+        }
+        self.settings = settings;
+    }
+
+    /// Shows what was wrong in the settings or keymap file, after any message
+    /// already showing.
+    fn report_file_problems(&mut self, file: &str, problems: Vec<String>) {
+        if problems.is_empty() {
+            return;
+        }
+        let mut message = format!("Problems in the {file} file:\n{}", problems.join("\n"));
+        if let Some(earlier) = self.error_message.take() {
+            message = format!("{earlier}\n\n{message}");
+        }
+        self.error_message = Some(message);
+    }
+
+    /// Picks up edits to the settings and keymap files, made by hand or by
+    /// another plainpad window.
+    fn check_settings_file(&mut self, ctx: &egui::Context) {
+        let paths = [Settings::path(), Keymap::path()]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+        if paths.is_empty() {
+            return;
+        }
+        if self.settings_watcher.is_none() {
+            self.settings_watcher = FileWatcher::new(ctx).ok();
+        }
+        let Some(watcher) = &mut self.settings_watcher else {
+            return;
+        };
+        watcher.sync(paths.iter());
+        let changed = watcher.changed();
+        if Settings::path().is_some_and(|path| changed.contains(&path)) {
+            let (settings, problems) = Settings::load();
+            self.report_file_problems("settings", problems);
+            if settings != self.settings {
+                self.apply_settings(settings);
+            }
+        }
+        if Keymap::path().is_some_and(|path| changed.contains(&path)) {
+            let (keymap, problems) = Keymap::load();
+            self.report_file_problems("keymap", problems);
+            self.keymap = keymap;
+        }
+    }
+
+    fn store_keymap(&mut self) {
+        if let Err(err) = self.keymap.save() {
+            self.error_message = Some(format!("Failed to save keyboard shortcuts: {err}"));
+        }
+    }
+
+    /// Opens `keymap.toml` in a tab, writing the current keymap first if
+    /// there is no file yet.
+    fn open_keymap_file(&mut self) {
+        let Some(path) = Keymap::path() else {
+            self.error_message = Some("Failed to find the config folder.".to_string());
+            return;
+        };
+        if !path.exists() {
+            self.store_keymap();
+        }
+        self.open_targets(
+            vec![Target::File {
+                path,
+                position: None,
+            }],
+            None,
+            false,
+        );
+    }
+
+    /// Opens `settings.toml` in a tab, writing the current settings first if
+    /// there is no file yet.
+    fn open_settings_file(&mut self) {
+        let Some(path) = Settings::path() else {
+            self.error_message = Some("Failed to find the config folder.".to_string());
+            return;
+        };
+        if !path.exists() {
+            self.store_settings();
+        }
+        self.open_targets(
+            vec![Target::File {
+                path,
+                position: None,
+            }],
+            None,
+            false,
+        );
+    }
+
+    /// Sets light or dark visuals for the theme setting. eframe only switches
+    /// them itself when following the system.
+    fn apply_theme(&self, ctx: &egui::Context, frame: &eframe::Frame) {
+        let dark = match self.settings.view.theme {
+            Theme::Light => false,
+            Theme::Dark => true,
+            Theme::System => match frame.info().system_theme {
+                Some(theme) => theme == eframe::Theme::Dark,
+                None => return,
+            },
+        };
+        if ctx.style().visuals.dark_mode != dark {
+            ctx.set_visuals(if dark {
+                egui::Visuals::dark()
+            } else {
+                egui::Visuals::light()
+            });
+        }
+    }
+
+    fn save_with_encoding(&mut self, encoding: TextEncoding) {
+        if let Some(doc) = self.editor.current_mut() {
+            doc.set_encoding(encoding);
+        }
+        self.save_current();
+    }
+
     fn show_print_notice(&mut self) {
         self.error_message = Some("Printing is not available yet.".to_string());
     }
@@ -577,9 +1810,7 @@ impl PlainpadApp {
         let path = self.editor.current().and_then(|doc| doc.path().cloned());
 
         if let Some(path) = path {
-            if let Err(err) = self.editor.save_document(index, path) {
-                self.error_message = Some(format!("Failed to save file: {err}"));
-            }
+            self.save_document_at(index, path);
         } else {
             self.save_as_current();
         }
@@ -589,9 +1820,7 @@ impl PlainpadApp {
         let index = self.editor.active_index();
         let dialog = self.save_dialog_for(index);
         if let Some(path) = dialog.save_file() {
-            if let Err(err) = self.editor.save_document(index, path) {
-                self.error_message = Some(format!("Failed to save file: {err}"));
-            }
+            self.save_document_at(index, path);
         }
     }
 
@@ -613,14 +1842,214 @@ impl PlainpadApp {
             };
 
             if let Some(path) = path {
-                if let Err(err) = self.editor.save_document(index, path) {
-                    self.error_message = Some(format!("Failed to save file: {err}"));
+                if !self.save_document_at(index, path) {
                     return;
                 }
             }
         }
     }
 
+    /// Saves tab `index` to `path`. Asks first instead of saving when the file
+    /// was changed by another program since the tab last read or wrote it.
+    fn save_document_at(&mut self, index: usize, path: PathBuf) -> bool {
+        if let Some(doc) = self.editor.document_mut(index) {
+            if doc.path() == Some(&path) && doc.check_disk() == DiskChange::Modified {
+                self.confirm_overwrite = Some((index, path));
+                return false;
+            }
+        }
+        match self
+            .editor
+            .save_document(index, path.clone(), &self.settings.save_options())
+        {
+            Ok(()) => {
+                self.remember_recent(path);
+                if let Some(id) = self.editor.documents().get(index).map(Document::id) {
+                    for waiter in &mut self.waiters {
+                        if waiter.ids.contains(&id) {
+                            waiter.saved.insert(id);
+                        }
+                    }
+                }
+                true
+            }
+            Err(err) => {
+                self.error_message = Some(format!("Failed to save file: {err}"));
+                false
+            }
+        }
+    }
+
+    /// Looks for open files that another program changed or deleted, whenever
+    /// the watcher reports their folder and for every file when the window
+    /// gets focus back, in case an event was missed. Clean tabs are reloaded
+    /// silently; tabs with unsaved changes are queued for the user to decide.
+    fn check_external_changes(&mut self, ctx: &egui::Context) {
+        if self.watcher.is_none() && !self.watcher_failed {
+            match FileWatcher::new(ctx) {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(_) => self.watcher_failed = true,
+            }
+        }
+
+        let focused = ctx.input(|input| input.focused);
+        let regained_focus = focused && !self.window_focused;
+        self.window_focused = focused;
+
+        let changed = match &mut self.watcher {
+            Some(watcher) => {
+                watcher.sync(self.editor.documents().iter().filter_map(Document::path));
+                watcher.changed()
+            }
+            None => HashSet::new(),
+        };
+        if changed.is_empty() && !regained_focus {
+            return;
+        }
+
+        for index in 0..self.editor.documents().len() {
+            let Some(doc) = self.editor.document_mut(index) else {
+                continue;
+            };
+            let Some(path) = doc.path().cloned() else {
+                continue;
+            };
+            if !regained_focus && !changed.contains(&path) {
+                continue;
+            }
+            match doc.check_disk() {
+                DiskChange::Modified if !doc.is_dirty() => {
+                    if let Err(err) = doc.reload() {
+                        self.error_message = Some(format!("Failed to reload file: {err}"));
+                    }
+                }
+                DiskChange::Modified => {
+                    if !self.external_changes.contains(&path) {
+                        self.external_changes.push(path);
+                    }
+                }
+                DiskChange::Unchanged | DiskChange::Deleted => {}
+            }
+        }
+    }
+
+    /// Keeps the find panel's matches in the current tab indexed in the
+    /// background while the panel is open, starting over whenever the text
+    /// or the search changes.
+    fn update_match_index(&mut self, ctx: &egui::Context) {
+        self.search_service.poll();
+        let Some(doc) = self
+            .editor
+            .current()
+            .filter(|_| self.find_panel.open && !self.find_panel.query.is_empty())
+        else {
+            self.search_service.cancel();
+            return;
+        };
+        let key = self.find_panel.key(doc);
+        match self.find_panel.compiled.get(&key.query, key.options) {
+            Ok(search) => self.search_service.request(ctx, key, doc.rope(), search),
+            Err(error) => {
+                self.search_service.cancel();
+                self.find_panel.error = Some(error);
+            }
+        }
+    }
+
+    /// Notices edits, keeps the recovery journal current and runs idle
+    /// autosave. Journal writes wait for a short pause in typing but never
+    /// fall more than a few seconds behind.
+    fn update_recovery(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        let revisions = self
+            .editor
+            .documents()
+            .iter()
+            .map(|doc| (doc.id(), doc.revision()))
+            .collect::<Vec<_>>();
+        if revisions != self.revisions {
+            self.revisions = revisions;
+            self.last_edit = now;
+            self.autosave_due = true;
+        }
+        let idle = now - self.last_edit;
+
+        if let Some(journal) = &mut self.journal {
+            if journal.is_stale(self.editor.documents()) {
+                if idle >= JOURNAL_IDLE || now - self.last_journal_write >= JOURNAL_MAX_DELAY {
+                    journal.write(self.editor.documents());
+                    self.last_journal_write = now;
+                }
+                ctx.request_repaint_after(JOURNAL_IDLE);
+            }
+        }
+
+        if let (Some(secs), true) = (self.settings.autosave_secs(), self.autosave_due) {
+            let delay = Duration::from_secs(secs);
+            if idle >= delay {
+                self.autosave_due = false;
+                self.autosave();
+            } else {
+                ctx.request_repaint_after(delay - idle);
+            }
+        }
+    }
+
+    /// Saves every dirty tab that has a file, leaving alone tabs whose file
+    /// was deleted or changed by another program.
+    fn autosave(&mut self) {
+        for index in 0..self.editor.documents().len() {
+            let doc = &self.editor.documents()[index];
+            let Some(path) = doc.path().cloned() else {
+                continue;
+            };
+            if !doc.is_dirty() || doc.is_missing_on_disk() || self.external_changes.contains(&path)
+            {
+                continue;
+            }
+            if !self.save_document_at(index, path) {
+                return;
+            }
+        }
+    }
+
+    fn restore_leftovers(&mut self) {
+        for leftover in std::mem::take(&mut self.leftovers) {
+            for doc in leftover.documents() {
+                // The session may already have reopened the file as it is on
+                // disk; the recovered text replaces that tab.
+                let reopened = doc.path().and_then(|path| self.document_index(path));
+                if let Some(index) = reopened {
+                    if !self.editor.documents()[index].is_dirty() {
+                        self.close_tab(index);
+                    }
+                }
+                self.editor.add_document(doc);
+            }
+            leftover.discard();
+        }
+    }
+
+    fn document_index(&self, path: &Path) -> Option<usize> {
+        self.editor
+            .documents()
+            .iter()
+            .position(|doc| doc.path().is_some_and(|doc_path| doc_path == path))
+    }
+
+    fn show_diff(&mut self, index: usize) {
+        let Some(doc) = self.editor.documents().get(index) else {
+            return;
+        };
+        match doc.disk_text() {
+            Ok(on_disk) => {
+                let lines = ui::diff::DiffLines::new(&on_disk, &doc.text());
+                self.diff_view = Some((doc.title(), lines));
+            }
+            Err(err) => self.error_message = Some(format!("Failed to read file: {err}")),
+        }
+    }
+
     fn save_dialog_for(&self, index: usize) -> FileDialog {
         let name = self
             .editor
@@ -629,76 +2058,154 @@ impl PlainpadApp {
             .map(Document::title)
             .unwrap_or_else(|| "Untitled".to_string());
 
-        let file_name = if name.to_lowercase().ends_with(".txt") {
+        let extension = &self.settings.files.default_extension;
+        let suffix = format!(".{}", extension.to_lowercase());
+        let file_name = if name.to_lowercase().ends_with(&suffix) {
             name
         } else {
-            format!("{name}.txt")
+            format!("{name}.{extension}")
         };
 
         FileDialog::new()
-            .add_filter("Text", &["txt"])
+            .add_filter("Text", &[extension.as_str()])
             .set_file_name(file_name)
     }
 }
 
-fn slice_char_range<'a>(text: &'a str, range: &Range<usize>) -> &'a str {
-    let start_byte = byte_index_from_char(text, range.start);
-    let end_byte = byte_index_from_char(text, range.end);
-    &text[start_byte..end_byte]
+/// Every match of `search` in `rope`, taken from `indexed` when it is
+/// finished and searched for otherwise.
+fn all_matches(indexed: Option<&Indexed>, search: &Search, rope: &Rope) -> Vec<Range<usize>> {
+    match indexed.and_then(Indexed::all) {
+        Some(ranges) => ranges.to_vec(),
+        None => search
+            .rope_matches(rope, 0..rope.len_chars())
+            .flatten()
+            .collect(),
+    }
 }
 
-fn byte_range_to_char_range(text: &str, range: Range<usize>) -> Range<usize> {
-    let start = text[..range.start].chars().count();
-    let end = text[..range.end].chars().count();
-    start..end
+/// The edits that replace the matches at `ranges`, leaving out those the
+/// replacement would not change.
+fn replacement_edits(
+    search: &Search,
+    rope: &Rope,
+    ranges: Vec<Range<usize>>,
+    replacement: &str,
+) -> Vec<(Range<usize>, String)> {
+    ranges
+        .into_iter()
+        .filter_map(|range| {
+            let with = search.replace_match(rope, range.clone(), replacement)?;
+            (rope.slice(range.clone()) != with.as_str()).then_some((range, with))
+        })
+        .collect()
 }
 
-fn byte_index_from_char(text: &str, char_index: usize) -> usize {
-    text.char_indices()
-        .nth(char_index)
-        .map(|(index, _)| index)
-        .unwrap_or_else(|| text.len())
+/// The document's non-empty selections.
+fn selection_ranges(doc: &Document) -> Vec<Range<usize>> {
+    doc.selections()
+        .iter()
+        .map(Selection::range)
+        .filter(|range| !range.is_empty())
+        .collect()
 }
 
-fn replace_all_matches(
-    use_regex: bool,
-    text: &str,
-    query: &str,
-    replacement: &str,
-) -> Result<String, String> {
-    if use_regex {
-        let regex = Regex::new(query).map_err(|err| err.to_string())?;
-        return Ok(regex.replace_all(text, replacement).into());
-    }
-
-    Ok(text.replace(query, replacement))
+/// Whether `range` lies inside one of `scope`, or anywhere without a scope.
+fn within(scope: Option<&[Range<usize>]>, range: &Range<usize>) -> bool {
+    scope.is_none_or(|scope| {
+        scope
+            .iter()
+            .any(|outer| outer.start <= range.start && range.end <= outer.end)
+    })
 }
 
-fn count_matches(use_regex: bool, text: &str, query: &str) -> Result<usize, String> {
-    if use_regex {
-        let regex = Regex::new(query).map_err(|err| err.to_string())?;
-        return Ok(regex.find_iter(text).count());
+/// The text Add Next Occurrence and Select All Occurrences look for: the
+/// primary selection, or the word at the cursor, which gets selected first.
+fn occurrence_query(doc: &mut Document) -> Option<String> {
+    let selection = doc.selection();
+    if selection.is_empty() {
+        let range = ui::editor_view::word_range_at(doc.rope(), selection.head);
+        if range.is_empty() {
+            return None;
+        }
+        doc.set_primary_selection(Selection {
+            anchor: range.start,
+            head: range.end,
+        });
     }
+    Some(doc.rope().slice(doc.selection().range()).to_string())
+}
 
-    if query.is_empty() {
-        return Ok(0);
+/// How much is selected, for the status bar: chars and lines, and the
+/// number of cursors when there is more than one. Empty when nothing is.
+fn selection_summary(doc: &Document) -> String {
+    let rope = doc.rope();
+    let mut chars = 0;
+    let mut lines = 0;
+    for selection in doc.selections() {
+        let range = selection.range();
+        if range.is_empty() {
+            continue;
+        }
+        chars += range.len();
+        let first = rope.char_to_line(range.start);
+        let mut last = rope.char_to_line(range.end);
+        // A selection that ends at the start of a line does not take it in.
+        if last > first && rope.line_to_char(last) == range.end {
+            last -= 1;
+        }
+        lines += last - first + 1;
     }
-
-    Ok(text.match_indices(query).count())
+    let cursors = doc.selections().len();
+    let mut summary = String::new();
+    if chars > 0 {
+        let plural = if lines == 1 { "" } else { "s" };
+        summary.push_str(&format!(" | Sel: {chars} ({lines} line{plural})"));
+    }
+    if cursors > 1 {
+        summary.push_str(&format!(" | {cursors} cursors"));
+    }
+    summary
 }
 
 impl eframe::App for PlainpadApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         let mut command: Option<AppCommand> = None;
+        self.check_settings_file(ctx);
+        self.apply_theme(ctx, frame);
 
         if ctx.input(|input| input.viewport().close_requested()) {
             self.handle_close_request(ctx);
         }
 
         egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
-            if let Some(action) =
-                ui::menu::menu_bar(ui, self.show_status_bar, self.show_line_numbers)
-            {
+            let state = ui::menu::MenuState {
+                show_status_bar: self.settings.view.status_bar,
+                show_line_numbers: self.settings.view.line_numbers,
+                word_wrap: self.settings.word_wrap(),
+                encoding: self
+                    .editor
+                    .current()
+                    .map(Document::encoding)
+                    .unwrap_or_default(),
+                can_reopen: self
+                    .editor
+                    .current()
+                    .is_some_and(|doc| doc.path().is_some()),
+                can_undo: self.editor.current().is_some_and(Document::can_undo),
+                can_redo: self.editor.current().is_some_and(Document::can_redo),
+                line_ending: self
+                    .editor
+                    .current()
+                    .map(Document::line_ending)
+                    .unwrap_or_else(LineEnding::platform_default),
+                read_only: self.editor.current().is_some_and(Document::is_read_only),
+                backup: self.settings.files.backup,
+                autosave_secs: self.settings.autosave_secs(),
+                restore_session: self.settings.files.reopen_tabs,
+                keep_undo_history: self.settings.files.keep_undo_history,
+            };
+            if let Some(action) = ui::menu::menu_bar(ui, &state, &self.keymap) {
                 command = Some(action.into());
             }
         });
@@ -716,36 +2223,116 @@ impl eframe::App for PlainpadApp {
             }
         });
 
-        if let Some(shortcut) = detect(ctx) {
-            command = Some(shortcut.into());
+        let mut shortcuts = Vec::new();
+        if self.keymap_editor.is_recording() {
+            self.keymap_editor.record_keys(ctx);
+        } else {
+            let focused = ctx.memory(|memory| memory.focused());
+            let text_focus = focused.is_none() || focused == self.editor_id;
+            shortcuts = shortcuts::detect(ctx, &self.keymap, &mut self.pending_chord, text_focus);
         }
 
         if let Some(command) = command {
             self.handle_command(ctx, command);
         }
+        for shortcut in shortcuts {
+            self.handle_command(ctx, self.shortcut_action(shortcut));
+        }
+
+        self.open_requests(ctx);
+        self.update_waiters(ctx);
+        self.check_external_changes(ctx);
+        self.update_recovery(ctx);
+        self.update_match_index(ctx);
+
+        self.results.poll();
+        match ui::results_panel::results_panel(ctx, &mut self.results) {
+            Some(ResultsAction::Open {
+                source,
+                line,
+                column,
+                len,
+            }) => self.open_result(ctx, source, line, column, len),
+            Some(ResultsAction::Replace(groups)) => self.replace_in_files(groups),
+            None => {}
+        }
 
         self.editor_focused = false;
         self.editor_id = None;
 
         egui::CentralPanel::default().show(ctx, |ui| {
             if let Some(doc) = self.editor.current_mut() {
-                let response = ui::editor_view::editor_view(ui, doc, self.show_line_numbers);
+                let options = ui::editor_view::ViewOptions {
+                    show_line_numbers: self.settings.view.line_numbers,
+                    wrap: self.settings.word_wrap(),
+                    font_id: self.settings.font_id(),
+                    tab_width: self.settings.editor.tab_width,
+                };
+                let indexed = self.search_service.indexed(&self.find_panel.key(doc));
+                // Until the whole tab is searched, the lines that can be on
+                // screen are matched here so highlights keep up with typing.
+                let window = indexed
+                    .filter(|indexed| !indexed.complete)
+                    .and_then(|indexed| {
+                        let search = self
+                            .find_panel
+                            .compiled
+                            .get(&indexed.key.query, indexed.key.options)
+                            .ok()?;
+                        let top = doc.view().top_line.max(0.0) as usize;
+                        let rows = (ui.available_height() / options.font_id.size) as usize;
+                        Some(MatchIndex::build_lines(
+                            &search,
+                            doc.rope(),
+                            top..top + rows + 2,
+                        ))
+                    });
+                let matches = match (indexed, &window) {
+                    (Some(indexed), None) => ui::editor_view::MatchHighlights {
+                        ranges: &indexed.index.ranges,
+                        current: indexed.index.position(&doc.selection().range()),
+                        ticks: &indexed.index.ticks,
+                    },
+                    (Some(indexed), Some(window)) => ui::editor_view::MatchHighlights {
+                        ranges: &window.ranges,
+                        current: window.position(&doc.selection().range()),
+                        ticks: &indexed.index.ticks,
+                    },
+                    (None, _) => Default::default(),
+                };
+                let response = ui::editor_view::editor_view(ui, doc, &options, &matches);
                 self.editor_focused = response.has_focus();
                 self.editor_id = Some(response.id);
             }
         });
 
-        if self.show_status_bar {
+        if self.settings.view.status_bar {
             egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
                 if let Some(doc) = self.editor.current() {
-                    let text = doc.text();
-                    let word_count = text.split_whitespace().count();
-                    let char_count = text.chars().count();
-                    let byte_count = text.len();
-                    let line_count = text.lines().count().max(1);
+                    let rope = doc.rope();
+                    let word_count = doc.word_count();
+                    let char_count = rope.len_chars();
+                    let byte_count = rope.len_bytes();
+                    let line_count = rope.len_lines();
+                    let encoding = doc.encoding().label();
+                    let mut line_ending = doc.line_ending().label().to_string();
+                    if doc.has_mixed_line_endings() {
+                        line_ending.push_str(" (mixed)");
+                    }
+                    let read_only = if doc.is_read_only() {
+                        " | Read-only"
+                    } else {
+                        ""
+                    };
+                    let (line, column) = doc.cursor_position();
+                    let selected = selection_summary(doc);
                     ui.horizontal(|ui| {
+                        if let Some(chord) = self.pending_chord {
+                            ui.strong(format!("{} was pressed. Waiting for the next key...", chord.label()));
+                            ui.separator();
+                        }
                         ui.label(format!(
-                            "Words: {word_count} | Chars: {char_count} | Bytes: {byte_count} | Lines: {line_count}"
+                            "Ln {line}, Col {column}{selected} | Words: {word_count} | Chars: {char_count} | Bytes: {byte_count} | Lines: {line_count} | {line_ending} | {encoding}{read_only}"
                         ));
                     });
                 }
@@ -766,14 +2353,14 @@ impl eframe::App for PlainpadApp {
                             self.editor.set_active(active_index);
                             if let Some(doc) = self.editor.documents().get(index) {
                                 if !doc.is_dirty() {
-                                    self.editor.close_document(index);
+                                    self.close_tab(index);
                                     self.confirm_close = None;
                                     self.close_next_pending();
                                 }
                             }
                         }
                         if ui.button("Discard").clicked() {
-                            self.editor.close_document(index);
+                            self.close_tab(index);
                             self.confirm_close = None;
                             self.close_next_pending();
                         }
@@ -818,48 +2405,288 @@ impl eframe::App for PlainpadApp {
                 .resizable(false)
                 .show(ctx, |ui| {
                     ui.label("Find:");
-                    ui.text_edit_singleline(&mut self.find_panel.query);
+                    let mut changed = ui
+                        .text_edit_singleline(&mut self.find_panel.query)
+                        .changed();
                     ui.label("Replace:");
                     ui.text_edit_singleline(&mut self.find_panel.replace);
-                    ui.checkbox(&mut self.find_panel.use_regex, "Use regex");
+                    ui.horizontal(|ui| {
+                        changed |= ui
+                            .checkbox(&mut self.find_panel.match_case, "Match case")
+                            .changed();
+                        changed |= ui
+                            .checkbox(&mut self.find_panel.whole_word, "Whole word")
+                            .changed();
+                        changed |= ui
+                            .checkbox(&mut self.find_panel.use_regex, "Use regex")
+                            .changed();
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.find_panel.backwards, "Search backwards");
+                        ui.checkbox(&mut self.find_panel.wrap_around, "Wrap around");
+                        ui.checkbox(&mut self.find_panel.in_selection, "In selection")
+                            .on_hover_text(
+                                "Replace All and Select All only change the selected text",
+                            );
+                    });
+
+                    // Messages from the last search no longer apply.
+                    if changed {
+                        self.find_panel.error = None;
+                        self.find_panel.notice = None;
+                    }
 
                     if let Some(doc) = self.editor.current() {
-                        if !self.find_panel.query.is_empty() {
-                            match count_matches(
-                                self.find_panel.use_regex,
-                                doc.text(),
-                                &self.find_panel.query,
-                            ) {
-                                Ok(count) => {
-                                    self.find_panel.error = None;
-                                    ui.label(format!("Matches: {count}"));
-                                }
-                                Err(error) => {
-                                    self.find_panel.error = Some(error);
+                        let key = self.find_panel.key(doc);
+                        match self.search_service.indexed(&key) {
+                            Some(indexed) if indexed.complete => {
+                                let total = indexed.index.ranges.len();
+                                match indexed.index.position(&doc.selection().range()) {
+                                    Some(current) => {
+                                        ui.label(format!("{} of {total}", current + 1));
+                                    }
+                                    None if total == 1 => {
+                                        ui.label("1 match");
+                                    }
+                                    None => {
+                                        ui.label(format!("{total} matches"));
+                                    }
                                 }
                             }
+                            Some(indexed) => {
+                                let found = indexed.index.ranges.len();
+                                ui.weak(format!("Counting matches... {found} so far"));
+                            }
+                            None => {}
                         }
                     }
 
                     if let Some(error) = self.find_panel.error.clone() {
                         ui.label(error);
                     }
+                    if let Some(notice) = &self.find_panel.notice {
+                        ui.weak(notice);
+                    }
 
                     ui.horizontal(|ui| {
                         if ui.button("Find Next").clicked() {
                             self.find_next(ctx);
                         }
+                        if ui.button("Find Previous").clicked() {
+                            self.find_previous(ctx);
+                        }
                         if ui.button("Replace").clicked() {
                             self.replace_current(ctx);
                         }
                         if ui.button("Replace All").clicked() {
                             self.replace_all();
                         }
+                        if ui.button("Select All").clicked() {
+                            self.select_all_matches(ctx);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Find in Open Tabs").clicked() {
+                            self.find_in_tabs(ctx);
+                        }
+                        if ui.button("Replace All in All Tabs").clicked() {
+                            self.replace_all_in_tabs(ctx);
+                        }
                     });
                 });
             self.find_panel.open = open;
         }
 
+        if !self.leftovers.is_empty() {
+            let count: usize = self.leftovers.iter().map(Leftover::tab_count).sum();
+            egui::Window::new("Restore Unsaved Tabs")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "Plainpad did not close properly last time. \
+                         Restore {count} unsaved tab{}?",
+                        if count == 1 { "" } else { "s" }
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Restore").clicked() {
+                            self.restore_leftovers();
+                        }
+                        if ui.button("Discard").clicked() {
+                            for leftover in self.leftovers.drain(..) {
+                                leftover.discard();
+                            }
+                        }
+                    });
+                });
+        }
+
+        if let Some(path) = self.external_changes.first().cloned() {
+            match self.document_index(&path) {
+                None => {
+                    self.external_changes.remove(0);
+                }
+                Some(index) => {
+                    let title = self.editor.documents()[index].title();
+                    egui::Window::new("File Changed on Disk")
+                        .collapsible(false)
+                        .resizable(false)
+                        .show(ctx, |ui| {
+                            ui.label(format!(
+                                "{title} was changed by another program, and this tab has unsaved changes."
+                            ));
+                            ui.horizontal(|ui| {
+                                if ui.button("Reload").clicked() {
+                                    if let Some(doc) = self.editor.document_mut(index) {
+                                        if let Err(err) = doc.reload() {
+                                            self.error_message =
+                                                Some(format!("Failed to reload file: {err}"));
+                                        }
+                                    }
+                                    self.external_changes.remove(0);
+                                }
+                                if ui.button("Keep My Changes").clicked() {
+                                    if let Some(doc) = self.editor.document_mut(index) {
+                                        doc.accept_disk_version();
+                                    }
+                                    self.external_changes.remove(0);
+                                }
+                                if ui.button("View Diff").clicked() {
+                                    self.show_diff(index);
+                                }
+                            });
+                        });
+                }
+            }
+        }
+
+        if self.go_to.open {
+            let (current, line_count) = self
+                .editor
+                .current()
+                .map(|doc| (doc.cursor_position(), doc.rope().len_lines()))
+                .unwrap_or(((1, 1), 1));
+            let target = ui::go_to::go_to_dialog(ctx, &mut self.go_to, current, line_count);
+            if let (Some(target), Some(doc)) = (target, self.editor.current_mut()) {
+                let index = target.char_index(doc.rope());
+                doc.select(index..index);
+                self.focus_editor(ctx);
+            }
+        }
+
+        if self.find_in_files.open {
+            let mut options = self.find_panel.options();
+            let action = ui::find_in_files::find_in_files_dialog(
+                ctx,
+                &mut self.find_in_files,
+                &mut self.find_panel.query,
+                &mut self.find_panel.replace,
+                &mut options,
+            );
+            self.find_panel.use_regex = options.use_regex;
+            self.find_panel.match_case = options.match_case;
+            self.find_panel.whole_word = options.whole_word;
+            match action {
+                Some(FindInFilesAction::Find) => self.find_in_files(ctx, false),
+                Some(FindInFilesAction::Replace) => self.find_in_files(ctx, true),
+                Some(FindInFilesAction::ChooseFolder) => {
+                    if let Some(dir) = FileDialog::new()
+                        .set_title("Choose a folder to search")
+                        .pick_folder()
+                    {
+                        self.find_in_files.root = dir.display().to_string();
+                    }
+                }
+                None => {}
+            }
+        }
+
+        if self.preferences.open {
+            let action =
+                ui::preferences::preferences_window(ctx, &mut self.preferences, &self.settings);
+            if let Some(settings) = action.changed {
+                self.apply_settings(settings);
+                self.settings_unsaved = true;
+            }
+            if action.choose_backup_folder {
+                self.set_backup(Backup::Timestamped);
+            }
+            if action.open_settings_file {
+                self.open_settings_file();
+            }
+        }
+        if self.settings_unsaved && !ctx.input(|input| input.pointer.any_down()) {
+            self.store_settings();
+        }
+
+        if self.command_palette.open {
+            let items = self.palette_items();
+            if let Some(command) =
+                ui::command_palette::command_palette(ctx, &mut self.command_palette, &items)
+            {
+                self.handle_command(ctx, command);
+            }
+        }
+
+        if self.keymap_editor.open {
+            let action =
+                ui::keymap_editor::keymap_editor_window(ctx, &mut self.keymap_editor, &self.keymap);
+            if let Some(keymap) = action.changed {
+                self.keymap = keymap;
+                self.store_keymap();
+            }
+            if action.open_keymap_file {
+                self.open_keymap_file();
+            }
+        }
+
+        if let Some((index, path)) = self.confirm_overwrite.clone() {
+            let title = self
+                .editor
+                .documents()
+                .get(index)
+                .map(Document::title)
+                .unwrap_or_default();
+            egui::Window::new("Overwrite File?")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "{title} was changed by another program since it was opened. \
+                         Saving will overwrite those changes."
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Overwrite").clicked() {
+                            self.confirm_overwrite = None;
+                            if let Some(doc) = self.editor.document_mut(index) {
+                                doc.accept_disk_version();
+                            }
+                            self.save_document_at(index, path.clone());
+                        }
+                        if ui.button("View Diff").clicked() {
+                            self.show_diff(index);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.confirm_overwrite = None;
+                        }
+                    });
+                });
+        }
+
+        if let Some((title, lines)) = &self.diff_view {
+            let mut open = true;
+            egui::Window::new(format!("Changes in {title}"))
+                .open(&mut open)
+                .default_width(640.0)
+                .show(ctx, |ui| {
+                    ui.label("Lines marked - are on disk, lines marked + are in this tab.");
+                    ui::diff::diff_view(ui, lines);
+                });
+            if !open {
+                self.diff_view = None;
+            }
+        }
+
         if let Some(message) = self.error_message.clone() {
             egui::Window::new("Error")
                 .collapsible(false)
@@ -893,4 +2720,24 @@ impl eframe::App for PlainpadApp {
                 });
         }
     }
+
+    /// A clean exit saves the session and drops the recovery journal; only a
+    /// crash leaves the journal behind.
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        if self.settings_unsaved {
+            self.store_settings();
+        }
+        self.save_session();
+        if self.settings.files.keep_undo_history {
+            for doc in self.editor.documents() {
+                let _ = history::store(doc);
+            }
+        }
+        for waiter in std::mem::take(&mut self.waiters) {
+            self.finish_waiter(waiter);
+        }
+        if let Some(journal) = self.journal.take() {
+            journal.discard();
+        }
+    }
 }
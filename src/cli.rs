@@ -0,0 +1,215 @@
+use crate::encoding::{self, TextEncoding};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Read},
+    path::{self, PathBuf},
+};
+
+#[derive(Parser)]
+#[command(name = "plainpad", version, about)]
+struct Args {
+    /// Files to open. `notes.txt:120` or `notes.txt:120:5` puts the cursor on
+    /// that line and column; `-` reads standard input into a new tab.
+    files: Vec<String>,
+
+    /// Open a window with only the given files instead of reopening the last
+    /// session. Implies --new-instance.
+    #[arg(long)]
+    new_window: bool,
+
+    /// Start a separate plainpad instead of handing the files to the one that
+    /// is already running.
+    #[arg(long)]
+    new_instance: bool,
+
+    /// Wait until the tabs for the given files are closed before exiting, for
+    /// use as $EDITOR or GIT_EDITOR. Exits with 0 if they were all saved and
+    /// 1 if not.
+    #[arg(long)]
+    wait: bool,
+
+    /// Open the given files read-only.
+    #[arg(long)]
+    readonly: bool,
+
+    /// Decode the given files with this encoding instead of detecting it,
+    /// e.g. utf-8, windows-1252, shift_jis or utf-16le.
+    #[arg(long, value_name = "LABEL", value_parser = parse_encoding)]
+    encoding: Option<TextEncoding>,
+}
+
+fn parse_encoding(label: &str) -> Result<TextEncoding, String> {
+    encoding::from_label(label).ok_or_else(|| format!("unknown encoding `{label}`"))
+}
+
+/// Something to open in a tab.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Target {
+    File {
+        path: PathBuf,
+        /// 1-based line and column to put the cursor at.
+        position: Option<(usize, usize)>,
+    },
+    Stdin(Vec<u8>),
+}
+
+/// What plainpad was asked to do on the command line.
+pub struct Launch {
+    pub targets: Vec<Target>,
+    pub new_window: bool,
+    pub new_instance: bool,
+    pub wait: bool,
+    pub read_only: bool,
+    pub encoding: Option<TextEncoding>,
+}
+
+impl Launch {
+    /// Parses the process arguments, exiting with a usage message when they
+    /// are invalid, and reads standard input if `-` was given.
+    pub fn from_args() -> io::Result<Self> {
+        let args = Args::parse();
+        let mut targets = Vec::new();
+        let mut stdin_read = false;
+        for arg in &args.files {
+            if arg == "-" {
+                if !stdin_read {
+                    let mut bytes = Vec::new();
+                    io::stdin().lock().read_to_end(&mut bytes)?;
+                    targets.push(Target::Stdin(bytes));
+                    stdin_read = true;
+                }
+            } else {
+                targets.push(parse_location(arg));
+            }
+        }
+        Ok(Self {
+            targets,
+            new_window: args.new_window,
+            new_instance: args.new_instance || args.new_window,
+            wait: args.wait,
+            read_only: args.readonly,
+            encoding: args.encoding,
+        })
+    }
+}
+
+/// Splits `notes.txt:120:5` into the path and position, as printed by
+/// compilers and `git grep -n`. An argument that names an existing file is
+/// taken whole, so names that contain colons still open.
+fn parse_location(arg: &str) -> Target {
+    let whole = PathBuf::from(arg);
+    if whole.exists() {
+        return Target::File {
+            path: absolute(whole),
+            position: None,
+        };
+    }
+
+    let mut rest = arg.strip_suffix(':').unwrap_or(arg);
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        let Some((head, tail)) = rest.rsplit_once(':') else {
+            break;
+        };
+        let Ok(number) = tail.parse::<usize>() else {
+            break;
+        };
+        if head.is_empty() {
+            break;
+        }
+        numbers.push(number);
+        rest = head;
+    }
+
+    let position = match numbers.as_slice() {
+        [line] => Some((*line, 1)),
+        [column, line] => Some((*line, *column)),
+        _ => None,
+    };
+    let path = if position.is_some() { rest } else { arg };
+    Target::File {
+        path: absolute(PathBuf::from(path)),
+        position,
+    }
+}
+
+/// Makes a path absolute without resolving symbolic links, so it matches the
+/// paths the file watcher and the session report.
+fn absolute(path: PathBuf) -> PathBuf {
+    path::absolute(&path).unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The path and position parsed from an argument naming no real file.
+    fn location(arg: &str) -> (PathBuf, Option<(usize, usize)>) {
+        match parse_location(arg) {
+            Target::File { path, position } => (path, position),
+            Target::Stdin(_) => unreachable!(),
+        }
+    }
+
+    fn path(name: &str) -> PathBuf {
+        absolute(PathBuf::from(name))
+    }
+
+    #[test]
+    fn file_and_line() {
+        assert_eq!(
+            location("missing.txt:120"),
+            (path("missing.txt"), Some((120, 1)))
+        );
+        // `git grep -n` style, with a trailing colon.
+        assert_eq!(
+            location("missing.txt:7:"),
+            (path("missing.txt"), Some((7, 1)))
+        );
+    }
+
+    #[test]
+    fn file_line_and_column() {
+        assert_eq!(
+            location("missing.txt:120:5"),
+            (path("missing.txt"), Some((120, 5)))
+        );
+        // At most two numbers are taken from the end.
+        assert_eq!(location("1:2:3"), (path("1"), Some((2, 3))));
+    }
+
+    #[test]
+    fn windows_drive_letters() {
+        assert_eq!(location(r"C:\x:3"), (path(r"C:\x"), Some((3, 1))));
+        assert_eq!(location(r"C:\x:3:4"), (path(r"C:\x"), Some((3, 4))));
+        assert_eq!(location(r"C:\x"), (path(r"C:\x"), None));
+    }
+
+    #[test]
+    fn non_numeric_suffix_is_part_of_the_name() {
+        assert_eq!(location("missing:abc"), (path("missing:abc"), None));
+        assert_eq!(location("missing:-3"), (path("missing:-3"), None));
+        // A bare number after a colon with nothing before it is a name.
+        assert_eq!(location(":5"), (path(":5"), None));
+    }
+
+    #[test]
+    fn line_zero_is_kept_for_go_to_to_clamp() {
+        assert_eq!(
+            location("missing.txt:0"),
+            (path("missing.txt"), Some((0, 1)))
+        );
+    }
+
+    #[test]
+    fn existing_file_is_taken_whole() {
+        let dir = std::env::temp_dir().join(format!("plainpad-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("name:12");
+        std::fs::write(&file, "").unwrap();
+        let parsed = location(file.to_str().unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(parsed, (file, None));
+    }
+}
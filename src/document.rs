@@ -1,42 +1,438 @@
-use ropey::Rope;
-use std::{fs, io, path::PathBuf};
+use crate::{
+    encoding::{self, TextEncoding},
+    line_ending::{self, LineEnding},
+    save::{self, SaveOptions},
+    watcher::{self, DiskChange, DiskState, HashingWriter},
+};
+use ropey::{Rope, RopeSlice};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    fs, io,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Typing or deleting that pauses for longer than this starts a new undo step.
+const GROUP_PAUSE: Duration = Duration::from_secs(1);
+
+/// A cursor with an optional selection, both as char indices into the rope.
+/// `anchor == head` means there is no selection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    pub fn cursor(index: usize) -> Self {
+        Self {
+            anchor: index,
+            head: index,
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.anchor.min(self.head)..self.anchor.max(self.head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+}
+
+/// Every cursor of a tab, sorted by position and never overlapping. The
+/// primary one is the cursor the view follows and single-cursor commands use.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Selections {
+    ranges: Vec<Selection>,
+    primary: usize,
+}
+
+impl Default for Selections {
+    fn default() -> Self {
+        Self {
+            ranges: vec![Selection::default()],
+            primary: 0,
+        }
+    }
+}
+
+impl Selections {
+    /// Sorts `ranges`, clamps them to `len` chars and merges any that overlap
+    /// or sit on the same spot.
+    fn new(ranges: Vec<Selection>, primary: usize, len: usize) -> Self {
+        let mut indexed = ranges
+            .into_iter()
+            .map(|selection| Selection {
+                anchor: selection.anchor.min(len),
+                head: selection.head.min(len),
+            })
+            .enumerate()
+            .collect::<Vec<_>>();
+        indexed.sort_by_key(|(_, selection)| (selection.range().start, selection.range().end));
+
+        let mut merged: Vec<Selection> = Vec::with_capacity(indexed.len());
+        let mut new_primary = 0;
+        for (index, selection) in indexed {
+            let next = selection.range();
+            if let Some(last) = merged.last_mut() {
+                let current = last.range();
+                let touches =
+                    next.start == current.end && (last.is_empty() || selection.is_empty());
+                if next.start < current.end || touches {
+                    let (start, end) = (current.start, current.end.max(next.end));
+                    *last = if last.head < last.anchor {
+                        Selection {
+                            anchor: end,
+                            head: start,
+                        }
+                    } else {
+                        Selection {
+                            anchor: start,
+                            head: end,
+                        }
+                    };
+                    if index == primary {
+                        new_primary = merged.len() - 1;
+                    }
+                    continue;
+                }
+            }
+            if index == primary {
+                new_primary = merged.len();
+            }
+            merged.push(selection);
+        }
+        if merged.is_empty() {
+            merged.push(Selection::default());
+        }
+        Self {
+            ranges: merged,
+            primary: new_primary,
+        }
+    }
+
+    fn primary(&self) -> Selection {
+        self.ranges[self.primary]
+    }
+}
+
+/// A rectangle selected with Alt+drag, as lines and char columns. The
+/// columns can lie past the end of short lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    pub anchor_line: usize,
+    pub anchor_col: usize,
+    pub head_line: usize,
+    pub head_col: usize,
+}
+
+impl Block {
+    pub fn lines(&self) -> RangeInclusive<usize> {
+        self.anchor_line.min(self.head_line)..=self.anchor_line.max(self.head_line)
+    }
+
+    pub fn columns(&self) -> Range<usize> {
+        self.anchor_col.min(self.head_col)..self.anchor_col.max(self.head_col)
+    }
+}
+
+/// Per-tab scroll position of the editor view.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ViewState {
+    /// First visible line; the fraction is how far that line is scrolled off.
+    pub top_line: f64,
+    pub scroll_x: f32,
+    /// Set when the selection moved programmatically and should be scrolled
+    /// into view on the next frame.
+    pub reveal_cursor: bool,
+}
+
+/// How an edit joins the undo history. Typing and deleting merge into the
+/// step before them while the cursor stays where the last one left it;
+/// anything else is an undo step of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EditKind {
+    Typing,
+    Deleting,
+    Other,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Edit {
+    start: usize,
+    removed: String,
+    inserted: String,
+}
+
+impl Edit {
+    /// Where the cursor ends up right after this edit.
+    fn end(&self) -> usize {
+        self.start + self.inserted.chars().count()
+    }
+}
+
+/// One undo step: the edits it made, in the order they were applied.
+#[derive(Clone, Serialize, Deserialize)]
+struct Transaction {
+    id: u64,
+    kind: EditKind,
+    edits: Vec<Edit>,
+    selections_before: Selections,
+    /// The cursors right after the step, put back by redo. Typing records
+    /// them as it goes; other steps take them when they are undone.
+    #[serde(default)]
+    selections_after: Option<Selections>,
+    #[serde(skip, default = "Instant::now")]
+    last_edit: Instant,
+}
+
+impl Transaction {
+    fn text_len(&self) -> usize {
+        self.edits
+            .iter()
+            .map(|edit| edit.removed.len() + edit.inserted.len())
+            .sum()
+    }
+}
+
+/// The undo and redo stacks of a tab as they stand at the text of its file,
+/// so they can be kept after the tab is closed. Both stacks run from the
+/// oldest step to the one next in line.
+#[derive(Default, Serialize, Deserialize)]
+pub struct UndoHistory {
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+}
+
+impl UndoHistory {
+    pub fn is_empty(&self) -> bool {
+        self.undo.is_empty() && self.redo.is_empty()
+    }
+
+    /// Drops the steps furthest from the file's text until the edits they
+    /// hold add up to at most `max_bytes`.
+    pub fn trim(&mut self, max_bytes: usize) {
+        let mut total: usize = self
+            .undo
+            .iter()
+            .chain(&self.redo)
+            .map(Transaction::text_len)
+            .sum();
+        while total > max_bytes {
+            let stack = if self.undo.len() >= self.redo.len() {
+                &mut self.undo
+            } else {
+                &mut self.redo
+            };
+            if stack.is_empty() {
+                break;
+            }
+            total -= stack.remove(0).text_len();
+        }
+    }
+}
 
 pub struct Document {
-    text: String,
+    id: u64,
+    revision: u64,
     rope: Rope,
+    word_count: Cell<Option<usize>>,
+    /// Length in chars of the longest line, for the horizontal scroll range.
+    longest_line: Cell<Option<usize>>,
     path: Option<PathBuf>,
     untitled_name: String,
     dirty: bool,
+    encoding: TextEncoding,
+    malformed: bool,
+    line_ending: LineEnding,
+    mixed_line_endings: bool,
+    /// Style of every line break as the file had it, kept while the file has
+    /// mixed endings and the text is unedited.
+    original_breaks: Option<Vec<LineEnding>>,
+    disk: Option<DiskState>,
+    missing_on_disk: bool,
+    selections: Selections,
+    /// Set while the cursors come from a block selection.
+    block: Option<Block>,
+    view: ViewState,
+    undo_stack: Vec<Transaction>,
+    redo_stack: Vec<Transaction>,
+    next_transaction: u64,
+    /// The undo step the file on disk matches: `Some(0)` before the first
+    /// one, `None` when no point in the history matches it.
+    saved_transaction: Option<u64>,
+    /// Set when the encoding or line endings changed since the last save.
+    format_changed: bool,
+    /// Keeps the next edit from merging into the top undo step.
+    group_closed: bool,
+    read_only: bool,
 }
 
 impl Document {
     pub fn new_untitled(index: usize) -> Self {
         let name = format!("Untitled {}", index);
-        Self {
-            text: String::new(),
-            rope: Rope::new(),
-            path: None,
-            untitled_name: name,
-            dirty: false,
-        }
+        Self::with_rope(Rope::new(), None, name)
     }
 
-    pub fn from_path(path: PathBuf) -> io::Result<Self> {
-        let text = fs::read_to_string(&path)?;
-        let rope = Rope::from_str(&text);
-        let untitled_name = path
-            .file_name()
-            .and_then(|name| name.to_str())
-            .unwrap_or("Untitled")
-            .to_string();
+    /// Opens `path`, detecting its encoding unless one is given.
+    pub fn from_path(path: PathBuf, encoding: Option<TextEncoding>) -> io::Result<Self> {
+        let meta = fs::metadata(&path)?;
+        let bytes = fs::read(&path)?;
+        let disk = DiskState::new(&meta, watcher::hash_bytes(&bytes));
+        let untitled_name = file_name(&path);
+        let mut doc = Self::from_bytes(bytes, encoding, untitled_name);
+        doc.path = Some(path);
+        doc.disk = Some(disk);
+        Ok(doc)
+    }
+
+    /// Builds an untitled tab from raw bytes, such as text piped to standard
+    /// input, detecting their encoding unless one is given.
+    pub fn from_bytes(
+        bytes: Vec<u8>,
+        encoding: Option<TextEncoding>,
+        untitled_name: String,
+    ) -> Self {
+        let encoding = match encoding {
+            // A BOM that matches the requested encoding is still a BOM.
+            Some(encoding) if !encoding.bom => TextEncoding {
+                bom: encoding_rs::Encoding::for_bom(&bytes)
+                    .is_some_and(|(found, _)| found == encoding.encoding),
+                ..encoding
+            },
+            Some(encoding) => encoding,
+            None => encoding::detect(&bytes),
+        };
+        let decoded = encoding::decode(bytes, encoding);
+        let normalized = line_ending::normalize(&decoded.text);
+        drop(decoded.text);
+        let mut doc = Self::with_rope(normalized.rope, None, untitled_name);
+        doc.encoding = encoding;
+        doc.malformed = decoded.malformed;
+        doc.line_ending = normalized.line_ending;
+        doc.mixed_line_endings = normalized.mixed.is_some();
+        doc.original_breaks = normalized.mixed;
+        doc
+    }
 
-        Ok(Self {
-            text,
+    /// An empty tab for a file that does not exist yet; saving creates it.
+    pub fn new_at(path: PathBuf) -> Self {
+        let untitled_name = file_name(&path);
+        Self::with_rope(Rope::new(), Some(path), untitled_name)
+    }
+
+    /// Rebuilds a tab from text kept by the recovery journal. The tab starts
+    /// out dirty, and `disk` is what the file looked like when the text was
+    /// journaled, so a file changed since then is still noticed.
+    pub fn recovered(
+        path: Option<PathBuf>,
+        untitled_name: String,
+        text: &str,
+        encoding: TextEncoding,
+        line_ending: LineEnding,
+        disk: Option<DiskState>,
+    ) -> Self {
+        let mut doc = Self::with_rope(Rope::from_str(text), path, untitled_name);
+        doc.encoding = encoding;
+        doc.line_ending = line_ending;
+        doc.disk = disk;
+        doc.saved_transaction = None;
+        doc.dirty = true;
+        doc
+    }
+
+    /// Reads the file again from disk in its current encoding, dropping any
+    /// edits but keeping the cursor and scroll position.
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.reload_with_encoding(self.encoding)
+    }
+
+    /// Reads the file again from disk using `encoding`. Only the span that
+    /// differs is replaced, as one undo step, so the edits it drops can be
+    /// brought back and the rest of the editing state is left alone.
+    pub fn reload_with_encoding(&mut self, encoding: TextEncoding) -> io::Result<()> {
+        let Some(path) = self.path.clone() else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "this tab has not been saved to a file yet",
+            ));
+        };
+        let reloaded = Self::from_path(path, Some(encoding))?;
+        let (range, text) = changed_span(&self.rope, &reloaded.rope);
+        if !range.is_empty() || !text.is_empty() {
+            let removed = self.rope.slice(range.clone()).to_string();
+            self.apply(range.clone(), &text);
+            self.begin_transaction(EditKind::Other).edits.push(Edit {
+                start: range.start,
+                removed,
+                inserted: text,
+            });
+            self.group_closed = true;
+            self.redo_stack.clear();
+        }
+        self.set_selections(self.selections.ranges.clone(), self.selections.primary);
+        self.encoding = reloaded.encoding;
+        self.malformed = reloaded.malformed;
+        self.line_ending = reloaded.line_ending;
+        self.mixed_line_endings = reloaded.mixed_line_endings;
+        self.original_breaks = reloaded.original_breaks;
+        self.disk = reloaded.disk;
+        self.missing_on_disk = false;
+        self.saved_transaction = Some(
+            self.undo_stack
+                .last()
+                .map_or(0, |transaction| transaction.id),
+        );
+        self.format_changed = false;
+        self.dirty = false;
+        self.revision += 1;
+        Ok(())
+    }
+
+    fn with_rope(rope: Rope, path: Option<PathBuf>, untitled_name: String) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            revision: 0,
             rope,
-            path: Some(path),
+            word_count: Cell::new(None),
+            longest_line: Cell::new(None),
+            path,
             untitled_name,
             dirty: false,
-        })
+            encoding: TextEncoding::default(),
+            malformed: false,
+            line_ending: LineEnding::platform_default(),
+            mixed_line_endings: false,
+            original_breaks: None,
+            disk: None,
+            missing_on_disk: false,
+            selections: Selections::default(),
+            block: None,
+            view: ViewState::default(),
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            next_transaction: 1,
+            saved_transaction: Some(0),
+            format_changed: false,
+            group_closed: false,
+            read_only: false,
+        }
+    }
+
+    /// Identifies the tab for as long as the process runs, across reloads.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Bumped by every change to the text, the save format or the dirty flag.
+    pub fn revision(&self) -> u64 {
+        self.revision
     }
 
     pub fn title(&self) -> String {
@@ -50,35 +446,1357 @@ impl Document {
         }
     }
 
-    pub fn text_mut(&mut self) -> &mut String {
-        &mut self.text
+    pub fn rope(&self) -> &Rope {
+        &self.rope
     }
 
-    pub fn text(&self) -> &str {
-        &self.text
+    /// Returns the whole document as one string, borrowing when the rope is a
+    /// single chunk.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self.rope.slice(..).as_str() {
+            Some(text) => Cow::Borrowed(text),
+            None => Cow::Owned(self.rope.to_string()),
+        }
     }
 
     pub fn is_empty(&self) -> bool {
-        self.text.is_empty()
+        self.rope.len_chars() == 0
+    }
+
+    /// Counts whitespace-separated words. The full scan happens once; after
+    /// that every edit adjusts the count from the text around it.
+    pub fn word_count(&self) -> usize {
+        if let Some(count) = self.word_count.get() {
+            return count;
+        }
+        let (count, _, _) = count_words(self.rope.chars());
+        self.word_count.set(Some(count));
+        count
+    }
+
+    /// Length in chars of the longest line. Like the word count it is scanned
+    /// once and then kept up to date from the lines each edit touches.
+    pub fn longest_line(&self) -> usize {
+        if let Some(longest) = self.longest_line.get() {
+            return longest;
+        }
+        let longest = self.rope.lines().map(line_content_len).max().unwrap_or(0);
+        self.longest_line.set(Some(longest));
+        longest
+    }
+
+    /// The primary cursor and its selection.
+    pub fn selection(&self) -> Selection {
+        self.selections.primary()
+    }
+
+    /// All cursors, sorted by position.
+    pub fn selections(&self) -> &[Selection] {
+        &self.selections.ranges
+    }
+
+    pub fn primary_index(&self) -> usize {
+        self.selections.primary
+    }
+
+    /// Leaves `selection` as the only cursor.
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.set_selections(vec![selection], 0);
+    }
+
+    /// Replaces every cursor. Overlapping selections are merged, and
+    /// `primary` indexes into `selections` as given.
+    pub fn set_selections(&mut self, selections: Vec<Selection>, primary: usize) {
+        self.selections = Selections::new(selections, primary, self.rope.len_chars());
+        self.block = None;
+    }
+
+    pub fn block(&self) -> Option<Block> {
+        self.block
+    }
+
+    /// Selects a rectangle: one selection per line, cut off where a line is
+    /// shorter than the block.
+    pub fn set_block(&mut self, block: Block) {
+        let last_line = self.rope.len_lines() - 1;
+        let block = Block {
+            anchor_line: block.anchor_line.min(last_line),
+            head_line: block.head_line.min(last_line),
+            ..block
+        };
+        let mut selections = Vec::new();
+        for line in block.lines() {
+            let start = self.rope.line_to_char(line);
+            let len = line_content_len(self.rope.line(line));
+            let at = |col: usize| start + col.min(len);
+            selections.push(Selection {
+                anchor: at(block.anchor_col),
+                head: at(block.head_col),
+            });
+        }
+        let primary = if block.head_line >= block.anchor_line {
+            selections.len() - 1
+        } else {
+            0
+        };
+        self.set_selections(selections, primary);
+        self.block = Some(block);
+    }
+
+    /// Adds a cursor and makes it the primary one.
+    pub fn add_selection(&mut self, selection: Selection) {
+        let mut ranges = self.selections.ranges.clone();
+        ranges.push(selection);
+        let primary = ranges.len() - 1;
+        self.set_selections(ranges, primary);
     }
 
-    pub fn sync_rope(&mut self) {
-        self.rope = Rope::from_str(&self.text);
-        self.dirty = true;
+    /// Moves the primary cursor, keeping the others.
+    pub fn set_primary_selection(&mut self, selection: Selection) {
+        let mut ranges = self.selections.ranges.clone();
+        ranges[self.selections.primary] = selection;
+        self.set_selections(ranges, self.selections.primary);
+    }
+
+    /// The selected text of every cursor, one per line.
+    pub fn selected_text(&self) -> String {
+        let texts = self
+            .selections
+            .ranges
+            .iter()
+            .map(|selection| self.rope.slice(selection.range()).to_string())
+            .collect::<Vec<_>>();
+        if texts.iter().all(String::is_empty) {
+            return String::new();
+        }
+        texts.join("\n")
+    }
+
+    /// Selects `range` and asks the editor view to scroll it into view.
+    pub fn select(&mut self, range: Range<usize>) {
+        self.set_selection(Selection {
+            anchor: range.start,
+            head: range.end,
+        });
+        self.view.reveal_cursor = true;
+    }
+
+    pub fn view(&self) -> &ViewState {
+        &self.view
+    }
+
+    pub fn view_mut(&mut self) -> &mut ViewState {
+        &mut self.view
+    }
+
+    /// Puts the cursor at a 1-based line and column, clamped to the text,
+    /// and asks the editor view to scroll it into view.
+    pub fn go_to(&mut self, line: usize, column: usize) {
+        let index = line_column_index(&self.rope, line, column);
+        self.select(index..index);
+    }
+
+    /// 1-based line and column of the primary cursor.
+    pub fn cursor_position(&self) -> (usize, usize) {
+        let head = self.selection().head;
+        let line = self.rope.char_to_line(head);
+        (line + 1, head - self.rope.line_to_char(line) + 1)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// A read-only tab ignores edits, undo and changes to its save format.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Replaces the characters in `range` with `text` as one remove and one
+    /// insert on the rope, recorded as an undo step of its own. Line breaks
+    /// in `text` are normalized to `\n`.
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        self.replace_ranges(vec![(range, text.to_string())]);
+    }
+
+    /// Replaces several non-overlapping ranges, given in ascending order, as
+    /// a single undo step. The cursors are left for the caller to place.
+    pub fn replace_ranges(&mut self, replacements: Vec<(Range<usize>, String)>) {
+        if self.read_only || replacements.is_empty() {
+            return;
+        }
+        let mut edits = Vec::with_capacity(replacements.len());
+        // Back to front, so each range is still where the caller found it.
+        for (range, text) in replacements.into_iter().rev() {
+            let text = line_ending::normalize_str(&text).into_owned();
+            let end = range.end.min(self.rope.len_chars());
+            let start = range.start.min(end);
+            if start == end && text.is_empty() {
+                continue;
+            }
+            let removed = self.rope.slice(start..end).to_string();
+            self.apply(start..end, &text);
+            edits.push(Edit {
+                start,
+                removed,
+                inserted: text,
+            });
+        }
+        if edits.is_empty() {
+            return;
+        }
+        self.begin_transaction(EditKind::Other).edits = edits;
+        self.group_closed = true;
+        self.redo_stack.clear();
+        self.set_selections(self.selections.ranges.clone(), self.selections.primary);
+        self.update_dirty();
+    }
+
+    /// Replaces every selection with `text` and leaves a cursor after each
+    /// copy. With several cursors and one line of `text` per cursor, each
+    /// cursor gets its own line instead.
+    /// In a block selection, lines that end before the block are padded
+    /// with spaces so the text lines up.
+    pub fn insert(&mut self, text: &str, kind: EditKind) -> bool {
+        let text = line_ending::normalize_str(text);
+        let lines = text
+            .strip_suffix('\n')
+            .unwrap_or(&text)
+            .split('\n')
+            .collect::<Vec<_>>();
+        let count = self.selections.ranges.len();
+        let per_cursor = count > 1 && lines.len() == count;
+        let block_start = self.block.map(|block| block.columns().start);
+        self.edit_selections(kind, |rope, index, selection| {
+            let text = if per_cursor { lines[index] } else { &*text };
+            let range = selection.range();
+            let line = rope.char_to_line(range.start);
+            let col = range.start - rope.line_to_char(line);
+            let pad = block_start.map_or(0, |start| start.saturating_sub(col));
+            (range, format!("{}{text}", " ".repeat(pad)))
+        })
+    }
+
+    /// Pastes `lines` as a block at the primary cursor: each one goes on its
+    /// own line at the cursor's column. Lines too short to reach that column
+    /// are padded with spaces, and lines are added at the end as needed.
+    pub fn insert_block(&mut self, lines: &[&str]) -> bool {
+        if self.read_only || lines.is_empty() {
+            return false;
+        }
+        let head = self.selection().range().start;
+        let first_line = self.rope.char_to_line(head);
+        let col = head - self.rope.line_to_char(first_line);
+        let line_count = self.rope.len_lines();
+        let mut edits = Vec::new();
+        let mut appended = String::new();
+        for (offset, text) in lines.iter().enumerate() {
+            let line = first_line + offset;
+            if line < line_count {
+                let start = self.rope.line_to_char(line);
+                let len = line_content_len(self.rope.line(line));
+                let at = start + col.min(len);
+                let pad = " ".repeat(col.saturating_sub(len));
+                edits.push((at..at, format!("{pad}{text}")));
+            } else {
+                appended.push('\n');
+                appended.push_str(&" ".repeat(col));
+                appended.push_str(text);
+            }
+        }
+        if !appended.is_empty() {
+            let end = self.rope.len_chars();
+            edits.push((end..end, appended));
+        }
+        self.replace_ranges(edits);
+        self.select(head..head);
+        true
+    }
+
+    /// Makes one edit per cursor as a single undo step and leaves a cursor
+    /// after each inserted text. `edit` gets the rope, the cursor's index and
+    /// the cursor, and returns the range to replace and the text to put
+    /// there. Typing and deleting are grouped with the edits just before
+    /// them, so a burst of keystrokes undoes at once.
+    pub fn edit_selections(
+        &mut self,
+        kind: EditKind,
+        mut edit: impl FnMut(&Rope, usize, Selection) -> (Range<usize>, String),
+    ) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let len = self.rope.len_chars();
+        // (range, text, index of the first cursor it came from)
+        let mut planned: Vec<(Range<usize>, String, usize)> = Vec::new();
+        for (index, selection) in self.selections.ranges.iter().enumerate() {
+            let (range, text) = edit(&self.rope, index, *selection);
+            let end = range.end.min(len);
+            let start = range.start.min(end);
+            let text = line_ending::normalize_str(&text).into_owned();
+            match planned.last_mut() {
+                Some((last, last_text, _)) if start < last.end => {
+                    last.start = last.start.min(start);
+                    last.end = last.end.max(end);
+                    last_text.push_str(&text);
+                }
+                _ => planned.push((start..end, text, index)),
+            }
+        }
+        if planned
+            .iter()
+            .all(|(range, text, _)| range.is_empty() && text.is_empty())
+        {
+            return false;
+        }
+
+        let mut cursors = Vec::with_capacity(planned.len());
+        let mut shift = 0isize;
+        let mut primary = 0;
+        for (range, text, index) in &planned {
+            let inserted = text.chars().count() as isize;
+            let cursor = (range.start as isize + shift + inserted) as usize;
+            shift += inserted - range.len() as isize;
+            if *index <= self.selections.primary {
+                primary = cursors.len();
+            }
+            cursors.push(Selection::cursor(cursor));
+        }
+
+        // Started before the edits, so the step keeps the cursors as they were.
+        if !self.continues_group(kind) {
+            self.begin_transaction(kind);
+        }
+        let mut edits = Vec::with_capacity(planned.len());
+        // Back to front, so each range is still where it was planned.
+        for (range, text, _) in planned.into_iter().rev() {
+            if range.is_empty() && text.is_empty() {
+                continue;
+            }
+            let removed = self.rope.slice(range.clone()).to_string();
+            self.apply(range.clone(), &text);
+            edits.push(Edit {
+                start: range.start,
+                removed,
+                inserted: text,
+            });
+        }
+        self.set_selections(cursors, primary);
+        let after = Some(self.selections.clone());
+        let transaction = self
+            .undo_stack
+            .last_mut()
+            .expect("begun or continued above");
+        transaction.edits.extend(edits);
+        transaction.selections_after = after;
+        transaction.last_edit = Instant::now();
+        self.redo_stack.clear();
+        self.update_dirty();
+        true
+    }
+
+    /// True when an edit of `kind` carries on the step at the top of the
+    /// undo stack: same kind, soon enough, and with the cursors still where
+    /// that step left them. A typed line break ends the group.
+    fn continues_group(&self, kind: EditKind) -> bool {
+        let Some(transaction) = self.undo_stack.last() else {
+            return false;
+        };
+        kind != EditKind::Other
+            && kind == transaction.kind
+            && !self.group_closed
+            && transaction.last_edit.elapsed() < GROUP_PAUSE
+            && transaction.selections_after.as_ref() == Some(&self.selections)
+            && self.selections.ranges.iter().all(Selection::is_empty)
+            && !transaction
+                .edits
+                .iter()
+                .any(|edit| edit.inserted.ends_with('\n'))
+    }
+
+    fn begin_transaction(&mut self, kind: EditKind) -> &mut Transaction {
+        let id = self.next_transaction;
+        self.next_transaction += 1;
+        self.group_closed = false;
+        self.undo_stack.push(Transaction {
+            id,
+            kind,
+            edits: Vec::new(),
+            selections_before: self.selections.clone(),
+            selections_after: None,
+            last_edit: Instant::now(),
+        });
+        self.undo_stack.last_mut().expect("just pushed")
+    }
+
+    /// The history rewound or replayed to the point that matches the file,
+    /// without touching the text. `None` when no point in it does, such as
+    /// after the encoding changed or the file was replaced on disk.
+    pub fn saved_history(&self) -> Option<UndoHistory> {
+        let saved = self.saved_transaction?;
+        if self.format_changed {
+            return None;
+        }
+        let top = self
+            .undo_stack
+            .last()
+            .map_or(0, |transaction| transaction.id);
+        let mut undo = self.undo_stack.clone();
+        let mut redo = self.redo_stack.clone();
+        if saved == 0 {
+            redo.extend(undo.drain(..).rev());
+        } else if let Some(index) = undo.iter().position(|transaction| transaction.id == saved) {
+            redo.extend(undo.drain(index + 1..).rev());
+        } else if saved != top {
+            let index = redo
+                .iter()
+                .position(|transaction| transaction.id == saved)?;
+            undo.extend(redo.drain(index..).rev());
+        }
+        Some(UndoHistory { undo, redo })
+    }
+
+    /// Takes over a history kept from an earlier session. The text has to be
+    /// the file's, as it is right after opening.
+    pub fn restore_history(&mut self, history: UndoHistory) {
+        self.undo_stack = history.undo;
+        self.redo_stack = history.redo;
+        self.next_transaction = 1;
+        for transaction in self.undo_stack.iter_mut().chain(&mut self.redo_stack) {
+            transaction.id = self.next_transaction;
+            self.next_transaction += 1;
+        }
+        self.saved_transaction = Some(
+            self.undo_stack
+                .last()
+                .map_or(0, |transaction| transaction.id),
+        );
+        self.group_closed = true;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.read_only && !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.read_only && !self.redo_stack.is_empty()
+    }
+
+    pub fn undo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let Some(mut transaction) = self.undo_stack.pop() else {
+            return false;
+        };
+        transaction.selections_after = Some(self.selections.clone());
+        for edit in transaction.edits.iter().rev() {
+            self.apply(edit.start..edit.end(), &edit.removed);
+        }
+        let before = &transaction.selections_before;
+        self.set_selections(before.ranges.clone(), before.primary);
+        self.view.reveal_cursor = true;
+        self.redo_stack.push(transaction);
+        self.group_closed = true;
+        self.update_dirty();
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        if self.read_only {
+            return false;
+        }
+        let Some(transaction) = self.redo_stack.pop() else {
+            return false;
+        };
+        for edit in &transaction.edits {
+            let removed_end = edit.start + edit.removed.chars().count();
+            self.apply(edit.start..removed_end, &edit.inserted);
+        }
+        match &transaction.selections_after {
+            Some(after) => self.set_selections(after.ranges.clone(), after.primary),
+            None => {
+                if let Some(last) = transaction.edits.last() {
+                    self.set_selection(Selection::cursor(last.end()));
+                }
+            }
+        }
+        self.view.reveal_cursor = true;
+        self.undo_stack.push(transaction);
+        self.group_closed = true;
+        self.update_dirty();
+        true
+    }
+
+    fn apply(&mut self, range: Range<usize>, text: &str) {
+        if let Some(count) = self.word_count.get() {
+            let left = range
+                .start
+                .checked_sub(1)
+                .and_then(|index| self.rope.get_char(index));
+            let right = self.rope.get_char(range.end);
+            let removed = word_delta(left, self.rope.slice(range.clone()).chars(), right);
+            let inserted = word_delta(left, text.chars(), right);
+            let count = count as isize - removed + inserted;
+            self.word_count.set(Some(count.max(0) as usize));
+        }
+        let longest_before = self
+            .longest_line
+            .get()
+            .map(|longest| (longest, self.longest_in(range.clone())));
+
+        if !range.is_empty() {
+            self.rope.remove(range.clone());
+        }
+        if !text.is_empty() {
+            self.rope.insert(range.start, text);
+        }
+
+        if let Some((longest, touched)) = longest_before {
+            let inserted = self.longest_in(range.start..range.start + text.chars().count());
+            // Only a shrinking line that was the longest forces a rescan.
+            let longest = if inserted >= longest {
+                Some(inserted)
+            } else if touched < longest {
+                Some(longest)
+            } else {
+                None
+            };
+            self.longest_line.set(longest);
+        }
+        self.original_breaks = None;
+    }
+
+    /// The text is unchanged from the file when the undo history is back at
+    /// the step that was last saved and the save format was not touched.
+    fn update_dirty(&mut self) {
+        let top = self
+            .undo_stack
+            .last()
+            .map_or(0, |transaction| transaction.id);
+        self.dirty = self.format_changed || self.saved_transaction != Some(top);
+        self.revision += 1;
+    }
+
+    /// Longest of the lines that `range` starts, ends or lies on.
+    fn longest_in(&self, range: Range<usize>) -> usize {
+        let first = self.rope.char_to_line(range.start);
+        let last = self.rope.char_to_line(range.end);
+        (first..=last)
+            .map(|line| line_content_len(self.rope.line(line)))
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn mark_format_changed(&mut self) {
+        self.format_changed = true;
+        self.update_dirty();
     }
 
     pub fn is_dirty(&self) -> bool {
         self.dirty
     }
 
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Changes the encoding used by the next save.
+    pub fn set_encoding(&mut self, encoding: TextEncoding) {
+        if encoding != self.encoding && !self.read_only {
+            self.encoding = encoding;
+            self.mark_format_changed();
+        }
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// True when the file uses more than one line-ending style. Saving writes
+    /// them back as they were until the text is edited; after that every line
+    /// break is saved in the recorded style.
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if self.read_only {
+            return;
+        }
+        if line_ending != self.line_ending || self.mixed_line_endings {
+            self.line_ending = line_ending;
+            self.mixed_line_endings = false;
+            self.original_breaks = None;
+            self.mark_format_changed();
+        }
+    }
+
+    /// Compares the file on disk with what this document last read or wrote.
+    /// A touched file whose contents hash the same counts as unchanged.
+    pub fn check_disk(&mut self) -> DiskChange {
+        let (Some(path), Some(disk)) = (&self.path, self.disk) else {
+            return DiskChange::Unchanged;
+        };
+        let change = match fs::metadata(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => DiskChange::Deleted,
+            Err(_) => DiskChange::Unchanged,
+            Ok(meta) if disk.matches_metadata(&meta) => DiskChange::Unchanged,
+            Ok(meta) => match watcher::hash_file(path) {
+                Ok(hash) if hash == disk.hash => {
+                    self.disk = Some(DiskState::new(&meta, hash));
+                    DiskChange::Unchanged
+                }
+                Ok(_) => DiskChange::Modified,
+                Err(_) => DiskChange::Unchanged,
+            },
+        };
+        self.missing_on_disk = change == DiskChange::Deleted;
+        change
+    }
+
+    /// Takes the file as it is on disk now as the version this document is
+    /// based on, so the next save overwrites it without asking.
+    pub fn accept_disk_version(&mut self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let (Ok(meta), Ok(hash)) = (fs::metadata(path), watcher::hash_file(path)) {
+            self.disk = Some(DiskState::new(&meta, hash));
+            // Nothing in the undo history matches the new file.
+            self.saved_transaction = None;
+            self.update_dirty();
+        }
+    }
+
+    /// The file's current contents, decoded like this document, for diffing.
+    pub fn disk_text(&self) -> io::Result<String> {
+        let Some(path) = self.path.clone() else {
+            return Ok(String::new());
+        };
+        let on_disk = Self::from_path(path, Some(self.encoding))?;
+        Ok(on_disk.rope.to_string())
+    }
+
+    pub fn disk_state(&self) -> Option<DiskState> {
+        self.disk
+    }
+
+    pub fn is_missing_on_disk(&self) -> bool {
+        self.missing_on_disk
+    }
+
+    /// True when some bytes were not valid in the encoding the file was opened
+    /// with and were replaced by U+FFFD.
+    pub fn had_decode_errors(&self) -> bool {
+        self.malformed
+    }
+
     pub fn path(&self) -> Option<&PathBuf> {
         self.path.as_ref()
     }
 
-    pub fn save_to(&mut self, path: PathBuf) -> io::Result<()> {
-        fs::write(&path, &self.text)?;
+    pub fn save_to(&mut self, path: PathBuf, options: &SaveOptions) -> io::Result<()> {
+        let line_ending = self.line_ending;
+        let encoding = self.encoding;
+        let mut breaks = self
+            .original_breaks
+            .as_ref()
+            .map(|breaks| breaks.iter().copied());
+        let mut hash = 0;
+        save::write_atomic(&path, options, |writer| {
+            let mut writer = HashingWriter::new(writer);
+            let chunks = self.rope.chunks().map(|chunk| match breaks.as_mut() {
+                Some(breaks) => line_ending::restore(chunk, breaks),
+                None => line_ending.apply(chunk),
+            });
+            encoding::encode_to(&mut writer, chunks, encoding)?;
+            hash = writer.finish();
+            Ok(())
+        })?;
+        self.mixed_line_endings = self.original_breaks.is_some();
+        self.disk = fs::metadata(&path)
+            .ok()
+            .map(|meta| DiskState::new(&meta, hash));
+        self.missing_on_disk = false;
         self.path = Some(path);
+        self.saved_transaction = Some(
+            self.undo_stack
+                .last()
+                .map_or(0, |transaction| transaction.id),
+        );
+        self.format_changed = false;
+        self.group_closed = true;
         self.dirty = false;
+        self.revision += 1;
         Ok(())
     }
 }
+
+/// The smallest char range of `old` that, replaced by the returned text,
+/// turns it into `new`.
+fn changed_span(old: &Rope, new: &Rope) -> (Range<usize>, String) {
+    let prefix = old
+        .chars()
+        .zip(new.chars())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old
+        .chars_at(old.len_chars())
+        .reversed()
+        .zip(new.chars_at(new.len_chars()).reversed())
+        .take(old.len_chars().min(new.len_chars()) - prefix)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let text = new.slice(prefix..new.len_chars() - suffix).to_string();
+    (prefix..old.len_chars() - suffix, text)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("Untitled")
+        .to_string()
+}
+
+/// Char index of a 1-based line and column, clamped to the text.
+pub fn line_column_index(rope: &Rope, line: usize, column: usize) -> usize {
+    let line = line.saturating_sub(1).min(rope.len_lines() - 1);
+    let len = line_content_len(rope.line(line));
+    rope.line_to_char(line) + column.saturating_sub(1).min(len)
+}
+
+/// Number of chars in `line` without its trailing line break.
+pub fn line_content_len(line: RopeSlice) -> usize {
+    let len = line.len_chars();
+    if len == 0 {
+        return 0;
+    }
+    match line.char(len - 1) {
+        '\n' if len >= 2 && line.char(len - 2) == '\r' => len - 2,
+        '\n' | '\r' | '\u{000B}' | '\u{000C}' | '\u{0085}' | '\u{2028}' | '\u{2029}' => len - 1,
+        _ => len,
+    }
+}
+
+fn count_words(chars: impl Iterator<Item = char>) -> (usize, Option<char>, Option<char>) {
+    let mut count = 0;
+    let mut in_word = false;
+    let mut first = None;
+    let mut last = None;
+    for ch in chars {
+        first.get_or_insert(ch);
+        last = Some(ch);
+        let is_word = !ch.is_whitespace();
+        if is_word && !in_word {
+            count += 1;
+        }
+        in_word = is_word;
+    }
+    (count, first, last)
+}
+
+/// How many words a run of text contributes when it sits between `left` and
+/// `right`, accounting for words it joins or splits at either edge.
+fn word_delta(
+    left: Option<char>,
+    middle: impl Iterator<Item = char>,
+    right: Option<char>,
+) -> isize {
+    let (count, first, last) = count_words(middle);
+    if first.is_none() {
+        return 0;
+    }
+    let joins = |a: Option<char>, b: Option<char>| match (a, b) {
+        (Some(a), Some(b)) if !a.is_whitespace() && !b.is_whitespace() => 1,
+        _ => 0,
+    };
+    count as isize - joins(left, first) - joins(last, right) + joins(left, right)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(text: &str) -> Document {
+        Document::with_rope(Rope::from_str(text), None, "test".to_string())
+    }
+
+    /// Types one char at a time at every cursor, as the editor does.
+    fn type_text(doc: &mut Document, text: &str) {
+        for ch in text.chars() {
+            doc.insert(&ch.to_string(), EditKind::Typing);
+        }
+    }
+
+    fn backspace(doc: &mut Document) {
+        doc.edit_selections(EditKind::Deleting, |_, _, selection| {
+            (selection.head - 1..selection.head, String::new())
+        });
+    }
+
+    fn append(doc: &mut Document, text: &str) {
+        let end = doc.rope().len_chars();
+        doc.replace_range(end..end, text);
+    }
+
+    fn undo_all(doc: &mut Document) -> Vec<String> {
+        let mut texts = Vec::new();
+        while doc.undo() {
+            texts.push(doc.text().into_owned());
+        }
+        texts
+    }
+
+    /// Saves to a file in a folder of its own, removed on drop.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("plainpad-document-{}-{name}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir.join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    #[test]
+    fn edits_go_to_the_rope() {
+        let mut doc = doc("héllo wörld");
+        doc.replace_range(5..5, ", dear");
+        assert_eq!(doc.rope().to_string(), "héllo, dear wörld");
+        doc.replace_range(0..7, "");
+        assert_eq!(doc.text(), "dear wörld");
+        assert!(doc.is_dirty());
+    }
+
+    #[test]
+    fn out_of_range_edits_are_clamped() {
+        let mut doc = doc("abc");
+        doc.replace_range(10..10, "!");
+        assert_eq!(doc.text(), "abc!");
+        doc.replace_range(2..10, "");
+        assert_eq!(doc.text(), "ab");
+        doc.set_selection(Selection { anchor: 1, head: 9 });
+        assert_eq!(doc.selection().range(), 1..2);
+    }
+
+    #[test]
+    fn word_count_follows_edits() {
+        let mut doc = doc("one two three");
+        assert_eq!(doc.word_count(), 3);
+        // Splitting a word, joining two and typing inside one.
+        doc.replace_range(1..1, " ");
+        assert_eq!(doc.word_count(), 4);
+        doc.replace_range(1..2, "");
+        doc.replace_range(3..4, "");
+        assert_eq!(doc.text(), "onetwo three");
+        assert_eq!(doc.word_count(), 2);
+        doc.replace_range(2..2, "x");
+        assert_eq!(doc.word_count(), 2);
+        doc.replace_range(0..13, "  ");
+        assert_eq!(doc.word_count(), 0);
+    }
+
+    #[test]
+    fn longest_line_follows_edits() {
+        let mut doc = doc("short\nthe longest\r\nmid\n");
+        assert_eq!(doc.longest_line(), 11);
+        // Growing another line past it.
+        doc.replace_range(0..0, "much longer ");
+        assert_eq!(doc.longest_line(), 17);
+        // Shrinking the longest line drops back to the next one.
+        doc.replace_range(0..12, "");
+        assert_eq!(doc.longest_line(), 11);
+        // Joining lines makes one long line.
+        doc.replace_range(5..6, "");
+        assert_eq!(doc.longest_line(), 16);
+        assert_eq!(
+            doc.longest_line(),
+            doc.rope().lines().map(line_content_len).max().unwrap()
+        );
+    }
+
+    #[test]
+    fn undo_puts_back_text_caches_and_selection() {
+        let mut doc = doc("one two");
+        assert_eq!(doc.word_count(), 2);
+        assert_eq!(doc.longest_line(), 7);
+        doc.set_selection(Selection::cursor(3));
+        doc.replace_range(3..3, " and a half");
+        assert!(doc.undo());
+        assert_eq!(doc.text(), "one two");
+        assert_eq!(doc.word_count(), 2);
+        assert_eq!(doc.longest_line(), 7);
+        assert_eq!(doc.selection(), Selection::cursor(3));
+    }
+
+    #[test]
+    fn line_content_len_skips_the_line_break() {
+        let rope = Rope::from_str("a\r\nbc\nd\u{2028}");
+        let lens: Vec<_> = rope.lines().map(line_content_len).collect();
+        assert_eq!(lens, [1, 2, 1, 0]);
+    }
+
+    #[test]
+    fn unedited_mixed_endings_save_byte_for_byte() {
+        let file = TempFile::new("mixed.txt");
+        let bytes = b"one\r\ntwo\nthree\rfour\r\n";
+        fs::write(&file.0, bytes).unwrap();
+
+        let mut doc = Document::from_path(file.0.clone(), None).unwrap();
+        assert!(doc.has_mixed_line_endings());
+        doc.save_to(file.0.clone(), &SaveOptions::default())
+            .unwrap();
+        assert_eq!(fs::read(&file.0).unwrap(), bytes);
+        assert!(doc.has_mixed_line_endings());
+    }
+
+    #[test]
+    fn edited_mixed_endings_save_in_the_recorded_style() {
+        let file = TempFile::new("edited.txt");
+        fs::write(&file.0, b"one\r\ntwo\nthree\r\n").unwrap();
+
+        let mut doc = Document::from_path(file.0.clone(), None).unwrap();
+        doc.replace_range(0..0, "zero\n");
+        doc.save_to(file.0.clone(), &SaveOptions::default())
+            .unwrap();
+        assert_eq!(
+            fs::read(&file.0).unwrap(),
+            b"zero\r\none\r\ntwo\r\nthree\r\n"
+        );
+        assert!(!doc.has_mixed_line_endings());
+    }
+
+    #[test]
+    fn converting_mixed_endings_rewrites_every_break() {
+        let file = TempFile::new("converted.txt");
+        fs::write(&file.0, b"one\r\ntwo\n").unwrap();
+
+        let mut doc = Document::from_path(file.0.clone(), None).unwrap();
+        doc.set_line_ending(LineEnding::Lf);
+        assert!(doc.is_dirty());
+        doc.save_to(file.0.clone(), &SaveOptions::default())
+            .unwrap();
+        assert_eq!(fs::read(&file.0).unwrap(), b"one\ntwo\n");
+    }
+
+    #[test]
+    fn changed_span_is_the_differing_middle() {
+        let span = |old: &str, new: &str| changed_span(&Rope::from_str(old), &Rope::from_str(new));
+        assert_eq!(
+            span("one two three", "one 2 three"),
+            (4..7, "2".to_string())
+        );
+        assert_eq!(span("same", "same"), (4..4, String::new()));
+        assert_eq!(span("aaa", "aaaa"), (3..3, "a".to_string()));
+        assert_eq!(span("abc", ""), (0..3, String::new()));
+        assert_eq!(span("née", "nèe"), (1..2, "è".to_string()));
+    }
+
+    #[test]
+    fn reload_replaces_only_what_changed_on_disk() {
+        let file = TempFile::new("reload.txt");
+        fs::write(&file.0, "first line\nsecond line\n").unwrap();
+        let mut doc = Document::from_path(file.0.clone(), None).unwrap();
+        doc.set_selection(Selection::cursor(3));
+        doc.view_mut().top_line = 1.0;
+        doc.replace_range(0..0, "unsaved ");
+
+        fs::write(&file.0, "first line\nchanged line\n").unwrap();
+        doc.reload().unwrap();
+        assert_eq!(doc.text(), "first line\nchanged line\n");
+        assert!(!doc.is_dirty());
+        assert_eq!(doc.view().top_line, 1.0);
+        assert_eq!(doc.word_count(), 4);
+        assert_eq!(doc.check_disk(), DiskChange::Unchanged);
+    }
+
+    #[test]
+    fn typing_is_one_undo_step() {
+        let mut doc = doc("");
+        type_text(&mut doc, "hello");
+        assert_eq!(undo_all(&mut doc), [""]);
+        assert!(doc.redo());
+        assert_eq!(doc.text(), "hello");
+        assert_eq!(doc.selection(), Selection::cursor(5));
+    }
+
+    #[test]
+    fn moving_the_cursor_breaks_the_group() {
+        let mut doc = doc("");
+        type_text(&mut doc, "ab");
+        doc.set_selection(Selection::cursor(0));
+        type_text(&mut doc, "cd");
+        assert_eq!(undo_all(&mut doc), ["ab", ""]);
+    }
+
+    #[test]
+    fn a_line_break_ends_the_group() {
+        let mut doc = doc("");
+        type_text(&mut doc, "one\ntwo");
+        assert_eq!(undo_all(&mut doc), ["one\n", ""]);
+    }
+
+    #[test]
+    fn other_kinds_of_edit_are_steps_of_their_own() {
+        let mut doc = doc("");
+        type_text(&mut doc, "ab");
+        backspace(&mut doc);
+        backspace(&mut doc);
+        doc.replace_range(0..0, "xy");
+        doc.replace_range(2..2, "z");
+        assert_eq!(undo_all(&mut doc), ["xy", "", "ab", ""]);
+    }
+
+    #[test]
+    fn undo_closes_the_group() {
+        let mut doc = doc("");
+        type_text(&mut doc, "ab");
+        doc.undo();
+        doc.redo();
+        type_text(&mut doc, "cd");
+        assert_eq!(undo_all(&mut doc), ["ab", ""]);
+    }
+
+    #[test]
+    fn an_edit_clears_redo() {
+        let mut doc = doc("");
+        type_text(&mut doc, "ab");
+        doc.undo();
+        assert!(doc.can_redo());
+        doc.replace_range(0..0, "c");
+        assert!(!doc.can_redo());
+    }
+
+    #[test]
+    fn several_ranges_undo_as_one_step() {
+        let mut doc = doc("one two one");
+        doc.replace_ranges(vec![(0..3, "1".to_string()), (8..11, "1".to_string())]);
+        assert_eq!(doc.text(), "1 two 1");
+        assert_eq!(undo_all(&mut doc), ["one two one"]);
+        assert!(doc.redo());
+        assert_eq!(doc.text(), "1 two 1");
+    }
+
+    #[test]
+    fn dirty_follows_the_saved_step() {
+        let file = TempFile::new("dirty.txt");
+        let mut doc = doc("");
+        type_text(&mut doc, "ab");
+        doc.save_to(file.0.clone(), &SaveOptions::default())
+            .unwrap();
+        assert!(!doc.is_dirty());
+        doc.replace_range(2..2, "c");
+        assert!(doc.is_dirty());
+        doc.undo();
+        assert!(!doc.is_dirty());
+        doc.undo();
+        assert!(doc.is_dirty());
+        doc.redo();
+        assert!(!doc.is_dirty());
+    }
+
+    #[test]
+    fn a_reload_can_be_undone() {
+        let file = TempFile::new("undo-reload.txt");
+        fs::write(&file.0, "on disk\n").unwrap();
+        let mut doc = Document::from_path(file.0.clone(), None).unwrap();
+        doc.replace_range(0..0, "edited ");
+
+        fs::write(&file.0, "changed on disk\n").unwrap();
+        doc.reload().unwrap();
+        assert!(!doc.is_dirty());
+        // The edits the reload dropped come back as one step, then the
+        // history before them is still there.
+        assert_eq!(undo_all(&mut doc), ["edited on disk\n", "on disk\n"]);
+        assert!(doc.is_dirty());
+        while doc.redo() {}
+        assert_eq!(doc.text(), "changed on disk\n");
+        assert!(!doc.is_dirty());
+    }
+
+    #[test]
+    fn trim_drops_the_steps_furthest_from_the_file() {
+        let mut doc = doc("");
+        for word in ["aaaa", "bbbb", "cccc", "dddd"] {
+            append(&mut doc, word);
+        }
+        doc.undo();
+        let mut history = doc.saved_history().unwrap();
+        // Saved before any step, so every step is a redo step.
+        assert_eq!(history.undo.len(), 0);
+        assert_eq!(history.redo.len(), 4);
+
+        history.trim(9);
+        let kept = history
+            .redo
+            .iter()
+            .map(|step| step.edits[0].inserted.as_str())
+            .collect::<Vec<_>>();
+        // The steps furthest from the file's text go first; the redo stack
+        // holds them at its start.
+        assert_eq!(kept, ["bbbb", "aaaa"]);
+
+        history.trim(0);
+        assert!(history.is_empty());
+    }
+
+    #[test]
+    fn trim_takes_from_the_longer_stack() {
+        let step = |text: &str| Transaction {
+            id: 0,
+            kind: EditKind::Other,
+            edits: vec![Edit {
+                start: 0,
+                removed: String::new(),
+                inserted: text.to_string(),
+            }],
+            selections_before: Selections::default(),
+            selections_after: None,
+            last_edit: Instant::now(),
+        };
+        let mut history = UndoHistory {
+            undo: vec![step("u1"), step("u2"), step("u3")],
+            redo: vec![step("r1")],
+        };
+        history.trim(6);
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(history.undo[0].edits[0].inserted, "u2");
+        assert_eq!(history.redo.len(), 1);
+    }
+
+    #[test]
+    fn saved_history_rewinds_to_the_file_and_restores() {
+        let file = TempFile::new("history.txt");
+        let mut doc = doc("");
+        append(&mut doc, "one ");
+        append(&mut doc, "two ");
+        doc.save_to(file.0.clone(), &SaveOptions::default())
+            .unwrap();
+        append(&mut doc, "three");
+
+        let history = doc.saved_history().unwrap();
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(history.redo.len(), 1);
+        // The tab itself is left alone.
+        assert_eq!(doc.text(), "one two three");
+
+        // Reopened at the saved text, the history carries on from there.
+        let mut reopened = Document::from_path(file.0.clone(), None).unwrap();
+        assert_eq!(reopened.text(), "one two ");
+        reopened.restore_history(history);
+        assert!(!reopened.is_dirty());
+        assert!(reopened.redo());
+        assert_eq!(reopened.text(), "one two three");
+        assert!(reopened.is_dirty());
+        assert_eq!(undo_all(&mut reopened), ["one two ", "one ", ""]);
+        assert!(reopened.is_dirty());
+        while reopened.redo() {}
+        reopened.undo();
+        assert!(!reopened.is_dirty());
+    }
+
+    #[test]
+    fn saved_history_replays_undone_steps_to_the_file() {
+        let file = TempFile::new("replay.txt");
+        let mut doc = doc("");
+        append(&mut doc, "one ");
+        append(&mut doc, "two");
+        doc.save_to(file.0.clone(), &SaveOptions::default())
+            .unwrap();
+        doc.undo();
+        doc.undo();
+
+        let history = doc.saved_history().unwrap();
+        assert_eq!(history.undo.len(), 2);
+        assert!(history.redo.is_empty());
+    }
+
+    #[test]
+    fn no_saved_history_once_the_format_changed() {
+        let mut doc = doc("");
+        append(&mut doc, "text");
+        doc.set_line_ending(if doc.line_ending() == LineEnding::Lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        });
+        assert!(doc.saved_history().is_none());
+    }
+
+    fn cursors(doc: &mut Document, at: &[usize]) {
+        let selections = at.iter().map(|&at| Selection::cursor(at)).collect();
+        doc.set_selections(selections, 0);
+    }
+
+    fn heads(doc: &Document) -> Vec<usize> {
+        doc.selections()
+            .iter()
+            .map(|selection| selection.head)
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_cursors_merge() {
+        let mut doc = doc("abcdefghij");
+        doc.set_selections(
+            vec![
+                Selection::cursor(8),
+                Selection { anchor: 2, head: 5 },
+                Selection { anchor: 3, head: 0 },
+                Selection::cursor(8),
+            ],
+            1,
+        );
+        // A merged selection keeps the direction of the first one in it.
+        assert_eq!(
+            doc.selections(),
+            [Selection { anchor: 5, head: 0 }, Selection::cursor(8)]
+        );
+        // The primary cursor is the merged one it went into.
+        assert_eq!(doc.primary_index(), 0);
+
+        // A cursor at the edge of a selection joins it.
+        doc.add_selection(Selection::cursor(5));
+        assert_eq!(doc.selections().len(), 2);
+        assert_eq!(doc.selection(), Selection { anchor: 5, head: 0 });
+    }
+
+    #[test]
+    fn typing_goes_to_every_cursor() {
+        let mut doc = doc("ab\ncd\nef");
+        cursors(&mut doc, &[0, 3, 6]);
+        type_text(&mut doc, "xy");
+        assert_eq!(doc.text(), "xyab\nxycd\nxyef");
+        assert_eq!(heads(&doc), [2, 7, 12]);
+    }
+
+    #[test]
+    fn deleting_at_every_cursor() {
+        let mut doc = doc("ab\ncd\nef");
+        cursors(&mut doc, &[2, 5, 8]);
+        backspace(&mut doc);
+        assert_eq!(doc.text(), "a\nc\ne");
+        assert_eq!(heads(&doc), [1, 3, 5]);
+
+        // Selections are deleted whole.
+        doc.set_selections(
+            vec![
+                Selection { anchor: 0, head: 1 },
+                Selection { anchor: 2, head: 3 },
+            ],
+            0,
+        );
+        doc.insert("", EditKind::Deleting);
+        assert_eq!(doc.text(), "\n\ne");
+        assert_eq!(heads(&doc), [0, 1]);
+    }
+
+    #[test]
+    fn pasting_at_several_cursors() {
+        let mut doc = doc("a\nb\nc");
+        cursors(&mut doc, &[1, 3, 5]);
+        // One line per cursor: each gets its own.
+        doc.insert("1\n2\n3\n", EditKind::Other);
+        assert_eq!(doc.text(), "a1\nb2\nc3");
+        assert_eq!(heads(&doc), [2, 5, 8]);
+
+        // Any other number of lines goes whole to every cursor.
+        doc.insert("x\ny", EditKind::Other);
+        assert_eq!(doc.text(), "a1x\ny\nb2x\ny\nc3x\ny");
+    }
+
+    #[test]
+    fn one_undo_reverts_every_cursor() {
+        let mut doc = doc("ab\ncd");
+        cursors(&mut doc, &[1, 4]);
+        type_text(&mut doc, "xy");
+        backspace(&mut doc);
+        doc.insert("!", EditKind::Other);
+        assert_eq!(doc.text(), "ax!b\ncx!d");
+        assert_eq!(undo_all(&mut doc), ["axb\ncxd", "axyb\ncxyd", "ab\ncd"]);
+        assert_eq!(heads(&doc), [1, 4]);
+        assert!(doc.redo());
+        assert_eq!(doc.text(), "axyb\ncxyd");
+        assert_eq!(heads(&doc), [3, 8]);
+    }
+
+    fn block(anchor: (usize, usize), head: (usize, usize)) -> Block {
+        Block {
+            anchor_line: anchor.0,
+            anchor_col: anchor.1,
+            head_line: head.0,
+            head_col: head.1,
+        }
+    }
+
+    #[test]
+    fn a_block_is_cut_off_at_short_lines() {
+        let mut doc = doc("abcdef\nab\nabcdef");
+        doc.set_block(block((0, 1), (2, 4)));
+        let ranges = doc
+            .selections()
+            .iter()
+            .map(Selection::range)
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [1..4, 8..9, 11..14]);
+        assert_eq!(doc.primary_index(), 2);
+        assert_eq!(doc.block(), Some(block((0, 1), (2, 4))));
+        // Lines past the end are clamped.
+        doc.set_block(block((1, 0), (9, 1)));
+        assert_eq!(doc.selections().len(), 2);
+    }
+
+    #[test]
+    fn typing_across_a_block_pads_short_lines() {
+        let mut doc = doc("abcdef\na\nabcdef");
+        doc.set_block(block((0, 3), (2, 3)));
+        type_text(&mut doc, "|x");
+        assert_eq!(doc.text(), "abc|xdef\na  |x\nabc|xdef");
+        assert_eq!(doc.block(), None);
+        assert_eq!(undo_all(&mut doc), ["abcdef\na\nabcdef"]);
+    }
+
+    #[test]
+    fn typing_over_a_block_replaces_its_columns() {
+        let mut doc = doc("abcd\nab\nabcd");
+        doc.set_block(block((0, 1), (2, 3)));
+        doc.insert("X", EditKind::Typing);
+        assert_eq!(doc.text(), "aXd\naX\naXd");
+    }
+
+    #[test]
+    fn tabs_count_as_one_column() {
+        let mut doc = doc("\tab\nabc");
+        doc.set_block(block((0, 1), (1, 1)));
+        doc.insert("|", EditKind::Typing);
+        assert_eq!(doc.text(), "\t|ab\na|bc");
+    }
+
+    #[test]
+    fn a_block_paste_goes_down_one_column() {
+        let mut doc = doc("one\ntwo\nthree");
+        doc.set_selection(Selection::cursor(1));
+        assert!(doc.insert_block(&["A", "B", "C"]));
+        assert_eq!(doc.text(), "oAne\ntBwo\ntChree");
+        assert_eq!(doc.selection(), Selection::cursor(1));
+        assert_eq!(undo_all(&mut doc), ["one\ntwo\nthree"]);
+    }
+
+    #[test]
+    fn a_block_paste_pads_ragged_lines_and_adds_more() {
+        let mut doc = doc("abcd\nx");
+        doc.set_selection(Selection::cursor(3));
+        doc.insert_block(&["1", "2", "3"]);
+        assert_eq!(doc.text(), "abc1d\nx  2\n   3");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::document::Document;
+use crate::{document::Document, encoding::TextEncoding, save::SaveOptions};
 use std::{io, path::PathBuf};
 
 pub struct Editor {
@@ -22,6 +22,10 @@ impl Editor {
         &self.documents
     }
 
+    pub fn document_mut(&mut self, index: usize) -> Option<&mut Document> {
+        self.documents.get_mut(index)
+    }
+
     pub fn current_mut(&mut self) -> Option<&mut Document> {
         self.documents.get_mut(self.active)
     }
@@ -48,12 +52,33 @@ impl Editor {
     }
 
     pub fn open_document(&mut self, path: PathBuf) -> io::Result<()> {
-        let doc = Document::from_path(path)?;
+        let doc = Document::from_path(path, None)?;
         self.documents.push(doc);
         self.active = self.documents.len().saturating_sub(1);
         Ok(())
     }
 
+    /// Adds an untitled tab holding `bytes`, such as text piped to standard
+    /// input.
+    pub fn open_bytes(&mut self, bytes: Vec<u8>, encoding: Option<TextEncoding>) -> &mut Document {
+        let name = format!("Untitled {}", self.untitled_counter);
+        self.untitled_counter += 1;
+        self.add_document(Document::from_bytes(bytes, encoding, name));
+        &mut self.documents[self.active]
+    }
+
+    /// Adds an already built tab, taking the place of the blank tab plainpad
+    /// starts with if nothing was typed into it.
+    pub fn add_document(&mut self, doc: Document) {
+        if let [only] = self.documents.as_slice() {
+            if only.path().is_none() && only.is_empty() && !only.is_dirty() {
+                self.documents.clear();
+            }
+        }
+        self.documents.push(doc);
+        self.active = self.documents.len().saturating_sub(1);
+    }
+
     pub fn close_document(&mut self, index: usize) {
         if index >= self.documents.len() {
             return;
@@ -75,9 +100,21 @@ impl Editor {
         }
     }
 
-    pub fn save_document(&mut self, index: usize, path: PathBuf) -> io::Result<()> {
+    pub fn save_document(
+        &mut self,
+        index: usize,
+        path: PathBuf,
+        options: &SaveOptions,
+    ) -> io::Result<()> {
+        if let Some(doc) = self.documents.get_mut(index) {
+            doc.save_to(path, options)?;
+        }
+        Ok(())
+    }
+
+    pub fn reopen_with_encoding(&mut self, index: usize, encoding: TextEncoding) -> io::Result<()> {
         if let Some(doc) = self.documents.get_mut(index) {
-            doc.save_to(path)?;
+            doc.reload_with_encoding(encoding)?;
         }
         Ok(())
     }
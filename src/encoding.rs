@@ -0,0 +1,388 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use std::{
+    borrow::Cow,
+    io::{self, Write},
+};
+
+/// How many leading bytes the UTF-16 and legacy code-page heuristics look at.
+const SNIFF_LEN: usize = 64 * 1024;
+
+/// A character encoding together with whether the file carries a BOM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+impl TextEncoding {
+    pub const UTF8: Self = Self {
+        encoding: UTF_8,
+        bom: false,
+    };
+
+    pub fn label(&self) -> String {
+        let name = match self.encoding {
+            encoding if encoding == UTF_8 => "UTF-8",
+            encoding if encoding == UTF_16LE => "UTF-16 LE",
+            encoding if encoding == UTF_16BE => "UTF-16 BE",
+            encoding => encoding.name(),
+        };
+        if self.bom && self.encoding == UTF_8 {
+            format!("{name} BOM")
+        } else {
+            name.to_string()
+        }
+    }
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self::UTF8
+    }
+}
+
+/// Looks up an encoding by a WHATWG label such as `utf-8`, `latin1`,
+/// `shift_jis` or `utf-16le`.
+pub fn from_label(label: &str) -> Option<TextEncoding> {
+    Encoding::for_label(label.trim().as_bytes()).map(|encoding| TextEncoding {
+        encoding,
+        bom: false,
+    })
+}
+
+/// Encodings offered by the "Reopen with Encoding" and "Save with Encoding"
+/// menus.
+pub fn choices() -> Vec<TextEncoding> {
+    let unicode = [
+        TextEncoding::UTF8,
+        TextEncoding {
+            encoding: UTF_8,
+            bom: true,
+        },
+        TextEncoding {
+            encoding: UTF_16LE,
+            bom: true,
+        },
+        TextEncoding {
+            encoding: UTF_16BE,
+            bom: true,
+        },
+    ];
+    let legacy = [
+        encoding_rs::WINDOWS_1252,
+        encoding_rs::WINDOWS_1250,
+        encoding_rs::WINDOWS_1251,
+        encoding_rs::ISO_8859_2,
+        encoding_rs::ISO_8859_15,
+        encoding_rs::KOI8_R,
+        encoding_rs::SHIFT_JIS,
+        encoding_rs::EUC_JP,
+        encoding_rs::EUC_KR,
+        encoding_rs::GBK,
+        encoding_rs::GB18030,
+        encoding_rs::BIG5,
+    ]
+    .map(|encoding| TextEncoding {
+        encoding,
+        bom: false,
+    });
+    unicode.into_iter().chain(legacy).collect()
+}
+
+/// Picks an encoding for `bytes`: a BOM wins, then a UTF-16 byte pattern,
+/// then valid UTF-8, and finally a legacy code-page guess.
+pub fn detect(bytes: &[u8]) -> TextEncoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return TextEncoding {
+            encoding,
+            bom: true,
+        };
+    }
+
+    if let Some(encoding) = sniff_utf16(bytes) {
+        return TextEncoding {
+            encoding,
+            bom: false,
+        };
+    }
+
+    if std::str::from_utf8(bytes).is_ok() {
+        return TextEncoding::UTF8;
+    }
+
+    let mut detector = EncodingDetector::new();
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN * 16)];
+    detector.feed(sample, sample.len() == bytes.len());
+    TextEncoding {
+        encoding: detector.guess(None, false),
+        bom: false,
+    }
+}
+
+/// Whether `bytes` look like a binary file rather than text: a NUL byte near
+/// the start that is not explained by a BOM or a UTF-16 pattern.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    sample.contains(&0) && Encoding::for_bom(bytes).is_none() && sniff_utf16(bytes).is_none()
+}
+
+/// UTF-16 text without a BOM shows up as a NUL byte in every other position
+/// for Latin-script content.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(SNIFF_LEN)];
+    let pairs = sample.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let (mut even_zeros, mut odd_zeros) = (0, 0);
+    for pair in sample.chunks_exact(2) {
+        if pair[0] == 0 {
+            even_zeros += 1;
+        }
+        if pair[1] == 0 {
+            odd_zeros += 1;
+        }
+    }
+    let mostly = |count: usize| count * 10 >= pairs * 4;
+    let rarely = |count: usize| count * 20 <= pairs;
+    if mostly(odd_zeros) && rarely(even_zeros) {
+        Some(UTF_16LE)
+    } else if mostly(even_zeros) && rarely(odd_zeros) {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// The decoded text and whether any bytes had to be replaced with U+FFFD.
+pub struct Decoded {
+    pub text: String,
+    pub malformed: bool,
+}
+
+pub fn decode(bytes: Vec<u8>, encoding: TextEncoding) -> Decoded {
+    let body = match Encoding::for_bom(&bytes) {
+        Some((bom_encoding, len)) if encoding.bom && bom_encoding == encoding.encoding => {
+            &bytes[len..]
+        }
+        _ => &bytes[..],
+    };
+
+    if encoding.encoding == UTF_8 && body.len() == bytes.len() {
+        return match String::from_utf8(bytes) {
+            Ok(text) => Decoded {
+                text,
+                malformed: false,
+            },
+            Err(err) => Decoded {
+                text: String::from_utf8_lossy(err.as_bytes()).into_owned(),
+                malformed: true,
+            },
+        };
+    }
+
+    let (text, malformed) = encoding.encoding.decode_without_bom_handling(body);
+    Decoded {
+        text: text.into_owned(),
+        malformed,
+    }
+}
+
+/// Writes `chunks` to `writer` in `encoding`, one chunk at a time.
+/// Characters the encoding cannot represent are reported as an error instead
+/// of being replaced.
+pub fn encode_to<'a>(
+    writer: &mut impl Write,
+    chunks: impl Iterator<Item = Cow<'a, str>>,
+    encoding: TextEncoding,
+) -> io::Result<()> {
+    let target = encoding.encoding;
+    if encoding.bom {
+        let bom: &[u8] = match target {
+            target if target == UTF_16LE => b"\xFF\xFE",
+            target if target == UTF_16BE => b"\xFE\xFF",
+            target if target == UTF_8 => b"\xEF\xBB\xBF",
+            _ => b"",
+        };
+        writer.write_all(bom)?;
+    }
+
+    if target == UTF_8 {
+        for chunk in chunks {
+            writer.write_all(chunk.as_bytes())?;
+        }
+        return Ok(());
+    }
+
+    if target == UTF_16LE || target == UTF_16BE {
+        let mut buffer = Vec::new();
+        for chunk in chunks {
+            buffer.clear();
+            for unit in chunk.encode_utf16() {
+                let bytes = if target == UTF_16LE {
+                    unit.to_le_bytes()
+                } else {
+                    unit.to_be_bytes()
+                };
+                buffer.extend_from_slice(&bytes);
+            }
+            writer.write_all(&buffer)?;
+        }
+        return Ok(());
+    }
+
+    let mut encoder = target.new_encoder();
+    let mut buffer = vec![0u8; 16 * 1024];
+    let mut chunks = chunks.peekable();
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let mut remaining = &*chunk;
+        loop {
+            let (result, read, written) =
+                encoder.encode_from_utf8_without_replacement(remaining, &mut buffer, last);
+            writer.write_all(&buffer[..written])?;
+            remaining = &remaining[read..];
+            match result {
+                EncoderResult::InputEmpty => break,
+                EncoderResult::OutputFull => continue,
+                EncoderResult::Unmappable(ch) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "the character {ch:?} (U+{:04X}) cannot be saved as {}",
+                            ch as u32,
+                            target.name()
+                        ),
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    fn utf16be(text: &str) -> Vec<u8> {
+        text.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    fn with_bom(encoding: &'static Encoding) -> TextEncoding {
+        TextEncoding {
+            encoding,
+            bom: true,
+        }
+    }
+
+    fn without_bom(encoding: &'static Encoding) -> TextEncoding {
+        TextEncoding {
+            encoding,
+            bom: false,
+        }
+    }
+
+    #[test]
+    fn a_bom_wins() {
+        assert_eq!(detect(b"\xEF\xBB\xBFhello"), with_bom(UTF_8));
+        assert_eq!(detect(b"\xFF\xFEh\0i\0"), with_bom(UTF_16LE));
+        assert_eq!(detect(b"\xFE\xFF\0h\0i"), with_bom(UTF_16BE));
+        // A BOM alone is still a BOM.
+        assert_eq!(detect(b"\xFF\xFE"), with_bom(UTF_16LE));
+    }
+
+    #[test]
+    fn utf16_without_a_bom_is_sniffed() {
+        assert_eq!(detect(&utf16le("plain text\r\n")), without_bom(UTF_16LE));
+        assert_eq!(detect(&utf16be("plain text\r\n")), without_bom(UTF_16BE));
+        // Too short to tell.
+        assert_eq!(sniff_utf16(&utf16le("a")), None);
+    }
+
+    #[test]
+    fn nul_bytes_without_a_pattern_are_not_utf16() {
+        let bytes = b"\0\0\0\x01\x02\0\0\x7Fabc\0\0\0\0";
+        assert_eq!(sniff_utf16(bytes), None);
+        assert!(looks_binary(bytes));
+        assert!(!looks_binary(&utf16le("text")));
+        assert!(!looks_binary(b"\xFF\xFEt\0"));
+    }
+
+    #[test]
+    fn valid_utf8_is_utf8() {
+        assert_eq!(detect(b""), TextEncoding::UTF8);
+        assert_eq!(detect("naïve café ✓".as_bytes()), TextEncoding::UTF8);
+    }
+
+    #[test]
+    fn legacy_text_falls_back_to_a_guess() {
+        // "Le café crème était brûlé à Noël." in windows-1252.
+        let bytes = b"Le caf\xE9 cr\xE8me \xE9tait br\xFBl\xE9 \xE0 No\xEBl. \
+                      Il \xE9tait tr\xE8s fran\xE7ais et d\xE9j\xE0 froid.";
+        assert_eq!(detect(bytes), without_bom(encoding_rs::WINDOWS_1252));
+
+        // "日本語のテキストです。" in Shift_JIS.
+        let bytes = b"\x93\xfa\x96\x7b\x8c\xea\x82\xcc\x83\x65\x83\x4c\x83\x58\x83\x67\x82\xc5\x82\xb7\x81\x42";
+        assert_eq!(detect(bytes), without_bom(encoding_rs::SHIFT_JIS));
+    }
+
+    #[test]
+    fn decode_drops_a_matching_bom_only() {
+        let decoded = decode(b"\xEF\xBB\xBFhi".to_vec(), with_bom(UTF_8));
+        assert_eq!(decoded.text, "hi");
+        assert!(!decoded.malformed);
+
+        let decoded = decode(b"\xFF\xFEh\0i\0".to_vec(), with_bom(UTF_16LE));
+        assert_eq!(decoded.text, "hi");
+
+        let decoded = decode(utf16be("hi"), without_bom(UTF_16BE));
+        assert_eq!(decoded.text, "hi");
+
+        let decoded = decode(b"ok \xFF".to_vec(), TextEncoding::UTF8);
+        assert_eq!(decoded.text, "ok \u{FFFD}");
+        assert!(decoded.malformed);
+    }
+
+    #[test]
+    fn encoding_round_trips_through_detect() {
+        for encoding in choices() {
+            let mut bytes = Vec::new();
+            let chunks = ["plain ", "text\n"].into_iter().map(Cow::Borrowed);
+            encode_to(&mut bytes, chunks, encoding).unwrap();
+            let decoded = decode(bytes.clone(), encoding);
+            assert_eq!(decoded.text, "plain text\n", "{}", encoding.label());
+            if encoding.bom {
+                assert_eq!(detect(&bytes), encoding);
+            }
+        }
+    }
+
+    #[test]
+    fn unmappable_characters_are_an_error() {
+        let err = encode_to(
+            &mut Vec::new(),
+            ["✓"].into_iter().map(Cow::Borrowed),
+            without_bom(encoding_rs::WINDOWS_1252),
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(
+            from_label(" Latin1 "),
+            Some(without_bom(encoding_rs::WINDOWS_1252))
+        );
+        assert_eq!(from_label("utf-16le"), Some(without_bom(UTF_16LE)));
+        assert_eq!(from_label("nope"), None);
+        assert_eq!(with_bom(UTF_8).label(), "UTF-8 BOM");
+        assert_eq!(with_bom(UTF_16LE).label(), "UTF-16 LE");
+    }
+}
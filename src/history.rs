@@ -0,0 +1,165 @@
+use crate::{
+    document::{Document, UndoHistory},
+    paths,
+    save::{self, SaveOptions},
+    watcher,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Edits beyond this many bytes of text are dropped, oldest first, so one
+/// large Replace All does not leave a huge file behind.
+const MAX_STORED_BYTES: usize = 16 * 1024 * 1024;
+
+fn history_root() -> io::Result<PathBuf> {
+    paths::state_dir()
+        .map(|dir| dir.join("undo"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no local data folder"))
+}
+
+/// The history of one file, stored as `{hash of its canonical path}.json`.
+/// `hash` is the hash of the file's contents the history was taken at.
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    path: PathBuf,
+    hash: u64,
+    history: UndoHistory,
+}
+
+fn history_file(canonical: &Path) -> io::Result<PathBuf> {
+    let key = watcher::hash_bytes(canonical.as_os_str().as_encoded_bytes());
+    Ok(history_root()?.join(format!("{key:016x}.json")))
+}
+
+/// Keeps the undo history of a tab backed by a file, so it can be picked up
+/// again the next time the file is opened.
+pub fn store(doc: &Document) -> io::Result<()> {
+    let (Some(path), Some(disk)) = (doc.path(), doc.disk_state()) else {
+        return Ok(());
+    };
+    if doc.is_missing_on_disk() {
+        return Ok(());
+    }
+    let Some(mut history) = doc.saved_history() else {
+        return Ok(());
+    };
+    let canonical = fs::canonicalize(path)?;
+    let file = history_file(&canonical)?;
+    history.trim(MAX_STORED_BYTES);
+    if history.is_empty() {
+        return match fs::remove_file(&file) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+
+    let stored = Stored {
+        path: canonical,
+        hash: disk.hash,
+        history,
+    };
+    let json = serde_json::to_vec(&stored).map_err(io::Error::other)?;
+    fs::create_dir_all(history_root()?)?;
+    save::write_atomic(&file, &SaveOptions::default(), |writer| {
+        writer.write_all(&json)
+    })
+}
+
+/// Gives a freshly opened tab the history kept for its file. A history taken
+/// at different file contents no longer applies and is deleted.
+pub fn load(doc: &mut Document) {
+    let (Some(path), Some(disk)) = (doc.path(), doc.disk_state()) else {
+        return;
+    };
+    let Ok(canonical) = fs::canonicalize(path) else {
+        return;
+    };
+    let Ok(file) = history_file(&canonical) else {
+        return;
+    };
+    let Ok(bytes) = fs::read(&file) else {
+        return;
+    };
+    match matching_history(&bytes, &canonical, disk.hash) {
+        Some(history) => doc.restore_history(history),
+        None => {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// The history in a stored file, if it was taken for `canonical` at the
+/// contents that hash to `hash`.
+fn matching_history(bytes: &[u8], canonical: &Path, hash: u64) -> Option<UndoHistory> {
+    let stored = serde_json::from_slice::<Stored>(bytes).ok()?;
+    (stored.path == canonical && stored.hash == hash).then_some(stored.history)
+}
+
+/// Deletes every kept history, for when the setting is turned off.
+pub fn clear() {
+    if let Ok(root) = history_root() {
+        let _ = fs::remove_dir_all(root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A history kept for an empty file, with typing "one " left to redo.
+    fn history() -> UndoHistory {
+        let mut doc = Document::new_untitled(1);
+        doc.replace_range(0..0, "one ");
+        doc.saved_history().unwrap()
+    }
+
+    fn stored(path: &str, hash: u64) -> Vec<u8> {
+        serde_json::to_vec(&Stored {
+            path: PathBuf::from(path),
+            hash,
+            history: history(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn history_for_the_same_file_and_contents_applies() {
+        let bytes = stored("/notes/todo.txt", 42);
+        let history = matching_history(&bytes, Path::new("/notes/todo.txt"), 42).unwrap();
+        let mut doc = Document::new_untitled(1);
+        doc.restore_history(history);
+        assert!(doc.redo());
+        assert_eq!(doc.rope().to_string(), "one ");
+    }
+
+    #[test]
+    fn changed_contents_or_another_path_do_not_apply() {
+        let bytes = stored("/notes/todo.txt", 42);
+        assert!(matching_history(&bytes, Path::new("/notes/todo.txt"), 43).is_none());
+        assert!(matching_history(&bytes, Path::new("/notes/done.txt"), 42).is_none());
+    }
+
+    #[test]
+    fn a_damaged_file_does_not_apply() {
+        let bytes = stored("/notes/todo.txt", 42);
+        let cut = &bytes[..bytes.len() / 2];
+        assert!(matching_history(cut, Path::new("/notes/todo.txt"), 42).is_none());
+        assert!(matching_history(b"", Path::new("/notes/todo.txt"), 42).is_none());
+    }
+
+    #[test]
+    fn each_path_has_its_own_file() {
+        let (Ok(first), Ok(second)) = (
+            history_file(Path::new("/notes/todo.txt")),
+            history_file(Path::new("/notes/done.txt")),
+        ) else {
+            return;
+        };
+        assert_ne!(first, second);
+        assert_eq!(history_file(Path::new("/notes/todo.txt")).unwrap(), first);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::{
+    cli::{Launch, Target},
+    encoding::{self, TextEncoding},
+    paths,
+};
+use eframe::egui;
+use interprocess::local_socket::{
+    prelude::*, GenericFilePath, GenericNamespaced, Listener, ListenerOptions, Name, Stream,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+};
+
+/// Files a second launch hands to the running instance, sent as one line of
+/// JSON. The running instance answers `ok` once it has queued them. For a
+/// `--wait` launch it later sends `saved` or `closed` once the tabs it opened
+/// for the request are all closed.
+#[derive(Serialize, Deserialize)]
+pub struct OpenRequest {
+    pub targets: Vec<Target>,
+    pub read_only: bool,
+    encoding: Option<String>,
+    #[serde(default)]
+    wait: bool,
+}
+
+impl OpenRequest {
+    fn new(launch: &Launch) -> Self {
+        Self {
+            targets: launch.targets.clone(),
+            read_only: launch.read_only,
+            encoding: launch
+                .encoding
+                .map(|encoding| encoding.encoding.name().to_string()),
+            wait: launch.wait,
+        }
+    }
+
+    pub fn encoding(&self) -> Option<TextEncoding> {
+        self.encoding.as_deref().and_then(encoding::from_label)
+    }
+}
+
+/// A request as received by the running window. `done` is there for
+/// `--wait` launches and takes whether every tab was saved.
+pub struct Handoff {
+    pub request: OpenRequest,
+    pub done: Option<Sender<bool>>,
+}
+
+/// A named pipe on Windows; elsewhere a socket file in the user's runtime
+/// directory, which only the user can reach.
+fn socket_name() -> io::Result<Name<'static>> {
+    if cfg!(windows) {
+        let user = std::env::var("USERNAME").unwrap_or_default();
+        format!("plainpad-{user}")
+            .to_ns_name::<GenericNamespaced>()
+            .map(Name::into_owned)
+    } else {
+        let dir = dirs::runtime_dir()
+            .or_else(paths::state_dir)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no runtime folder"))?;
+        std::fs::create_dir_all(&dir)?;
+        dir.join("plainpad.sock")
+            .to_fs_name::<GenericFilePath>()
+            .map(Name::into_owned)
+    }
+}
+
+/// Passes the launch to an already running instance. An error means there
+/// is none, or it did not answer, and this process should open the window.
+/// Otherwise this returns whether the files were saved, after waiting for
+/// their tabs to close if the launch asked to; a window that goes away
+/// without answering counts as not saved.
+pub fn hand_off(launch: &Launch) -> io::Result<bool> {
+    let stream = Stream::connect(socket_name()?)?;
+    let mut stream = BufReader::new(stream);
+    let mut line = serde_json::to_vec(&OpenRequest::new(launch)).map_err(io::Error::other)?;
+    line.push(b'\n');
+    stream.get_mut().write_all(&line)?;
+
+    let mut reply = String::new();
+    stream.read_line(&mut reply)?;
+    if reply.trim() != "ok" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the running instance did not accept the files",
+        ));
+    }
+    if !launch.wait {
+        return Ok(true);
+    }
+
+    reply.clear();
+    let answered = stream.read_line(&mut reply).is_ok();
+    Ok(answered && reply.trim() == "saved")
+}
+
+/// Claims the socket name so later launches hand their files to this
+/// process. A socket left behind by a crashed instance is replaced.
+pub fn listen() -> Option<Listener> {
+    ListenerOptions::new()
+        .name(socket_name().ok()?)
+        .try_overwrite(true)
+        .create_sync()
+        .ok()
+}
+
+/// Accepts hand-offs on a background thread. Each request wakes the UI so it
+/// is opened right away.
+pub fn serve(listener: Listener, ctx: &egui::Context) -> Receiver<Handoff> {
+    let (sender, requests) = mpsc::channel();
+    let ctx = ctx.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let sender = sender.clone();
+            let ctx = ctx.clone();
+            thread::spawn(move || {
+                let _ = receive(stream, &sender, &ctx);
+            });
+        }
+    });
+    requests
+}
+
+fn receive(stream: Stream, sender: &Sender<Handoff>, ctx: &egui::Context) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    let mut line = String::new();
+    stream.read_line(&mut line)?;
+    let request: OpenRequest = serde_json::from_str(&line).map_err(io::Error::other)?;
+    let (done, finished) = if request.wait {
+        let (done, finished) = mpsc::channel();
+        (Some(done), Some(finished))
+    } else {
+        (None, None)
+    };
+    if sender.send(Handoff { request, done }).is_err() {
+        return Err(io::Error::new(
+            io::ErrorKind::BrokenPipe,
+            "the window closed",
+        ));
+    }
+    ctx.request_repaint();
+    stream.get_mut().write_all(b"ok\n")?;
+
+    if let Some(finished) = finished {
+        // The sender is dropped without an answer if the window goes away.
+        let saved = finished.recv().unwrap_or(false);
+        let answer: &[u8] = if saved { b"saved\n" } else { b"closed\n" };
+        stream.get_mut().write_all(answer)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn round_trip(request: &OpenRequest) -> OpenRequest {
+        let line = serde_json::to_string(request).unwrap();
+        assert!(!line.contains('\n'));
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[test]
+    fn a_hand_off_arrives_as_sent() {
+        let launch = Launch {
+            targets: vec![
+                Target::File {
+                    path: PathBuf::from("/notes/todo.txt"),
+                    position: Some((12, 3)),
+                },
+                Target::Stdin(b"piped\ntext".to_vec()),
+            ],
+            new_window: false,
+            new_instance: false,
+            read_only: true,
+            encoding: encoding::from_label("utf-16le"),
+            wait: true,
+        };
+        let request = round_trip(&OpenRequest::new(&launch));
+        assert!(request.read_only && request.wait);
+        assert_eq!(request.encoding(), launch.encoding);
+        match request.targets.as_slice() {
+            [Target::File { path, position }, Target::Stdin(bytes)] => {
+                assert_eq!(path, &PathBuf::from("/notes/todo.txt"));
+                assert_eq!(*position, Some((12, 3)));
+                assert_eq!(bytes, b"piped\ntext");
+            }
+            _ => panic!("targets changed on the way"),
+        }
+    }
+
+    #[test]
+    fn the_encoding_and_wait_are_optional() {
+        let request: OpenRequest =
+            serde_json::from_str(r#"{"targets": [], "read_only": false}"#).unwrap();
+        assert!(request.targets.is_empty());
+        assert_eq!(request.encoding(), None);
+        assert!(!request.wait);
+
+        let request: OpenRequest =
+            serde_json::from_str(r#"{"targets": [], "read_only": false, "encoding": "nope"}"#)
+                .unwrap();
+        assert_eq!(request.encoding(), None);
+    }
+}
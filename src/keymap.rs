@@ -0,0 +1,546 @@
+use crate::{
+    paths,
+    save::{self, SaveOptions},
+    shortcuts::{KeyChord, KeySequence, ShortcutCommand},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+const KEYMAP_FILE: &str = "keymap.toml";
+/// Bumped when the file format changes meaning, so older files can be
+/// migrated.
+pub const KEYMAP_VERSION: u32 = 1;
+
+/// The bindings a keymap starts from before the user's own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Default,
+    /// Emacs-style two-key bindings such as Ctrl+X Ctrl+S.
+    Emacs,
+    /// macOS habits such as Command+Q and Command+Shift+Z.
+    Mac,
+}
+
+impl Preset {
+    pub const ALL: [Self; 3] = [Self::Default, Self::Emacs, Self::Mac];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Default => "Default",
+            Self::Emacs => "Emacs",
+            Self::Mac => "Mac",
+        }
+    }
+}
+
+type Bindings = &'static [(ShortcutCommand, &'static [&'static str])];
+
+const DEFAULT_BINDINGS: Bindings = &[
+    (ShortcutCommand::NewTab, &["Ctrl+N"]),
+    (ShortcutCommand::Open, &["Ctrl+O"]),
+    (ShortcutCommand::Print, &["Ctrl+P"]),
+    (ShortcutCommand::Save, &["Ctrl+S"]),
+    (ShortcutCommand::SaveAs, &["Ctrl+Shift+S"]),
+    (ShortcutCommand::CloseTab, &["Ctrl+W"]),
+    (ShortcutCommand::Quit, &["Ctrl+Shift+W"]),
+    (ShortcutCommand::Undo, &["Ctrl+Z"]),
+    (ShortcutCommand::Redo, &["Ctrl+Y", "Ctrl+Shift+Z"]),
+    (ShortcutCommand::Cut, &["Ctrl+X"]),
+    (ShortcutCommand::Copy, &["Ctrl+C"]),
+    (ShortcutCommand::Paste, &["Ctrl+V"]),
+    (ShortcutCommand::SelectAll, &["Ctrl+A"]),
+    (ShortcutCommand::AddNextOccurrence, &["Ctrl+D"]),
+    (ShortcutCommand::SelectAllOccurrences, &["Ctrl+Shift+L"]),
+    (ShortcutCommand::Find, &["Ctrl+F"]),
+    (ShortcutCommand::FindNext, &["F3"]),
+    (ShortcutCommand::FindPrevious, &["Shift+F3"]),
+    (ShortcutCommand::FindInFiles, &["Ctrl+Shift+F"]),
+    (ShortcutCommand::Replace, &["Ctrl+H"]),
+    (ShortcutCommand::GoTo, &["Ctrl+G"]),
+    (ShortcutCommand::Preferences, &["Ctrl+Comma"]),
+    (ShortcutCommand::KeyboardShortcuts, &["Ctrl+K Ctrl+S"]),
+    (ShortcutCommand::CommandPalette, &["Ctrl+Shift+P"]),
+    (ShortcutCommand::NextTab, &["Ctrl+Tab"]),
+    (ShortcutCommand::PreviousTab, &["Ctrl+Shift+Tab"]),
+    (ShortcutCommand::SelectTab(0), &["Ctrl+1"]),
+    (ShortcutCommand::SelectTab(1), &["Ctrl+2"]),
+    (ShortcutCommand::SelectTab(2), &["Ctrl+3"]),
+    (ShortcutCommand::SelectTab(3), &["Ctrl+4"]),
+    (ShortcutCommand::SelectTab(4), &["Ctrl+5"]),
+    (ShortcutCommand::SelectTab(5), &["Ctrl+6"]),
+    (ShortcutCommand::SelectTab(6), &["Ctrl+7"]),
+    (ShortcutCommand::SelectTab(7), &["Ctrl+8"]),
+    (ShortcutCommand::SelectLastTab, &["Ctrl+9"]),
+];
+
+/// Replaces the default bindings of the commands it lists.
+const EMACS_BINDINGS: Bindings = &[
+    (ShortcutCommand::Open, &["Ctrl+X Ctrl+F"]),
+    (ShortcutCommand::Save, &["Ctrl+X Ctrl+S"]),
+    (ShortcutCommand::SaveAs, &["Ctrl+X Ctrl+W"]),
+    (ShortcutCommand::SaveAll, &["Ctrl+X S"]),
+    (ShortcutCommand::CloseTab, &["Ctrl+X K"]),
+    (ShortcutCommand::Quit, &["Ctrl+X Ctrl+C"]),
+    (ShortcutCommand::Undo, &["Ctrl+Slash", "Ctrl+X U"]),
+    (ShortcutCommand::Redo, &["Ctrl+Shift+Questionmark"]),
+    (ShortcutCommand::Cut, &["Ctrl+W"]),
+    (ShortcutCommand::Copy, &["Alt+W"]),
+    (ShortcutCommand::Paste, &["Ctrl+Y"]),
+    (ShortcutCommand::SelectAll, &["Ctrl+X H"]),
+    (ShortcutCommand::Find, &["Ctrl+S"]),
+    (ShortcutCommand::Replace, &["Alt+R"]),
+    (ShortcutCommand::GoTo, &["Alt+G G", "Alt+G Alt+G"]),
+    (ShortcutCommand::CommandPalette, &["Alt+X"]),
+    (ShortcutCommand::NextTab, &["Ctrl+X Right"]),
+    (ShortcutCommand::PreviousTab, &["Ctrl+X Left"]),
+];
+
+/// Replaces the default bindings of the commands it lists. Ctrl is Command
+/// on macOS, so these also help Mac habits elsewhere.
+const MAC_BINDINGS: Bindings = &[
+    (ShortcutCommand::CloseAll, &["Ctrl+Alt+W"]),
+    (ShortcutCommand::Quit, &["Ctrl+Q"]),
+    (ShortcutCommand::Redo, &["Ctrl+Shift+Z"]),
+    (ShortcutCommand::Replace, &["Ctrl+Alt+F"]),
+    (ShortcutCommand::GoTo, &["Ctrl+L"]),
+    (ShortcutCommand::FindNext, &["F3", "Ctrl+G"]),
+    (ShortcutCommand::FindPrevious, &["Shift+F3", "Ctrl+Shift+G"]),
+    (ShortcutCommand::NextTab, &["Ctrl+Alt+Right", "MacCtrl+Tab"]),
+    (
+        ShortcutCommand::PreviousTab,
+        &["Ctrl+Alt+Left", "MacCtrl+Shift+Tab"],
+    ),
+];
+
+/// What `keymap.toml` holds: a preset and the commands whose keys differ
+/// from it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct KeymapFile {
+    version: u32,
+    preset: Preset,
+    /// Command ids and their keys, such as `"file.save" = ["Ctrl+S"]`. An
+    /// empty list unbinds the command.
+    bindings: BTreeMap<String, Vec<String>>,
+}
+
+/// Two commands that the same keys cannot both reach.
+pub struct Conflict {
+    pub commands: [ShortcutCommand; 2],
+    pub sequences: [KeySequence; 2],
+}
+
+impl Conflict {
+    pub fn describe(&self) -> String {
+        let [first, second] = &self.sequences;
+        let [first_command, second_command] = self.commands;
+        if first == second {
+            format!(
+                "{} runs {} and {}.",
+                first.label(),
+                first_command.label(),
+                second_command.label()
+            )
+        } else {
+            format!(
+                "{} ({}) never runs because {} ({}) starts with it.",
+                first.label(),
+                first_command.label(),
+                second.label(),
+                second_command.label()
+            )
+        }
+    }
+}
+
+/// Which keys run which commands: a preset plus the user's changes, kept
+/// in `keymap.toml` in the platform config folder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    preset: Preset,
+    overrides: BTreeMap<ShortcutCommand, Vec<KeySequence>>,
+    /// The preset with the overrides applied, in `ShortcutCommand::ALL`
+    /// order.
+    bindings: Vec<(ShortcutCommand, Vec<KeySequence>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(Preset::Default, BTreeMap::new())
+    }
+}
+
+impl Keymap {
+    fn new(preset: Preset, overrides: BTreeMap<ShortcutCommand, Vec<KeySequence>>) -> Self {
+        let mut keymap = Self {
+            preset,
+            overrides,
+            bindings: Vec::new(),
+        };
+        keymap.rebuild();
+        keymap
+    }
+
+    fn rebuild(&mut self) {
+        self.bindings = ShortcutCommand::ALL
+            .into_iter()
+            .map(|command| {
+                let sequences = self
+                    .overrides
+                    .get(&command)
+                    .cloned()
+                    .unwrap_or_else(|| preset_bindings(self.preset, command));
+                (command, sequences)
+            })
+            .collect();
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        paths::config_dir().map(|dir| dir.join(KEYMAP_FILE))
+    }
+
+    /// Reads the keymap file. A missing file gives the default preset. A file
+    /// that cannot be read gives the default preset, and bindings that name
+    /// an unknown command or keys are skipped; each problem is described in
+    /// the returned list.
+    pub fn load() -> (Self, Vec<String>) {
+        let Some(path) = Self::path() else {
+            return (Self::default(), Vec::new());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return (Self::default(), Vec::new())
+            }
+            Err(err) => {
+                return (
+                    Self::default(),
+                    vec![format!("Failed to read {}: {err}", path.display())],
+                )
+            }
+        };
+        let file = match toml::from_str::<KeymapFile>(&text) {
+            Ok(file) => file,
+            Err(err) => {
+                return (
+                    Self::default(),
+                    vec![format!(
+                        "{} is not valid, so the default shortcuts are used:\n{err}",
+                        path.display()
+                    )],
+                )
+            }
+        };
+
+        let mut problems = Vec::new();
+        if file.version > KEYMAP_VERSION {
+            problems.push(format!(
+                "`version` is {}, but this plainpad understands up to {KEYMAP_VERSION}; bindings it does not know are ignored.",
+                file.version
+            ));
+        }
+        let mut overrides = BTreeMap::new();
+        for (id, keys) in file.bindings {
+            let Some(command) = ShortcutCommand::from_id(&id) else {
+                problems.push(format!("\"{id}\" is not a command; it is ignored."));
+                continue;
+            };
+            let mut sequences = Vec::new();
+            for text in keys {
+                match KeySequence::parse(&text) {
+                    Ok(sequence) => sequences.push(sequence),
+                    Err(err) => problems.push(format!("\"{id}\": {err}; \"{text}\" is ignored.")),
+                }
+            }
+            overrides.insert(command, sequences);
+        }
+        (Self::new(file.preset, overrides), problems)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config folder"))?;
+        let file = KeymapFile {
+            version: KEYMAP_VERSION,
+            preset: self.preset,
+            bindings: self
+                .overrides
+                .iter()
+                .map(|(command, sequences)| {
+                    let keys = sequences.iter().map(ToString::to_string).collect();
+                    (command.id().to_string(), keys)
+                })
+                .collect(),
+        };
+        let body = toml::to_string_pretty(&file).map_err(io::Error::other)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        save::write_atomic(&path, &SaveOptions::default(), |writer| {
+            writer.write_all(
+                b"# plainpad keyboard shortcuts. Edits are picked up while plainpad runs.\n\
+                  # `preset` is \"default\", \"emacs\" or \"mac\". Each entry under [bindings]\n\
+                  # replaces the preset's keys for one command, for example\n\
+                  # \"file.save\" = [\"Ctrl+S\", \"Ctrl+X Ctrl+S\"]; an empty list unbinds it.\n\
+                  # Ctrl is the Command key on macOS, and MacCtrl is its Control key.\n\n",
+            )?;
+            writer.write_all(body.as_bytes())
+        })
+    }
+
+    pub fn preset(&self) -> Preset {
+        self.preset
+    }
+
+    /// Switches to another preset. Commands the user rebound keep their keys.
+    pub fn set_preset(&mut self, preset: Preset) {
+        self.preset = preset;
+        self.rebuild();
+    }
+
+    pub fn bindings(&self, command: ShortcutCommand) -> &[KeySequence] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == command)
+            .map(|(_, sequences)| sequences.as_slice())
+            .unwrap_or_default()
+    }
+
+    pub fn set_bindings(&mut self, command: ShortcutCommand, sequences: Vec<KeySequence>) {
+        if sequences == preset_bindings(self.preset, command) {
+            self.overrides.remove(&command);
+        } else {
+            self.overrides.insert(command, sequences);
+        }
+        self.rebuild();
+    }
+
+    /// Whether the command's keys differ from the preset.
+    pub fn is_changed(&self, command: ShortcutCommand) -> bool {
+        self.overrides.contains_key(&command)
+    }
+
+    pub fn reset(&mut self, command: ShortcutCommand) {
+        self.overrides.remove(&command);
+        self.rebuild();
+    }
+
+    /// Drops every change, going back to the preset's keys.
+    pub fn reset_all(&mut self) {
+        self.overrides.clear();
+        self.rebuild();
+    }
+
+    /// The first binding of `command`, as shown next to it in menus.
+    pub fn accelerator(&self, command: ShortcutCommand) -> Option<String> {
+        self.bindings(command).first().map(KeySequence::label)
+    }
+
+    /// The command `sequence` runs. When several share it, the first one
+    /// listed wins.
+    pub fn command_for(&self, sequence: &KeySequence) -> Option<ShortcutCommand> {
+        self.bindings
+            .iter()
+            .find(|(_, sequences)| sequences.contains(sequence))
+            .map(|(command, _)| *command)
+    }
+
+    /// Whether `chord` starts a two-key binding.
+    pub fn is_prefix(&self, chord: KeyChord) -> bool {
+        self.bindings
+            .iter()
+            .flat_map(|(_, sequences)| sequences)
+            .any(|sequence| !sequence.is_single() && sequence.first() == chord)
+    }
+
+    /// Keys bound to more than one command, and single chords that can never
+    /// run because a two-key binding starts with them.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let all = self
+            .bindings
+            .iter()
+            .flat_map(|(command, sequences)| {
+                sequences.iter().map(move |sequence| (*command, sequence))
+            })
+            .collect::<Vec<_>>();
+        let mut conflicts = Vec::new();
+        for (index, (command, sequence)) in all.iter().enumerate() {
+            for (other_command, other) in &all[index + 1..] {
+                if command == other_command {
+                    continue;
+                }
+                let (single, double) = match (sequence.is_single(), other.is_single()) {
+                    (true, false) => ((command, sequence), (other_command, other)),
+                    (false, true) => ((other_command, other), (command, sequence)),
+                    _ if sequence == other => ((command, sequence), (other_command, other)),
+                    _ => continue,
+                };
+                if single.1 != double.1 && single.1.first() != double.1.first() {
+                    continue;
+                }
+                conflicts.push(Conflict {
+                    commands: [*single.0, *double.0],
+                    sequences: [(*single.1).clone(), (*double.1).clone()],
+                });
+            }
+        }
+        conflicts
+    }
+}
+
+fn preset_bindings(preset: Preset, command: ShortcutCommand) -> Vec<KeySequence> {
+    let overrides = match preset {
+        Preset::Default => &[],
+        Preset::Emacs => EMACS_BINDINGS,
+        Preset::Mac => MAC_BINDINGS,
+    };
+    let keys = [overrides, DEFAULT_BINDINGS]
+        .into_iter()
+        .find_map(|table| table.iter().find(|(bound, _)| *bound == command))
+        .map(|(_, keys)| *keys)
+        .unwrap_or_default();
+    keys.iter()
+        .filter_map(|text| KeySequence::parse(text).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(text: &str) -> KeySequence {
+        KeySequence::parse(text).unwrap()
+    }
+
+    fn keymap(preset: Preset, changes: &[(ShortcutCommand, &[&str])]) -> Keymap {
+        let mut keymap = Keymap::default();
+        keymap.set_preset(preset);
+        for (command, texts) in changes {
+            keymap.set_bindings(*command, texts.iter().map(|text| keys(text)).collect());
+        }
+        keymap
+    }
+
+    /// The commands and keys of each conflict, for comparing.
+    fn conflicts(keymap: &Keymap) -> Vec<([ShortcutCommand; 2], [String; 2])> {
+        keymap
+            .conflicts()
+            .into_iter()
+            .map(|conflict| {
+                let [first, second] = conflict.sequences;
+                (conflict.commands, [first.to_string(), second.to_string()])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_preset_entry_parses() {
+        // `preset_bindings` skips keys that fail to parse, so a typo in a
+        // table would quietly leave a command unbound.
+        for table in [DEFAULT_BINDINGS, EMACS_BINDINGS, MAC_BINDINGS] {
+            for (command, texts) in table {
+                for text in *texts {
+                    let sequence = KeySequence::parse(text)
+                        .unwrap_or_else(|err| panic!("{command:?} \"{text}\": {err}"));
+                    assert_eq!(KeySequence::parse(&sequence.to_string()), Ok(sequence));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn presets_have_no_conflicts() {
+        for preset in Preset::ALL {
+            assert_eq!(conflicts(&keymap(preset, &[])), [], "{preset:?}");
+        }
+    }
+
+    #[test]
+    fn presets_fall_back_to_the_default_keys() {
+        let emacs = keymap(Preset::Emacs, &[]);
+        assert_eq!(
+            emacs.bindings(ShortcutCommand::Save),
+            [keys("Ctrl+X Ctrl+S")]
+        );
+        assert_eq!(
+            emacs.bindings(ShortcutCommand::AddNextOccurrence),
+            [keys("Ctrl+D")]
+        );
+        assert!(emacs.is_prefix(keys("Ctrl+X").first()));
+        assert!(!keymap(Preset::Default, &[]).is_prefix(keys("Ctrl+X").first()));
+    }
+
+    #[test]
+    fn same_keys_on_two_commands_conflict() {
+        let keymap = keymap(Preset::Default, &[(ShortcutCommand::Open, &["Ctrl+S"])]);
+        assert_eq!(
+            conflicts(&keymap),
+            [(
+                [ShortcutCommand::Open, ShortcutCommand::Save],
+                ["Ctrl+S".to_string(), "Ctrl+S".to_string()]
+            )]
+        );
+        // The command listed first wins.
+        assert_eq!(
+            keymap.command_for(&keys("Ctrl+S")),
+            Some(ShortcutCommand::Open)
+        );
+    }
+
+    #[test]
+    fn a_chord_that_starts_a_two_key_binding_conflicts() {
+        // Binding Cut back to Ctrl+X in Emacs shadows Ctrl+X Ctrl+S and the
+        // other Ctrl+X bindings.
+        let keymap = keymap(Preset::Emacs, &[(ShortcutCommand::Cut, &["Ctrl+X"])]);
+        let found = conflicts(&keymap);
+        assert!(found.contains(&(
+            [ShortcutCommand::Cut, ShortcutCommand::Save],
+            ["Ctrl+X".to_string(), "Ctrl+X Ctrl+S".to_string()]
+        )));
+        assert!(found
+            .iter()
+            .all(|(commands, _)| commands[0] == ShortcutCommand::Cut));
+        let save = keymap
+            .conflicts()
+            .into_iter()
+            .find(|conflict| conflict.commands[1] == ShortcutCommand::Save)
+            .unwrap();
+        assert!(save.describe().contains("never runs"));
+    }
+
+    #[test]
+    fn two_key_bindings_sharing_a_first_chord_do_not_conflict() {
+        let keymap = keymap(
+            Preset::Default,
+            &[
+                (ShortcutCommand::Open, &["Ctrl+K Ctrl+O"]),
+                (ShortcutCommand::Save, &["Ctrl+K S"]),
+            ],
+        );
+        assert_eq!(conflicts(&keymap), []);
+    }
+
+    #[test]
+    fn setting_the_preset_keys_is_not_a_change() {
+        let mut keymap = keymap(Preset::Emacs, &[(ShortcutCommand::Save, &["Ctrl+S"])]);
+        assert!(keymap.is_changed(ShortcutCommand::Save));
+        keymap.set_bindings(ShortcutCommand::Save, vec![keys("Ctrl+X Ctrl+S")]);
+        assert!(!keymap.is_changed(ShortcutCommand::Save));
+    }
+
+    #[test]
+    fn keys_must_start_with_a_modifier_and_have_at_most_two_presses() {
+        assert!(KeySequence::parse("S").is_err());
+        assert!(KeySequence::parse("").is_err());
+        assert!(KeySequence::parse("Ctrl+X Ctrl+K Ctrl+S").is_err());
+        assert!(KeySequence::parse("Ctrl+Nope").is_err());
+        assert!(KeySequence::parse("F5").is_ok());
+    }
+}
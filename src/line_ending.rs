@@ -0,0 +1,240 @@
+use ropey::{Rope, RopeBuilder};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// Line-ending style a document is saved with. Inside the rope every line
+/// break is a plain `\n`; the style is applied again on save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    Cr,
+}
+
+impl LineEnding {
+    pub const ALL: [Self; 3] = [Self::Crlf, Self::Lf, Self::Cr];
+
+    pub fn platform_default() -> Self {
+        if cfg!(windows) {
+            Self::Crlf
+        } else {
+            Self::Lf
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Lf => "LF",
+            Self::Crlf => "CRLF",
+            Self::Cr => "CR",
+        }
+    }
+
+    pub fn menu_label(&self) -> &'static str {
+        match self {
+            Self::Lf => "Unix (LF)",
+            Self::Crlf => "Windows (CRLF)",
+            Self::Cr => "Classic Mac (CR)",
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    /// Rewrites the `\n` breaks in a chunk of rope text to this style.
+    pub fn apply<'a>(&self, chunk: &'a str) -> Cow<'a, str> {
+        if *self == Self::Lf || !chunk.contains('\n') {
+            Cow::Borrowed(chunk)
+        } else {
+            Cow::Owned(chunk.replace('\n', self.as_str()))
+        }
+    }
+}
+
+/// Rewrites the `\n` breaks in a chunk of rope text to the styles taken in
+/// order from `breaks`, which holds one entry per break in the document.
+pub fn restore<'a>(chunk: &'a str, breaks: &mut impl Iterator<Item = LineEnding>) -> Cow<'a, str> {
+    if !chunk.contains('\n') {
+        return Cow::Borrowed(chunk);
+    }
+    let mut restored = String::with_capacity(chunk.len() + chunk.len() / 16);
+    for piece in chunk.split_inclusive('\n') {
+        match piece.strip_suffix('\n') {
+            Some(line) => {
+                restored.push_str(line);
+                let style = breaks.next().unwrap_or(LineEnding::Lf);
+                restored.push_str(style.as_str());
+            }
+            None => restored.push_str(piece),
+        }
+    }
+    Cow::Owned(restored)
+}
+
+/// The normalized text of a file along with the line-ending style it mostly
+/// used. When it used more than one, `mixed` holds the style of every break
+/// in order so an unedited file can be written back byte for byte.
+pub struct Normalized {
+    pub rope: Rope,
+    pub line_ending: LineEnding,
+    pub mixed: Option<Vec<LineEnding>>,
+}
+
+/// Builds a rope with every CRLF and lone CR turned into `\n`, counting each
+/// style on the way.
+pub fn normalize(text: &str) -> Normalized {
+    let bytes = text.as_bytes();
+    let (mut crlf, mut lf, mut cr) = (0usize, 0usize, 0usize);
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\r' if bytes.get(index + 1) == Some(&b'\n') => {
+                crlf += 1;
+                index += 1;
+            }
+            b'\r' => cr += 1,
+            b'\n' => lf += 1,
+            _ => {}
+        }
+        index += 1;
+    }
+
+    let kinds = [crlf, lf, cr].iter().filter(|count| **count > 0).count();
+    let line_ending = if kinds == 0 {
+        LineEnding::platform_default()
+    } else if crlf >= lf && crlf >= cr {
+        LineEnding::Crlf
+    } else if lf >= cr {
+        LineEnding::Lf
+    } else {
+        LineEnding::Cr
+    };
+
+    let rope = if crlf + cr == 0 {
+        Rope::from_str(text)
+    } else {
+        let mut builder = RopeBuilder::new();
+        let mut start = 0;
+        for (pos, _) in text.match_indices('\r') {
+            builder.append(&text[start..pos]);
+            if bytes.get(pos + 1) != Some(&b'\n') {
+                builder.append("\n");
+            }
+            start = pos + 1;
+        }
+        builder.append(&text[start..]);
+        builder.finish()
+    };
+
+    let mixed = (kinds > 1).then(|| {
+        let mut breaks = Vec::with_capacity(crlf + lf + cr);
+        let mut index = 0;
+        while index < bytes.len() {
+            match bytes[index] {
+                b'\r' if bytes.get(index + 1) == Some(&b'\n') => {
+                    breaks.push(LineEnding::Crlf);
+                    index += 1;
+                }
+                b'\r' => breaks.push(LineEnding::Cr),
+                b'\n' => breaks.push(LineEnding::Lf),
+                _ => {}
+            }
+            index += 1;
+        }
+        breaks
+    });
+
+    Normalized {
+        rope,
+        line_ending,
+        mixed,
+    }
+}
+
+/// Turns CRLF and lone CR in inserted text into `\n`.
+pub fn normalize_str(text: &str) -> Cow<'_, str> {
+    if text.contains('\r') {
+        Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lone_cr_is_a_line_break() {
+        let normalized = normalize("one\rtwo\rthree");
+        assert_eq!(normalized.rope.to_string(), "one\ntwo\nthree");
+        assert_eq!(normalized.line_ending, LineEnding::Cr);
+        assert!(normalized.mixed.is_none());
+    }
+
+    #[test]
+    fn mixed_endings_pick_the_most_used() {
+        let normalized = normalize("a\r\nb\r\nc\nd");
+        assert_eq!(normalized.rope.to_string(), "a\nb\nc\nd");
+        assert_eq!(normalized.line_ending, LineEnding::Crlf);
+        assert_eq!(
+            normalized.mixed.unwrap(),
+            [LineEnding::Crlf, LineEnding::Crlf, LineEnding::Lf]
+        );
+
+        let normalized = normalize("a\nb\nc\r\nd");
+        assert_eq!(normalized.line_ending, LineEnding::Lf);
+        assert!(normalized.mixed.is_some());
+    }
+
+    #[test]
+    fn a_tie_prefers_crlf() {
+        let normalized = normalize("a\r\nb\nc");
+        assert_eq!(normalized.line_ending, LineEnding::Crlf);
+    }
+
+    #[test]
+    fn trailing_cr_at_the_end() {
+        let normalized = normalize("a\r\nb\r");
+        assert_eq!(normalized.rope.to_string(), "a\nb\n");
+        assert_eq!(normalized.line_ending, LineEnding::Crlf);
+        assert_eq!(
+            normalized.mixed.unwrap(),
+            [LineEnding::Crlf, LineEnding::Cr]
+        );
+
+        let normalized = normalize("\r");
+        assert_eq!(normalized.rope.to_string(), "\n");
+        assert_eq!(normalized.line_ending, LineEnding::Cr);
+    }
+
+    #[test]
+    fn no_breaks_use_the_platform_default() {
+        let normalized = normalize("just one line");
+        assert_eq!(normalized.rope.to_string(), "just one line");
+        assert_eq!(normalized.line_ending, LineEnding::platform_default());
+        assert!(normalized.mixed.is_none());
+    }
+
+    #[test]
+    fn normalize_str_and_apply_round_trip() {
+        assert_eq!(normalize_str("a\r\nb\rc\n"), "a\nb\nc\n");
+        assert!(matches!(normalize_str("a\nb"), Cow::Borrowed(_)));
+        assert_eq!(LineEnding::Crlf.apply("a\nb\n"), "a\r\nb\r\n");
+        assert_eq!(LineEnding::Cr.apply("a\nb"), "a\rb");
+        assert_eq!(LineEnding::Lf.apply("a\nb"), "a\nb");
+    }
+
+    #[test]
+    fn restore_puts_back_each_break() {
+        let mut breaks = [LineEnding::Crlf, LineEnding::Cr, LineEnding::Lf].into_iter();
+        assert_eq!(restore("a\nb", &mut breaks), "a\r\nb");
+        assert!(matches!(restore("no break", &mut breaks), Cow::Borrowed(_)));
+        assert_eq!(restore("\nc\n", &mut breaks), "\rc\n");
+    }
+}
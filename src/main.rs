@@ -1,17 +1,62 @@
 mod app;
+mod cli;
 mod document;
 mod editor;
+mod encoding;
+mod history;
+mod instance;
+mod keymap;
+mod line_ending;
+mod multi_search;
+mod paths;
+mod recovery;
+mod save;
+mod search;
+mod session;
+mod settings;
 mod shortcuts;
 mod ui;
 mod updater;
+mod watcher;
+
+use std::{cell::Cell, rc::Rc};
 
 fn main() -> eframe::Result<()> {
     // Initialize Velopack - must run first as it may terminate/restart the process
     velopack::VelopackApp::build().run();
+    let launch = match cli::Launch::from_args() {
+        Ok(launch) => launch,
+        Err(err) => {
+            eprintln!("plainpad: failed to read standard input: {err}");
+            std::process::exit(1);
+        }
+    };
+    let listener = if launch.new_instance {
+        None
+    } else if let Ok(saved) = instance::hand_off(&launch) {
+        std::process::exit(if saved { 0 } else { 1 });
+    } else if launch.wait {
+        // This window lives only as long as the caller waits, so it does not
+        // take files from later launches.
+        None
+    } else {
+        instance::listen()
+    };
+
+    let wait_result = launch.wait.then(|| Rc::new(Cell::new(false)));
+    let app_wait_result = wait_result.clone();
     let options = eframe::NativeOptions::default();
     eframe::run_native(
         "plainpad",
         options,
-        Box::new(|_cc| Box::new(app::PlainpadApp::new())),
-    )
+        Box::new(|cc| {
+            let requests = listener.map(|listener| instance::serve(listener, &cc.egui_ctx));
+            Box::new(app::PlainpadApp::new(launch, requests, app_wait_result))
+        }),
+    )?;
+
+    if let Some(saved) = wait_result {
+        std::process::exit(if saved.get() { 0 } else { 1 });
+    }
+    Ok(())
 }
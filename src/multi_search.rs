@@ -0,0 +1,573 @@
+use crate::{
+    document::line_content_len,
+    encoding,
+    line_ending::{self, LineEnding},
+    save::{self, SaveOptions},
+    search::Search,
+};
+use eframe::egui;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use ropey::Rope;
+use std::{
+    borrow::Cow,
+    fs, io, iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+/// A search across tabs stops collecting after this many matches.
+const MAX_HITS: usize = 10_000;
+/// Context lines longer than this are cut down around the match.
+const CONTEXT_CHARS: usize = 160;
+/// How much of the line before the match a shortened context keeps.
+const CONTEXT_BEFORE: usize = 40;
+
+/// Where a group of results was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// An open tab, by document id.
+    Tab(u64),
+    File(PathBuf),
+}
+
+/// One match, with the line it is on for context.
+pub struct Hit {
+    /// Zero-based line and char column where the match starts.
+    pub line: usize,
+    pub column: usize,
+    /// Length in chars, up to the end of the line.
+    pub len: usize,
+    /// The line the match is on, shortened around the match when it is long.
+    pub context: String,
+    /// Byte range of the match within `context`.
+    pub highlight: Range<usize>,
+    /// Char range of the whole match in the text searched.
+    pub range: Range<usize>,
+    /// What the match becomes, in a replace preview.
+    pub replacement: Option<String>,
+}
+
+/// The matches found in one tab or file.
+pub struct ResultGroup {
+    pub source: Source,
+    pub title: String,
+    pub hits: Vec<Hit>,
+}
+
+pub enum Update {
+    Group(ResultGroup),
+    /// The search finished. `truncated` when it stopped at the match limit.
+    Done {
+        truncated: bool,
+    },
+}
+
+/// The text of an open tab as it was when the search started.
+pub struct TabSnapshot {
+    pub id: u64,
+    pub title: String,
+    pub rope: Rope,
+}
+
+/// A search running on a worker thread. Dropping it stops the search.
+pub struct Run {
+    updates: Receiver<Update>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Run {
+    fn spawn(ctx: &egui::Context, job: impl FnOnce(&mut Sink) + Send + 'static) -> Self {
+        let (sender, updates) = mpsc::channel();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut sink = Sink {
+            ctx: ctx.clone(),
+            updates: sender,
+            cancelled: cancelled.clone(),
+            remaining: MAX_HITS,
+        };
+        thread::spawn(move || {
+            job(&mut sink);
+            if !sink.is_cancelled() {
+                sink.send(Update::Done {
+                    truncated: sink.remaining == 0,
+                });
+            }
+        });
+        Self { updates, cancelled }
+    }
+
+    /// What the search sent since the last call.
+    pub fn poll(&self) -> Vec<Update> {
+        self.updates.try_iter().collect()
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// The worker's end of a `Run`.
+struct Sink {
+    ctx: egui::Context,
+    updates: Sender<Update>,
+    cancelled: Arc<AtomicBool>,
+    remaining: usize,
+}
+
+impl Sink {
+    /// Whether the `Run` was dropped.
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Whether to stop: cancelled, or the match limit was reached.
+    fn is_done(&self) -> bool {
+        self.is_cancelled() || self.remaining == 0
+    }
+
+    fn send(&self, update: Update) {
+        let _ = self.updates.send(update);
+        self.ctx.request_repaint();
+    }
+
+    /// The matches of `search` in `rope`, counted against the match limit.
+    /// With a `replacement` only the matches it would change are kept, each
+    /// with what it becomes.
+    fn hits(&mut self, search: &Search, rope: &Rope, replacement: Option<&str>) -> Vec<Hit> {
+        let mut hits = Vec::new();
+        for window in search.rope_matches(rope, 0..rope.len_chars()) {
+            for range in window {
+                if self.is_done() {
+                    return hits;
+                }
+                let replacement = match replacement {
+                    Some(replacement) => {
+                        match search.replace_match(rope, range.clone(), replacement) {
+                            Some(with) if rope.slice(range.clone()) != with.as_str() => Some(with),
+                            _ => continue,
+                        }
+                    }
+                    None => None,
+                };
+                hits.push(Hit {
+                    replacement,
+                    ..hit(rope, range)
+                });
+                self.remaining -= 1;
+            }
+            if self.is_done() {
+                break;
+            }
+        }
+        hits
+    }
+}
+
+/// Describes the match at chars `range` of `rope`.
+fn hit(rope: &Rope, range: Range<usize>) -> Hit {
+    let line = rope.char_to_line(range.start);
+    let line_start = rope.line_to_char(line);
+    let line_len = line_content_len(rope.line(line));
+    let column = range.start - line_start;
+    let len = range.end.min(line_start + line_len) - range.start;
+
+    // Keep a little of the line before the match and cut the rest short.
+    let skip = column.saturating_sub(CONTEXT_BEFORE);
+    let mut context = String::new();
+    if skip > 0 {
+        context.push('…');
+    }
+    let shown_end = skip + (line_len - skip).min(CONTEXT_CHARS);
+    let piece = |from: usize, to: usize| {
+        rope.slice(line_start + from.min(shown_end)..line_start + to.min(shown_end))
+    };
+    context.extend(piece(skip, column).chars());
+    let highlight_start = context.len();
+    context.extend(piece(column, column + len).chars());
+    let highlight = highlight_start..context.len();
+    context.extend(piece(column + len, shown_end).chars());
+    if line_len > shown_end {
+        context.push('…');
+    }
+    Hit {
+        line,
+        column,
+        len,
+        context,
+        highlight,
+        range,
+        replacement: None,
+    }
+}
+
+/// Searches every tab in `tabs` for `search`, one group per tab with
+/// matches.
+pub fn search_tabs(ctx: &egui::Context, tabs: Vec<TabSnapshot>, search: Arc<Search>) -> Run {
+    Run::spawn(ctx, move |sink| {
+        for tab in tabs {
+            if sink.is_done() {
+                break;
+            }
+            let hits = sink.hits(&search, &tab.rope, None);
+            if !hits.is_empty() && !sink.is_cancelled() {
+                sink.send(Update::Group(ResultGroup {
+                    source: Source::Tab(tab.id),
+                    title: tab.title,
+                    hits,
+                }));
+            }
+        }
+    })
+}
+
+/// Which files under a folder Find in Files looks at.
+pub struct FileSearch {
+    pub root: PathBuf,
+    /// Only files matching one of these are searched, unless it is empty.
+    pub include: GlobSet,
+    /// Files and folders matching one of these are skipped.
+    pub exclude: GlobSet,
+    /// Files larger than this many bytes are skipped.
+    pub max_size: u64,
+    pub skip_binary: bool,
+    /// Skip what `.gitignore` files, `.git/info/exclude` and the global git
+    /// excludes list.
+    pub use_gitignore: bool,
+}
+
+impl FileSearch {
+    /// Whether `path` under the root is matched by `globs`.
+    fn matches(&self, globs: &GlobSet, path: &Path) -> bool {
+        globs.is_match(path.strip_prefix(&self.root).unwrap_or(path))
+    }
+}
+
+/// Parses a comma-separated list of globs such as `*.rs, docs/**/*.md`. A
+/// glob without a `/` matches file and folder names at any depth; one with a
+/// `/` matches the path from the root folder.
+pub fn glob_set(list: &str) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in list
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+    {
+        let pattern = pattern.trim_start_matches("./");
+        let full = if pattern.contains('/') {
+            pattern.to_string()
+        } else {
+            format!("**/{pattern}")
+        };
+        let glob = GlobBuilder::new(&full)
+            .literal_separator(true)
+            .build()
+            .map_err(|err| format!("Invalid glob `{pattern}`: {err}"))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|err| format!("Invalid glob list: {err}"))
+}
+
+/// The edits that replace the matches at chars `ranges` of `rope`, leaving
+/// out those the replacement would not change. `None` when one of them is
+/// no longer a match, as when the text changed since the preview.
+pub fn previewed_edits(
+    search: &Search,
+    rope: &Rope,
+    ranges: &[Range<usize>],
+    replacement: &str,
+) -> Option<Vec<(Range<usize>, String)>> {
+    let mut edits = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let with = search.replace_match(rope, range.clone(), replacement)?;
+        if rope.slice(range.clone()) != with.as_str() {
+            edits.push((range.clone(), with));
+        }
+    }
+    Some(edits)
+}
+
+/// Replaces the matches previewed at chars `ranges` in the file at `path`,
+/// reading it again first. Its line breaks are kept as they were, mixed
+/// ones included; line breaks in the replacements take the style it uses
+/// most. Returns how many matches were replaced.
+pub fn replace_in_file(
+    path: &Path,
+    search: &Search,
+    ranges: &[Range<usize>],
+    replacement: &str,
+    options: &SaveOptions,
+) -> io::Result<usize> {
+    let bytes = fs::read(path)?;
+    let encoding = encoding::detect(&bytes);
+    let decoded = encoding::decode(bytes, encoding);
+    if decoded.malformed {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("some bytes are not valid {}", encoding.label()),
+        ));
+    }
+    let normalized = line_ending::normalize(&decoded.text);
+    let edits = previewed_edits(search, &normalized.rope, ranges, replacement)
+        .ok_or_else(|| io::Error::other("the file changed since the preview"))?;
+    if edits.is_empty() {
+        return Ok(0);
+    }
+    let text = splice(&decoded.text, &edits, normalized.line_ending);
+    save::write_atomic(path, options, |writer| {
+        encoding::encode_to(writer, iter::once(Cow::Borrowed(text.as_str())), encoding)
+    })?;
+    Ok(edits.len())
+}
+
+/// `raw` with `edits` made to it. The edits are at char ranges of the text
+/// with every line break turned into `\n`, as a tab holds it; everything
+/// outside them is copied from `raw` as it is.
+fn splice(raw: &str, edits: &[(Range<usize>, String)], line_ending: LineEnding) -> String {
+    let mut spliced = String::with_capacity(raw.len());
+    let mut edits = edits.iter().peekable();
+    // Where `raw` is copied from next, and the end of the edit being
+    // skipped over, in chars of the normalized text.
+    let mut copied = 0;
+    let mut skipping = None;
+    let mut normalized = 0;
+    let mut chars = raw.char_indices().peekable();
+    loop {
+        let offset = chars.peek().map_or(raw.len(), |(offset, _)| *offset);
+        if skipping == Some(normalized) {
+            copied = offset;
+            skipping = None;
+        }
+        if skipping.is_none() {
+            if let Some((range, with)) = edits.next_if(|(range, _)| range.start == normalized) {
+                spliced.push_str(&raw[copied..offset]);
+                spliced.push_str(&line_ending.apply(with));
+                copied = offset;
+                skipping = Some(range.end);
+                continue;
+            }
+        }
+        let Some((_, ch)) = chars.next() else {
+            break;
+        };
+        if ch == '\r' {
+            chars.next_if(|(_, next)| *next == '\n');
+        }
+        normalized += 1;
+    }
+    spliced.push_str(&raw[copied..]);
+    spliced
+}
+
+/// Reads a file the way a tab would show it: in its detected encoding with
+/// `\n` line breaks. `None` when it cannot be read or, with `skip_binary`,
+/// looks binary.
+fn read_text(path: &Path, skip_binary: bool) -> Option<Rope> {
+    let bytes = fs::read(path).ok()?;
+    if skip_binary && encoding::looks_binary(&bytes) {
+        return None;
+    }
+    let encoding = encoding::detect(&bytes);
+    let text = encoding::decode(bytes, encoding).text;
+    Some(Rope::from_str(&line_ending::normalize_str(&text)))
+}
+
+/// Searches the files under `files.root` for `search`, one group per file
+/// with matches, in path order. With a `replacement` the hits are a preview
+/// of replacing them.
+pub fn search_files(
+    ctx: &egui::Context,
+    files: FileSearch,
+    search: Arc<Search>,
+    replacement: Option<String>,
+) -> Run {
+    Run::spawn(ctx, move |sink| {
+        let files = Arc::new(files);
+        let filter = files.clone();
+        let walker = WalkBuilder::new(&files.root)
+            .hidden(false)
+            .ignore(false)
+            .parents(true)
+            .require_git(false)
+            .git_ignore(files.use_gitignore)
+            .git_global(files.use_gitignore)
+            .git_exclude(files.use_gitignore)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .filter_entry(move |entry| {
+                entry.file_name() != ".git" && !filter.matches(&filter.exclude, entry.path())
+            })
+            .build();
+        for entry in walker {
+            if sink.is_done() {
+                break;
+            }
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let path = entry.path();
+            if !entry.file_type().is_some_and(|kind| kind.is_file())
+                || (!files.include.is_empty() && !files.matches(&files.include, path))
+                || entry
+                    .metadata()
+                    .map_or(true, |meta| meta.len() > files.max_size)
+            {
+                continue;
+            }
+            let Some(text) = read_text(path, files.skip_binary) else {
+                continue;
+            };
+            let hits = sink.hits(&search, &text, replacement.as_deref());
+            if !hits.is_empty() && !sink.is_cancelled() {
+                let title = path.strip_prefix(&files.root).unwrap_or(path);
+                sink.send(Update::Group(ResultGroup {
+                    source: Source::File(path.to_path_buf()),
+                    title: title.display().to_string(),
+                    hits,
+                }));
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    fn search(query: &str) -> Search {
+        Search::new(query, SearchOptions::default()).unwrap()
+    }
+
+    fn regex(query: &str) -> Search {
+        let options = SearchOptions {
+            use_regex: true,
+            ..Default::default()
+        };
+        Search::new(query, options).unwrap()
+    }
+
+    /// Every match of `search` in `raw` replaced the way a preview would.
+    fn replace_all(search: &Search, raw: &str, replacement: &str) -> String {
+        let normalized = line_ending::normalize(raw);
+        let rope = &normalized.rope;
+        let ranges = search
+            .rope_matches(rope, 0..rope.len_chars())
+            .flatten()
+            .collect::<Vec<_>>();
+        let edits = previewed_edits(search, rope, &ranges, replacement).unwrap();
+        splice(raw, &edits, normalized.line_ending)
+    }
+
+    #[test]
+    fn splice_keeps_line_breaks_as_they_were() {
+        let raw = "one two\r\ntwo\nthree two\rtwo";
+        assert_eq!(
+            replace_all(&search("two"), raw, "2"),
+            "one 2\r\n2\nthree 2\r2"
+        );
+    }
+
+    #[test]
+    fn a_match_across_a_line_break_replaces_all_of_it() {
+        let raw = "end\r\nstart\r\nrest";
+        assert_eq!(
+            replace_all(&regex(r"end\nstart"), raw, "joined"),
+            "joined\r\nrest"
+        );
+        // Up to the very end of the text.
+        assert_eq!(replace_all(&regex(r"rest$"), raw, "x"), "end\r\nstart\r\nx");
+    }
+
+    #[test]
+    fn line_breaks_in_a_replacement_follow_the_file() {
+        let raw = "a,b\r\nc\r\nd\n";
+        assert_eq!(replace_all(&search(","), raw, "\n"), "a\r\nb\r\nc\r\nd\n");
+    }
+
+    #[test]
+    fn multibyte_text_before_an_edit() {
+        let raw = "日本\r\néé x\r\n";
+        assert_eq!(replace_all(&search("x"), raw, "✓"), "日本\r\néé ✓\r\n");
+    }
+
+    #[test]
+    fn stale_ranges_are_refused() {
+        let rope = Rope::from_str("one two three");
+        let search = search("two");
+        let two = 4..7;
+        assert_eq!(
+            previewed_edits(&search, &rope, std::slice::from_ref(&two), "2"),
+            Some(vec![(4..7, "2".to_string())])
+        );
+        assert_eq!(previewed_edits(&search, &rope, &[4..7, 8..11], "2"), None);
+        let past_the_end = 20..23;
+        assert_eq!(previewed_edits(&search, &rope, &[past_the_end], "2"), None);
+        // A replacement that changes nothing is left out.
+        assert_eq!(previewed_edits(&search, &rope, &[two], "two"), Some(vec![]));
+    }
+
+    #[test]
+    fn only_the_previewed_matches_are_written() {
+        let dir = std::env::temp_dir().join(format!("plainpad-replace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notes.txt");
+        fs::write(&path, b"\xEF\xBB\xBFtwo\r\ntwo\ntwo\r\n").unwrap();
+
+        // As if the preview stopped at the match limit after two matches.
+        let search = search("two");
+        let options = SaveOptions::default();
+        let result = replace_in_file(&path, &search, &[0..3, 4..7], "2", &options);
+        let written = fs::read(&path).unwrap();
+        // The first match is gone now, so the same preview no longer applies.
+        let first = 0..3;
+        let stale = replace_in_file(&path, &search, &[first], "2", &options);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(written, b"\xEF\xBB\xBF2\r\n2\ntwo\r\n");
+        assert_eq!(stale.unwrap_err().kind(), io::ErrorKind::Other);
+    }
+
+    fn hit_at(text: &str, start: usize, end: usize) -> Hit {
+        hit(&Rope::from_str(text), start..end)
+    }
+
+    #[test]
+    fn a_hit_knows_its_line_and_char_column() {
+        let hit = hit_at("first\nsé cat here\n", 9, 12);
+        assert_eq!((hit.line, hit.column, hit.len), (1, 3, 3));
+        assert_eq!(hit.context, "sé cat here");
+        assert_eq!(&hit.context[hit.highlight], "cat");
+    }
+
+    #[test]
+    fn a_match_over_a_line_break_is_shown_to_the_end_of_its_line() {
+        let hit = hit_at("one two\nthree", 4, 10);
+        assert_eq!((hit.line, hit.column, hit.len), (0, 4, 3));
+        assert_eq!(&hit.context[hit.highlight], "two");
+    }
+
+    #[test]
+    fn long_lines_are_cut_around_the_match() {
+        let line = format!("{}cat{}", "a".repeat(100), "b".repeat(300));
+        let hit = hit_at(&line, 100, 103);
+        assert_eq!(hit.column, 100);
+        assert!(hit.context.starts_with('…') && hit.context.ends_with('…'));
+        assert_eq!(hit.context.chars().count(), CONTEXT_CHARS + 2);
+        assert_eq!(&hit.context[hit.highlight.clone()], "cat");
+        assert_eq!(
+            hit.context[..hit.highlight.start].chars().count(),
+            CONTEXT_BEFORE + 1
+        );
+    }
+}
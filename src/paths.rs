@@ -0,0 +1,12 @@
+use std::path::PathBuf;
+
+/// The per-user folder plainpad keeps its own state in, such as the session
+/// and the recovery journal.
+pub fn state_dir() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|dir| dir.join("plainpad"))
+}
+
+/// The per-user folder plainpad keeps its settings in.
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("plainpad"))
+}
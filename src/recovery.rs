@@ -0,0 +1,400 @@
+use crate::{
+    document::{Document, Selection},
+    encoding::TextEncoding,
+    line_ending::LineEnding,
+    paths,
+    save::{self, SaveOptions},
+    watcher::DiskState,
+};
+use encoding_rs::Encoding;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const MANIFEST_FILE: &str = "journal.json";
+const LOCK_FILE: &str = "lock";
+
+fn recovery_root() -> io::Result<PathBuf> {
+    paths::state_dir()
+        .map(|dir| dir.join("recovery"))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no local data folder"))
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Manifest {
+    tabs: Vec<Entry>,
+}
+
+/// One journaled tab. Its text is kept next to the manifest in `{id}.txt`,
+/// as UTF-8 with `\n` line breaks.
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    id: u64,
+    path: Option<PathBuf>,
+    title: String,
+    encoding: String,
+    bom: bool,
+    line_ending: LineEnding,
+    selection: Selection,
+    top_line: f64,
+    disk: Option<DiskState>,
+}
+
+impl Entry {
+    fn new(doc: &Document) -> Self {
+        Self {
+            id: doc.id(),
+            path: doc.path().cloned(),
+            title: doc.title(),
+            encoding: doc.encoding().encoding.name().to_string(),
+            bom: doc.encoding().bom,
+            line_ending: doc.line_ending(),
+            selection: doc.selection(),
+            top_line: doc.view().top_line,
+            disk: doc.disk_state(),
+        }
+    }
+
+    fn text_file(&self) -> String {
+        format!("{}.txt", self.id)
+    }
+}
+
+/// Copies of every tab with unsaved changes, kept in a folder of its own for
+/// each running instance. The folder stays locked while the instance runs and
+/// is removed on a clean exit, so a folder that is left behind unlocked
+/// belongs to an instance that crashed.
+pub struct Journal {
+    dir: PathBuf,
+    lock: File,
+    /// `(id, revision)` of the dirty tabs as of the last write.
+    written: Vec<(u64, u64)>,
+    worker: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl Journal {
+    pub fn start() -> io::Result<Self> {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or(0);
+        let dir = recovery_root()?.join(format!("{}-{millis}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let lock = lock_dir(&dir)?.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::WouldBlock, "recovery folder is in use")
+        })?;
+        Ok(Self {
+            dir,
+            lock,
+            written: Vec::new(),
+            worker: None,
+        })
+    }
+
+    /// Journals left behind by instances that did not exit cleanly. Each one
+    /// stays locked by this instance until it is restored or discarded.
+    pub fn leftovers(&self) -> Vec<Leftover> {
+        let Ok(entries) = recovery_root().and_then(fs::read_dir) else {
+            return Vec::new();
+        };
+        let mut leftovers = Vec::new();
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            if dir == self.dir || !dir.is_dir() {
+                continue;
+            }
+            let Ok(Some(lock)) = lock_dir(&dir) else {
+                continue;
+            };
+            let manifest = fs::read(dir.join(MANIFEST_FILE))
+                .ok()
+                .and_then(|bytes| serde_json::from_slice::<Manifest>(&bytes).ok())
+                .unwrap_or_default();
+            let leftover = Leftover {
+                dir,
+                lock,
+                tabs: manifest.tabs,
+            };
+            if leftover.tabs.is_empty() {
+                leftover.discard();
+            } else {
+                leftovers.push(leftover);
+            }
+        }
+        leftovers
+    }
+
+    /// True when a tab was edited, saved or closed since the last write, or
+    /// when the last write failed.
+    pub fn is_stale(&mut self, documents: &[Document]) -> bool {
+        if self
+            .worker
+            .as_ref()
+            .is_some_and(|worker| worker.is_finished())
+        {
+            let finished = self.worker.take().map(JoinHandle::join);
+            if !matches!(finished, Some(Ok(Ok(())))) {
+                self.written.clear();
+            }
+        }
+        self.written != dirty_revisions(documents)
+    }
+
+    /// Writes the dirty tabs on a background thread, unless the previous
+    /// write is still running. Ropes clone in constant time, so the UI never
+    /// waits on the disk; only tabs that changed since the last write have
+    /// their text written again.
+    pub fn write(&mut self, documents: &[Document]) {
+        if self
+            .worker
+            .as_ref()
+            .is_some_and(|worker| !worker.is_finished())
+        {
+            return;
+        }
+        let revisions = dirty_revisions(documents);
+        let tabs = documents
+            .iter()
+            .filter(|doc| doc.is_dirty())
+            .map(|doc| {
+                let unchanged = self.written.contains(&(doc.id(), doc.revision()));
+                let rope = (!unchanged).then(|| doc.rope().clone());
+                (Entry::new(doc), rope)
+            })
+            .collect::<Vec<_>>();
+        self.written = revisions;
+        let dir = self.dir.clone();
+        self.worker = Some(thread::spawn(move || write_journal(&dir, tabs)));
+    }
+
+    /// Removes this instance's journal on a clean exit.
+    pub fn discard(self) {
+        let Self {
+            dir, lock, worker, ..
+        } = self;
+        if let Some(worker) = worker {
+            let _ = worker.join();
+        }
+        // Windows will not remove a folder while a file in it is open.
+        drop(lock);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+/// The tabs of a journal left behind by a crashed instance.
+pub struct Leftover {
+    dir: PathBuf,
+    lock: File,
+    tabs: Vec<Entry>,
+}
+
+impl Leftover {
+    pub fn tab_count(&self) -> usize {
+        self.tabs.len()
+    }
+
+    /// Rebuilds the journaled tabs, skipping any whose text went missing.
+    pub fn documents(&self) -> Vec<Document> {
+        self.tabs
+            .iter()
+            .filter_map(|entry| {
+                let text = fs::read_to_string(self.dir.join(entry.text_file())).ok()?;
+                let encoding = TextEncoding {
+                    encoding: Encoding::for_label(entry.encoding.as_bytes())
+                        .unwrap_or(encoding_rs::UTF_8),
+                    bom: entry.bom,
+                };
+                let mut doc = Document::recovered(
+                    entry.path.clone(),
+                    entry.title.clone(),
+                    &text,
+                    encoding,
+                    entry.line_ending,
+                    entry.disk,
+                );
+                doc.set_selection(entry.selection);
+                doc.view_mut().top_line = entry.top_line;
+                doc.view_mut().reveal_cursor = true;
+                Some(doc)
+            })
+            .collect()
+    }
+
+    pub fn discard(self) {
+        let Self { dir, lock, .. } = self;
+        drop(lock);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+fn dirty_revisions(documents: &[Document]) -> Vec<(u64, u64)> {
+    documents
+        .iter()
+        .filter(|doc| doc.is_dirty())
+        .map(|doc| (doc.id(), doc.revision()))
+        .collect()
+}
+
+/// Takes the lock of a journal folder. `None` means another running instance
+/// holds it.
+fn lock_dir(dir: &Path) -> io::Result<Option<File>> {
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(fs::TryLockError::WouldBlock) => Ok(None),
+        Err(fs::TryLockError::Error(err)) => Err(err),
+    }
+}
+
+/// Writes changed texts first and the manifest last, so the manifest never
+/// names a text that is not on disk yet, then drops texts of tabs that are
+/// no longer dirty.
+fn write_journal(dir: &Path, tabs: Vec<(Entry, Option<Rope>)>) -> io::Result<()> {
+    let options = SaveOptions::default();
+    let mut manifest = Manifest::default();
+    for (entry, rope) in tabs {
+        if let Some(rope) = rope {
+            save::write_atomic(&dir.join(entry.text_file()), &options, |writer| {
+                for chunk in rope.chunks() {
+                    writer.write_all(chunk.as_bytes())?;
+                }
+                Ok(())
+            })?;
+        }
+        manifest.tabs.push(entry);
+    }
+
+    let json = serde_json::to_vec(&manifest).map_err(io::Error::other)?;
+    save::write_atomic(&dir.join(MANIFEST_FILE), &options, |writer| {
+        writer.write_all(&json)
+    })?;
+
+    let keep = manifest
+        .tabs
+        .iter()
+        .map(Entry::text_file)
+        .collect::<Vec<_>>();
+    for file in fs::read_dir(dir)?.flatten() {
+        let name = file.file_name().to_string_lossy().into_owned();
+        if name.ends_with(".txt") && !keep.contains(&name) {
+            let _ = fs::remove_file(file.path());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A journal folder of its own for one test, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir()
+                .join(format!("plainpad-recovery-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Opens the folder the way a later instance finds a leftover journal.
+        fn leftover(&self) -> Leftover {
+            let manifest: Manifest =
+                serde_json::from_slice(&fs::read(self.0.join(MANIFEST_FILE)).unwrap()).unwrap();
+            Leftover {
+                dir: self.0.clone(),
+                lock: lock_dir(&self.0).unwrap().unwrap(),
+                tabs: manifest.tabs,
+            }
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn dirty_doc(text: &str) -> Document {
+        let mut doc = Document::new_untitled(3);
+        doc.replace_range(0..0, text);
+        doc
+    }
+
+    fn journal(doc: &Document) -> (Entry, Option<Rope>) {
+        (Entry::new(doc), Some(doc.rope().clone()))
+    }
+
+    #[test]
+    fn a_journaled_tab_comes_back_as_it_was() {
+        let dir = TempDir::new("round-trip");
+        let mut doc = dirty_doc("héllo\nwörld\n");
+        doc.set_encoding(TextEncoding {
+            encoding: encoding_rs::UTF_16LE,
+            bom: true,
+        });
+        doc.set_line_ending(LineEnding::Crlf);
+        doc.set_selection(Selection { anchor: 2, head: 8 });
+        doc.view_mut().top_line = 1.5;
+        write_journal(&dir.0, vec![journal(&doc)]).unwrap();
+
+        let leftover = dir.leftover();
+        assert_eq!(leftover.tab_count(), 1);
+        let restored = leftover.documents().remove(0);
+        assert_eq!(restored.text(), "héllo\nwörld\n");
+        assert_eq!(restored.title(), "Untitled 3");
+        assert_eq!(restored.path(), None);
+        assert_eq!(restored.encoding(), doc.encoding());
+        assert_eq!(restored.line_ending(), LineEnding::Crlf);
+        assert_eq!(restored.selection(), Selection { anchor: 2, head: 8 });
+        assert_eq!(restored.view().top_line, 1.5);
+        assert!(restored.is_dirty());
+    }
+
+    #[test]
+    fn unchanged_texts_are_kept_and_closed_tabs_dropped() {
+        let dir = TempDir::new("rewrite");
+        let first = dirty_doc("first");
+        let second = dirty_doc("second");
+        write_journal(&dir.0, vec![journal(&first), journal(&second)]).unwrap();
+
+        // The first tab did not change since, and the second was closed.
+        write_journal(&dir.0, vec![(Entry::new(&first), None)]).unwrap();
+        let texts: Vec<_> = dir
+            .leftover()
+            .documents()
+            .iter()
+            .map(|doc| doc.text().into_owned())
+            .collect();
+        assert_eq!(texts, ["first"]);
+        assert!(!dir.0.join(Entry::new(&second).text_file()).exists());
+    }
+
+    #[test]
+    fn a_tab_whose_text_went_missing_is_skipped() {
+        let dir = TempDir::new("missing");
+        let doc = dirty_doc("lost");
+        write_journal(&dir.0, vec![journal(&doc)]).unwrap();
+        fs::remove_file(dir.0.join(Entry::new(&doc).text_file())).unwrap();
+        assert!(dir.leftover().documents().is_empty());
+    }
+
+    #[test]
+    fn a_locked_folder_is_left_alone() {
+        let dir = TempDir::new("locked");
+        let _held = lock_dir(&dir.0).unwrap().unwrap();
+        assert!(lock_dir(&dir.0).unwrap().is_none());
+    }
+}
@@ -0,0 +1,425 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Whether a copy of the previous file is kept when it is overwritten.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum BackupMode {
+    #[default]
+    Off,
+    /// `notes.txt` is copied to `notes.txt.bak` next to it.
+    Sibling,
+    /// `notes.txt` is copied to `<dir>/notes.20260131-142530-123.1a2b3c.txt`:
+    /// the UTC time to the millisecond, then a short hash of the folder the
+    /// file is in so same-named files from different folders do not collide.
+    /// An existing backup is never overwritten.
+    Timestamped(PathBuf),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SaveOptions {
+    pub backup: BackupMode,
+}
+
+/// Writes a file by filling a temp file in the same directory, syncing it and
+/// renaming it over `path`, so a crash never leaves a half-written file.
+/// Errors name the step that failed.
+pub fn write_atomic(
+    path: &Path,
+    options: &SaveOptions,
+    write: impl FnOnce(&mut BufWriter<fs::File>) -> io::Result<()>,
+) -> io::Result<()> {
+    // Write through symlinks instead of replacing them with a regular file.
+    let path = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => {
+            step(fs::canonicalize(path), "resolving the symbolic link")?
+        }
+        _ => path.to_path_buf(),
+    };
+    let existing = fs::metadata(&path).ok();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+
+    let temp_path = temp_path_for(&path, &dir);
+    let file = step(
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path),
+        "creating a temporary file",
+    )?;
+
+    let result = fill_temp(file, existing.as_ref(), write)
+        .and_then(|()| backup_existing(&path, existing.as_ref(), &options.backup))
+        .and_then(|()| {
+            step(
+                fs::rename(&temp_path, &path),
+                "replacing the file with the new contents",
+            )
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+        return result;
+    }
+
+    sync_dir(&dir);
+    Ok(())
+}
+
+fn fill_temp(
+    file: fs::File,
+    existing: Option<&fs::Metadata>,
+    write: impl FnOnce(&mut BufWriter<fs::File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(file);
+    step(write(&mut writer), "writing the new contents")?;
+    step(writer.flush(), "writing the new contents")?;
+    let file = step(
+        writer.into_inner().map_err(|err| err.into_error()),
+        "writing the new contents",
+    )?;
+    if let Some(meta) = existing {
+        step(
+            file.set_permissions(meta.permissions()),
+            "copying the original file permissions",
+        )?;
+    }
+    step(file.sync_all(), "flushing the new contents to disk")
+}
+
+fn backup_existing(
+    path: &Path,
+    existing: Option<&fs::Metadata>,
+    mode: &BackupMode,
+) -> io::Result<()> {
+    let Some(existing) = existing else {
+        return Ok(());
+    };
+    match mode {
+        BackupMode::Off => Ok(()),
+        BackupMode::Sibling => {
+            let mut name = path.file_name().unwrap_or_default().to_os_string();
+            name.push(".bak");
+            step(
+                fs::copy(path, path.with_file_name(name)),
+                "writing the backup copy",
+            )
+            .map(|_| ())
+        }
+        BackupMode::Timestamped(dir) => {
+            step(fs::create_dir_all(dir), "creating the backup folder")?;
+            let backup_path = dir.join(timestamped_name(path, SystemTime::now()));
+            step(
+                copy_new(path, &backup_path, existing),
+                "writing the backup copy",
+            )
+        }
+    }
+}
+
+/// Copies `from` to a file that must not exist yet.
+fn copy_new(from: &Path, to: &Path, meta: &fs::Metadata) -> io::Result<()> {
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    io::copy(&mut source, &mut target)?;
+    target.set_permissions(meta.permissions())
+}
+
+fn timestamped_name(path: &Path, time: SystemTime) -> String {
+    let stamp = utc_timestamp(time);
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_millis())
+        .unwrap_or(0);
+    let mut hasher = DefaultHasher::new();
+    path.parent().hash(&mut hasher);
+    let folder = hasher.finish() & 0xFF_FFFF;
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let name = format!("{stem}.{stamp}-{millis:03}.{folder:06x}");
+    match path.extension() {
+        Some(ext) => format!("{name}.{}", ext.to_string_lossy()),
+        None => name,
+    }
+}
+
+/// Formats `time` as `YYYYMMDD-HHMMSS` in UTC.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+
+    // Civil-from-days, after Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+fn temp_path_for(path: &Path, dir: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let unique = COUNTER.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!(".{name}.{}-{unique}.tmp", std::process::id()))
+}
+
+/// Makes the rename itself durable. Directories cannot be opened for syncing
+/// on Windows, where the rename is already flushed by the file system.
+fn sync_dir(dir: &Path) {
+    #[cfg(unix)]
+    if let Ok(dir) = fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+}
+
+fn step<T>(result: io::Result<T>, what: &str) -> io::Result<T> {
+    result.map_err(|err| io::Error::new(err.kind(), format!("{what} failed: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn stamp(secs: u64) -> String {
+        utc_timestamp(UNIX_EPOCH + Duration::from_secs(secs))
+    }
+
+    /// A folder of its own for one test, removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("plainpad-save-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn names(&self) -> Vec<String> {
+            let mut names: Vec<_> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn save(path: &Path, options: &SaveOptions, text: &str) -> io::Result<()> {
+        write_atomic(path, options, |writer| writer.write_all(text.as_bytes()))
+    }
+
+    fn with_backup(backup: BackupMode) -> SaveOptions {
+        SaveOptions { backup }
+    }
+
+    #[test]
+    fn replaces_the_file_and_leaves_no_temp_file() {
+        let dir = TempDir::new("replace");
+        let path = dir.0.join("notes.txt");
+        save(&path, &SaveOptions::default(), "first").unwrap();
+        save(&path, &SaveOptions::default(), "second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(dir.names(), ["notes.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_the_original_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("permissions");
+        let path = dir.0.join("script.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        save(&path, &SaveOptions::default(), "new").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+
+    #[test]
+    fn sibling_backup_keeps_the_previous_contents() {
+        let dir = TempDir::new("sibling");
+        let path = dir.0.join("notes.txt");
+        let options = with_backup(BackupMode::Sibling);
+        // Nothing to back up the first time.
+        save(&path, &options, "one").unwrap();
+        assert_eq!(dir.names(), ["notes.txt"]);
+        save(&path, &options, "two").unwrap();
+        save(&path, &options, "three").unwrap();
+        assert_eq!(dir.names(), ["notes.txt", "notes.txt.bak"]);
+        assert_eq!(
+            fs::read_to_string(dir.0.join("notes.txt.bak")).unwrap(),
+            "two"
+        );
+    }
+
+    #[test]
+    fn timestamped_backup_goes_to_its_folder() {
+        let dir = TempDir::new("timestamped");
+        let path = dir.0.join("notes.txt");
+        let backups = dir.0.join("backups");
+        fs::write(&path, "old").unwrap();
+        save(
+            &path,
+            &with_backup(BackupMode::Timestamped(backups.clone())),
+            "new",
+        )
+        .unwrap();
+
+        let names: Vec<_> = fs::read_dir(&backups)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        assert_eq!(names.len(), 1);
+        let name = names[0].file_name().unwrap().to_string_lossy();
+        assert!(
+            name.starts_with("notes.") && name.ends_with(".txt"),
+            "{name}"
+        );
+        assert_eq!(fs::read_to_string(&names[0]).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+
+    #[test]
+    fn timestamped_names_tell_apart_times_and_folders() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_704_067_200_042);
+        let name = timestamped_name(Path::new("/a/notes.txt"), time);
+        assert!(name.starts_with("notes.20240101-000000-042."), "{name}");
+        assert!(name.ends_with(".txt"), "{name}");
+        assert_ne!(name, timestamped_name(Path::new("/b/notes.txt"), time));
+        let later = time + Duration::from_millis(1);
+        assert_ne!(name, timestamped_name(Path::new("/a/notes.txt"), later));
+        assert!(!timestamped_name(Path::new("/a/README"), time).contains(".txt"));
+    }
+
+    #[test]
+    fn an_existing_backup_is_not_overwritten() {
+        let dir = TempDir::new("clobber");
+        let path = dir.0.join("notes.txt");
+        let backup = dir.0.join("backup.txt");
+        fs::write(&path, "current").unwrap();
+        fs::write(&backup, "earlier backup").unwrap();
+        let meta = fs::metadata(&path).unwrap();
+        let err = copy_new(&path, &backup, &meta).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "earlier backup");
+    }
+
+    #[test]
+    fn a_failed_write_keeps_the_original_and_removes_the_temp_file() {
+        let dir = TempDir::new("failed");
+        let path = dir.0.join("notes.txt");
+        fs::write(&path, "original").unwrap();
+        let err = write_atomic(&path, &SaveOptions::default(), |writer| {
+            writer.write_all(b"partial")?;
+            Err(io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "writing the new contents failed: disk full"
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(dir.names(), ["notes.txt"]);
+    }
+
+    #[test]
+    fn errors_name_the_failed_step() {
+        let dir = TempDir::new("missing");
+        let path = dir.0.join("no such folder").join("notes.txt");
+        let err = save(&path, &SaveOptions::default(), "text").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(
+            err.to_string()
+                .starts_with("creating a temporary file failed: "),
+            "{err}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_a_symbolic_link() {
+        let dir = TempDir::new("symlink");
+        let target = dir.0.join("target.txt");
+        let link = dir.0.join("link.txt");
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        save(&link, &SaveOptions::default(), "new").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(dir.names(), ["link.txt", "target.txt"]);
+    }
+
+    #[test]
+    fn the_epoch() {
+        assert_eq!(stamp(0), "19700101-000000");
+        // Times before it are clamped rather than wrapped.
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH - Duration::from_secs(1)),
+            "19700101-000000"
+        );
+    }
+
+    #[test]
+    fn across_a_leap_day() {
+        assert_eq!(stamp(1_709_164_799), "20240228-235959");
+        assert_eq!(stamp(1_709_208_000), "20240229-120000");
+        assert_eq!(stamp(1_709_251_200), "20240301-000000");
+        assert_eq!(stamp(951_782_400), "20000229-000000");
+        // 2100 is not a leap year.
+        assert_eq!(stamp(4_107_542_399), "21000228-235959");
+        assert_eq!(stamp(4_107_542_400), "21000301-000000");
+    }
+
+    #[test]
+    fn end_of_year() {
+        assert_eq!(stamp(1_704_067_199), "20231231-235959");
+        assert_eq!(stamp(1_704_067_200), "20240101-000000");
+        assert_eq!(stamp(1_735_632_309), "20241231-080509");
+    }
+}
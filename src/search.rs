@@ -0,0 +1,781 @@
+use eframe::egui;
+use regex::{Regex, RegexBuilder};
+use ropey::{str_utils, Rope};
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How finely scrollbar ticks are told apart.
+const TICK_BUCKETS: f32 = 1000.0;
+/// How often the worker hands over the matches it found so far.
+const BATCH_INTERVAL: Duration = Duration::from_millis(50);
+/// How many chars of a rope are copied out and searched at a time.
+const WINDOW_CHARS: usize = 1 << 20;
+/// How far past its window a match may run. Longer matches are cut short at
+/// the end of the searched text.
+const WINDOW_OVERLAP: usize = 64 * 1024;
+
+/// How the text in the find panel is matched. The options mean the same in
+/// plain and regex mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SearchOptions {
+    pub use_regex: bool,
+    pub match_case: bool,
+    pub whole_word: bool,
+}
+
+/// A compiled search. Plain text is escaped into a regex, so case and whole
+/// words are handled by the same engine in both modes.
+pub struct Search {
+    regex: Regex,
+    /// Whether `$1` and `${name}` in a replacement refer to groups. Plain
+    /// mode inserts the replacement as written.
+    expand: bool,
+}
+
+impl Search {
+    pub fn new(query: &str, options: SearchOptions) -> Result<Self, String> {
+        let pattern = if options.use_regex {
+            query.to_string()
+        } else {
+            regex::escape(query)
+        };
+        // Half boundaries only look outside the match, so a whole-word search
+        // for "x+" or "-x" still works where the query starts or ends with a
+        // non-word char.
+        let pattern = if options.whole_word {
+            format!(r"\b{{start-half}}(?:{pattern})\b{{end-half}}")
+        } else {
+            pattern
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!options.match_case)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            regex,
+            expand: options.use_regex,
+        })
+    }
+
+    /// The non-empty matches that start within the chars `range` of `rope`,
+    /// as char ranges, a window of text at a time.
+    pub fn rope_matches<'a>(&'a self, rope: &'a Rope, range: Range<usize>) -> RopeMatches<'a> {
+        let end = range.end.min(rope.len_chars());
+        RopeMatches {
+            search: self,
+            rope,
+            next: range.start.min(end),
+            end,
+        }
+    }
+
+    /// The first non-empty match that starts at or after char `from`, or
+    /// with `backwards` the last one that ends at or before it. Stops at the
+    /// first window with an answer.
+    pub fn find_in_rope(&self, rope: &Rope, from: usize, backwards: bool) -> Option<Range<usize>> {
+        if !backwards {
+            return self
+                .rope_matches(rope, from..rope.len_chars())
+                .find_map(|window| window.into_iter().next());
+        }
+        let mut end = from.min(rope.len_chars());
+        while end > 0 {
+            let start = end.saturating_sub(WINDOW_CHARS);
+            let last = self
+                .rope_matches(rope, start..end)
+                .flatten()
+                .take_while(|range| range.end <= from)
+                .last();
+            if last.is_some() {
+                return last;
+            }
+            end = start;
+        }
+        None
+    }
+
+    /// What the chars `range` become when they are exactly a match. The text
+    /// around it is searched as [`RopeMatches`] would, from a char before it
+    /// to well past its end, so anchors, word boundaries and a match that
+    /// runs on past `range` come out as they do in the whole text.
+    pub fn replace_match(
+        &self,
+        rope: &Rope,
+        range: Range<usize>,
+        replacement: &str,
+    ) -> Option<String> {
+        if range.end > rope.len_chars() {
+            return None;
+        }
+        let text_start = range.start.saturating_sub(1);
+        let text_end = (range.end + WINDOW_OVERLAP).min(rope.len_chars());
+        let text = rope.slice(text_start..text_end).to_string();
+        let base = rope.char_to_byte(text_start);
+        let bytes = rope.char_to_byte(range.start) - base..rope.char_to_byte(range.end) - base;
+        let captures = self.regex.captures_at(&text, bytes.start)?;
+        if captures.get(0)?.range() != bytes {
+            return None;
+        }
+        Some(self.expand(&captures, replacement))
+    }
+
+    fn expand(&self, captures: &regex::Captures, replacement: &str) -> String {
+        if !self.expand {
+            return replacement.to_string();
+        }
+        let mut expanded = String::new();
+        captures.expand(replacement, &mut expanded);
+        expanded
+    }
+}
+
+/// Matches of a search in a rope, one window of text per item. A window is
+/// searched with a little text on either side, so anchors, word boundaries
+/// and matches running past its end come out as they would in the whole
+/// text.
+pub struct RopeMatches<'a> {
+    search: &'a Search,
+    rope: &'a Rope,
+    /// Char where the next window starts.
+    next: usize,
+    end: usize,
+}
+
+impl Iterator for RopeMatches<'_> {
+    type Item = Vec<Range<usize>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let start = self.next;
+        let end = (start + WINDOW_CHARS).min(self.end);
+        let text_start = start.saturating_sub(1);
+        let text_end = (end + WINDOW_OVERLAP).min(self.rope.len_chars());
+        let text = self.rope.slice(text_start..text_end).to_string();
+        let base = self.rope.char_to_byte(text_start);
+        let until = self.rope.char_to_byte(end) - base;
+        self.next = end;
+
+        let mut ranges = Vec::new();
+        let mut at = self.rope.char_to_byte(start) - base;
+        // Matches come in order, so chars are counted on from the last one.
+        let (mut counted, mut chars) = (0, text_start);
+        let mut count_to = |to: usize| {
+            chars += str_utils::byte_to_char_idx(&text[counted..to], to - counted);
+            counted = to;
+            chars
+        };
+        while at <= text.len() {
+            let Some(found) = self.search.regex.find_at(&text, at) else {
+                break;
+            };
+            if found.start() >= until {
+                break;
+            }
+            if found.is_empty() {
+                at = found.end() + text[found.end()..].chars().next().map_or(1, char::len_utf8);
+                continue;
+            }
+            let range = count_to(found.start())..count_to(found.end());
+            // A match that runs into the next window hides what starts there.
+            self.next = self.next.max(range.end);
+            ranges.push(range);
+            at = found.end();
+        }
+        Some(ranges)
+    }
+}
+
+/// Matches of a search in one revision of a document, as sorted char
+/// ranges, for highlighting and "n of m".
+#[derive(Default)]
+pub struct MatchIndex {
+    pub ranges: Vec<Range<usize>>,
+    /// Where the matches are, as fractions of the line count, at most one per
+    /// thousandth of the document, for scrollbar ticks.
+    pub ticks: Vec<f32>,
+}
+
+impl MatchIndex {
+    /// Only the matches within `lines`, which is quick for the lines on
+    /// screen. Has no ticks.
+    pub fn build_lines(search: &Search, rope: &Rope, lines: Range<usize>) -> Self {
+        let last = rope.len_lines();
+        let start = rope.line_to_char(lines.start.min(last));
+        let end = rope.line_to_char(lines.end.min(last));
+        let ranges = search.rope_matches(rope, start..end).flatten().collect();
+        Self {
+            ranges,
+            ticks: Vec::new(),
+        }
+    }
+
+    /// Adds a match that comes after all the others, on the line that is
+    /// `line_fraction` of the way through the document.
+    fn push(&mut self, range: Range<usize>, line_fraction: f32) {
+        self.push_tick(line_fraction);
+        self.ranges.push(range);
+    }
+
+    /// Appends a batch of matches that come after these.
+    fn extend(&mut self, batch: MatchIndex) {
+        self.ranges.extend(batch.ranges);
+        for tick in batch.ticks {
+            self.push_tick(tick);
+        }
+    }
+
+    fn push_tick(&mut self, line_fraction: f32) {
+        let bucket = |fraction: f32| (fraction * TICK_BUCKETS) as u32;
+        if self.ticks.last().map(|last| bucket(*last)) != Some(bucket(line_fraction)) {
+            self.ticks.push(line_fraction);
+        }
+    }
+
+    /// The index of the match that is exactly `range`.
+    pub fn position(&self, range: &Range<usize>) -> Option<usize> {
+        let index = self
+            .ranges
+            .binary_search_by_key(&range.start, |found| found.start)
+            .ok()?;
+        (self.ranges[index] == *range).then_some(index)
+    }
+
+    /// The first match that starts at or after char `from`, or with
+    /// `backwards` the last one that ends at or before it.
+    pub fn find(&self, from: usize, backwards: bool) -> Option<Range<usize>> {
+        if backwards {
+            let end = self.ranges.partition_point(|range| range.end <= from);
+            return end.checked_sub(1).map(|index| self.ranges[index].clone());
+        }
+        let start = self.ranges.partition_point(|range| range.start < from);
+        self.ranges.get(start).cloned()
+    }
+}
+
+/// Keeps the last compiled search, so asking again for the same query and
+/// options does not compile it again.
+#[derive(Default)]
+pub struct CompiledSearch {
+    last: Option<(String, SearchOptions, Result<Arc<Search>, String>)>,
+}
+
+impl CompiledSearch {
+    pub fn get(&mut self, query: &str, options: SearchOptions) -> Result<Arc<Search>, String> {
+        match &self.last {
+            Some((last_query, last_options, result))
+                if last_query == query && *last_options == options =>
+            {
+                result.clone()
+            }
+            _ => {
+                let result = Search::new(query, options).map(Arc::new);
+                self.last = Some((query.to_string(), options, result.clone()));
+                result
+            }
+        }
+    }
+}
+
+/// What a match index is for: a tab at one revision and a search.
+#[derive(Clone, PartialEq)]
+pub struct SearchKey {
+    pub doc_id: u64,
+    pub revision: u64,
+    pub query: String,
+    pub options: SearchOptions,
+}
+
+/// The matches found so far for the newest request.
+pub struct Indexed {
+    pub key: SearchKey,
+    pub index: MatchIndex,
+    /// Whether the whole text has been searched.
+    pub complete: bool,
+}
+
+impl Indexed {
+    /// What `MatchIndex::find` answers, once the matches found so far are
+    /// enough to be sure: the index is complete, or its last match is past
+    /// `from`, so no match still to come can be the answer.
+    pub fn find(&self, from: usize, backwards: bool) -> Option<Option<Range<usize>>> {
+        let known = self.complete
+            || self.index.ranges.last().is_some_and(|last| {
+                if backwards {
+                    last.end >= from
+                } else {
+                    last.start >= from
+                }
+            });
+        known.then(|| self.index.find(from, backwards))
+    }
+
+    /// Every match, once the whole text has been searched.
+    pub fn all(&self) -> Option<&[Range<usize>]> {
+        self.complete.then_some(&self.index.ranges[..])
+    }
+}
+
+struct Job {
+    generation: u64,
+    ctx: egui::Context,
+    rope: Rope,
+    search: Arc<Search>,
+}
+
+struct Batch {
+    generation: u64,
+    index: MatchIndex,
+    complete: bool,
+}
+
+/// Indexes matches on a worker thread so typing in the find panel or in a
+/// large tab does not wait for the whole text to be searched. Each request
+/// cancels the one before it, and matches come back in batches while the
+/// search goes on.
+pub struct SearchService {
+    jobs: Sender<Job>,
+    batches: Receiver<Batch>,
+    /// Bumped by every request and by `cancel`; the worker stops searching
+    /// once it no longer matches the job's.
+    generation: Arc<AtomicU64>,
+    current: Option<Indexed>,
+}
+
+impl SearchService {
+    pub fn new() -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (batch_sender, batches) = mpsc::channel();
+        let generation = Arc::new(AtomicU64::new(0));
+        let worker_generation = generation.clone();
+        thread::spawn(move || index_matches(job_receiver, batch_sender, worker_generation));
+        Self {
+            jobs,
+            batches,
+            generation,
+            current: None,
+        }
+    }
+
+    /// Starts indexing the matches of `search` in `rope` unless that is done
+    /// or under way for `key` already.
+    pub fn request(
+        &mut self,
+        ctx: &egui::Context,
+        key: SearchKey,
+        rope: &Rope,
+        search: Arc<Search>,
+    ) {
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|current| current.key == key)
+        {
+            return;
+        }
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed) + 1;
+        self.current = Some(Indexed {
+            key,
+            index: MatchIndex::default(),
+            complete: false,
+        });
+        let _ = self.jobs.send(Job {
+            generation,
+            ctx: ctx.clone(),
+            rope: rope.clone(),
+            search,
+        });
+    }
+
+    /// Stops the running search and forgets its matches.
+    pub fn cancel(&mut self) {
+        if self.current.take().is_some() {
+            self.generation.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes in the batches the worker sent since the last frame.
+    pub fn poll(&mut self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        while let Ok(batch) = self.batches.try_recv() {
+            if batch.generation != generation {
+                continue;
+            }
+            if let Some(current) = &mut self.current {
+                current.index.extend(batch.index);
+                current.complete = batch.complete;
+            }
+        }
+    }
+
+    /// The matches found so far, if they are for `key`.
+    pub fn indexed(&self, key: &SearchKey) -> Option<&Indexed> {
+        self.current.as_ref().filter(|current| current.key == *key)
+    }
+}
+
+/// The worker: searches each job's text, skipping jobs that were replaced
+/// before it got to them.
+fn index_matches(jobs: Receiver<Job>, batches: Sender<Batch>, generation: Arc<AtomicU64>) {
+    while let Ok(mut job) = jobs.recv() {
+        while let Ok(newer) = jobs.try_recv() {
+            job = newer;
+        }
+        let cancelled = || generation.load(Ordering::Relaxed) != job.generation;
+        if cancelled() {
+            continue;
+        }
+        let rope = &job.rope;
+        let line_count = rope.len_lines() as f32;
+        let mut batch = MatchIndex::default();
+        let mut sent = Instant::now();
+        for window in job.search.rope_matches(rope, 0..rope.len_chars()) {
+            if cancelled() {
+                break;
+            }
+            for range in window {
+                let line = rope.char_to_line(range.start);
+                batch.push(range, line as f32 / line_count);
+            }
+            if sent.elapsed() >= BATCH_INTERVAL {
+                let _ = batches.send(Batch {
+                    generation: job.generation,
+                    index: std::mem::take(&mut batch),
+                    complete: false,
+                });
+                job.ctx.request_repaint();
+                sent = Instant::now();
+            }
+        }
+        if cancelled() {
+            continue;
+        }
+        let _ = batches.send(Batch {
+            generation: job.generation,
+            index: batch,
+            complete: true,
+        });
+        job.ctx.request_repaint();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(query: &str, options: SearchOptions) -> Search {
+        Search::new(query, options).unwrap()
+    }
+
+    fn regex(query: &str) -> Search {
+        search(
+            query,
+            SearchOptions {
+                use_regex: true,
+                match_case: true,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn all(search: &Search, rope: &Rope) -> Vec<Range<usize>> {
+        search
+            .rope_matches(rope, 0..rope.len_chars())
+            .flatten()
+            .collect()
+    }
+
+    /// The index the background search builds for `rope`.
+    fn index(search: &Search, rope: &Rope) -> MatchIndex {
+        let mut index = MatchIndex::default();
+        let line_count = rope.len_lines() as f32;
+        for range in all(search, rope) {
+            let line = rope.char_to_line(range.start);
+            index.push(range, line as f32 / line_count);
+        }
+        index
+    }
+
+    #[test]
+    fn plain_text_is_not_a_pattern() {
+        let rope = Rope::from_str("abc a.c A.C");
+        let plain = search("a.c", SearchOptions::default());
+        assert_eq!(all(&plain, &rope), [4..7, 8..11]);
+        let match_case = search(
+            "a.c",
+            SearchOptions {
+                match_case: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(all(&match_case, &rope), vec![4..7]);
+        assert_eq!(
+            plain.replace_match(&rope, 4..7, "$0").as_deref(),
+            Some("$0")
+        );
+    }
+
+    #[test]
+    fn whole_words_allow_symbols_at_the_edges() {
+        let options = SearchOptions {
+            whole_word: true,
+            ..Default::default()
+        };
+        let rope = Rope::from_str("cat concat cats");
+        assert_eq!(all(&search("cat", options), &rope), vec![0..3]);
+        let rope = Rope::from_str("a -x -xy");
+        assert_eq!(all(&search("-x", options), &rope), vec![2..4]);
+    }
+
+    #[test]
+    fn an_invalid_pattern_is_an_error() {
+        assert!(Search::new(
+            "(",
+            SearchOptions {
+                use_regex: true,
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+    #[test]
+    fn windows_find_what_a_whole_text_search_finds() {
+        // Multi-byte chars make char and byte offsets differ, and a match
+        // straddles the first window boundary.
+        let mut text = "é".repeat(WINDOW_CHARS - 3);
+        text.push_str("needle needle\n");
+        text.push_str(&"ü needle ".repeat(1000));
+        let rope = Rope::from_str(&text);
+        let search = regex("needle");
+        let expected = search
+            .regex
+            .find_iter(&text)
+            .map(|found| {
+                str_utils::byte_to_char_idx(&text, found.start())
+                    ..str_utils::byte_to_char_idx(&text, found.end())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(expected.len(), 1002);
+        assert_eq!(all(&search, &rope), expected);
+    }
+
+    #[test]
+    fn a_match_running_past_its_window_is_found_once() {
+        let rope = Rope::from_str(&"a".repeat(WINDOW_CHARS + 10));
+        assert_eq!(all(&regex("a+"), &rope), vec![0..WINDOW_CHARS + 10]);
+    }
+
+    #[test]
+    fn anchors_and_word_boundaries_see_the_text_before_a_window() {
+        let mut text = "x".repeat(WINDOW_CHARS);
+        text.push_str("word x\nx");
+        let rope = Rope::from_str(&text);
+        assert!(all(&regex("^word"), &rope).is_empty());
+        let whole_word = search(
+            "word",
+            SearchOptions {
+                whole_word: true,
+                ..Default::default()
+            },
+        );
+        assert!(all(&whole_word, &rope).is_empty());
+        assert_eq!(
+            all(&regex("x$"), &rope),
+            vec![WINDOW_CHARS + 7..WINDOW_CHARS + 8]
+        );
+    }
+
+    #[test]
+    fn empty_matches_are_skipped() {
+        let rope = Rope::from_str("ab ab");
+        assert_eq!(all(&regex("b*"), &rope), vec![1..2, 4..5]);
+    }
+
+    #[test]
+    fn find_in_rope_goes_either_way_from_a_char() {
+        let rope = Rope::from_str("cat dog cat dog");
+        let search = regex("cat");
+        assert_eq!(search.find_in_rope(&rope, 0, false), Some(0..3));
+        assert_eq!(search.find_in_rope(&rope, 1, false), Some(8..11));
+        assert_eq!(search.find_in_rope(&rope, 9, false), None);
+        assert_eq!(search.find_in_rope(&rope, 15, true), Some(8..11));
+        assert_eq!(search.find_in_rope(&rope, 10, true), Some(0..3));
+        assert_eq!(search.find_in_rope(&rope, 2, true), None);
+    }
+
+    #[test]
+    fn replace_match_needs_exactly_a_match() {
+        let rope = Rope::from_str("key=value keys=v");
+        let search = regex(r"(\w+)=(\w+)");
+        assert_eq!(
+            search.replace_match(&rope, 0..9, "$2=$1"),
+            Some("value=key".to_string())
+        );
+        // Part of a longer match, and not a match at all.
+        assert_eq!(search.replace_match(&rope, 0..5, "$2=$1"), None);
+        assert_eq!(search.replace_match(&rope, 3..9, "x"), None);
+    }
+
+    #[test]
+    fn replace_match_sees_a_match_run_on_past_the_range() {
+        let rope = Rope::from_str("abcd");
+        assert_eq!(regex("ab(cd)?").replace_match(&rope, 0..2, "x"), None);
+        assert_eq!(
+            regex("ab(cd)?").replace_match(&rope, 0..4, "x").as_deref(),
+            Some("x")
+        );
+        // Well past the char after it, too.
+        let rope = Rope::from_str(&format!("ab{}", "c".repeat(1000)));
+        assert_eq!(regex("abc*").replace_match(&rope, 0..3, "x"), None);
+        assert_eq!(
+            regex("abc*").replace_match(&rope, 0..1002, "x").as_deref(),
+            Some("x")
+        );
+        // Lines are no limit either.
+        let rope = Rope::from_str("a\nb\nc");
+        assert_eq!(regex("(?s)a.*").replace_match(&rope, 0..2, "x"), None);
+    }
+
+    #[test]
+    fn replace_match_judges_word_boundaries_in_context() {
+        let rope = Rope::from_str("cat concat");
+        let whole_word = search(
+            "cat",
+            SearchOptions {
+                whole_word: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            whole_word.replace_match(&rope, 0..3, "$1"),
+            Some("$1".into())
+        );
+        assert_eq!(whole_word.replace_match(&rope, 7..10, "dog"), None);
+        assert_eq!(
+            regex("^b").replace_match(&Rope::from_str("ab"), 1..2, "x"),
+            None
+        );
+    }
+
+    #[test]
+    fn n_of_m_is_the_selected_match() {
+        let rope = Rope::from_str("cät dog cät dog cät");
+        let index = index(&regex("cät"), &rope);
+        // Char ranges, not byte ranges.
+        assert_eq!(index.ranges, [0..3, 8..11, 16..19]);
+        assert_eq!(index.position(&(8..11)), Some(1));
+        assert_eq!(index.position(&(16..19)), Some(2));
+        // A selection that is not exactly a match has no number.
+        assert_eq!(index.position(&(8..10)), None);
+        assert_eq!(index.position(&(4..7)), None);
+    }
+
+    #[test]
+    fn n_of_m_follows_edits() {
+        let search = regex("cat");
+        let mut rope = Rope::from_str("cat dog cat");
+        assert_eq!(index(&search, &rope).position(&(8..11)), Some(1));
+
+        // A new match before it moves it down the list and along the text.
+        rope.insert(4, "cat ");
+        let after = index(&search, &rope);
+        assert_eq!(after.ranges.len(), 3);
+        assert_eq!(after.position(&(12..15)), Some(2));
+        assert_eq!(after.position(&(8..11)), None);
+
+        // Breaking a match takes it out of the count.
+        rope.remove(1..2);
+        let after = index(&search, &rope);
+        assert_eq!(after.ranges, [3..6, 11..14]);
+        assert_eq!(after.position(&(11..14)), Some(1));
+    }
+
+    #[test]
+    fn n_of_m_counts_across_window_boundaries() {
+        // Matches on both sides of the first window boundary and one
+        // straddling it, after multibyte chars.
+        let mut text = "ü".repeat(WINDOW_CHARS - 6);
+        text.push_str(" cat cat cat cat");
+        let rope = Rope::from_str(&text);
+        let index = index(&regex("cat"), &rope);
+        let start = WINDOW_CHARS - 5;
+        assert_eq!(
+            index.ranges,
+            [
+                start..start + 3,
+                start + 4..start + 7,
+                start + 8..start + 11,
+                start + 12..start + 15
+            ]
+        );
+        assert_eq!(index.position(&(start + 4..start + 7)), Some(1));
+        assert_eq!(index.position(&(start + 8..start + 11)), Some(2));
+    }
+
+    #[test]
+    fn a_window_of_lines_matches_what_the_whole_text_does() {
+        let rope = Rope::from_str(&"ä cat\n".repeat(20));
+        let search = regex("cat");
+        let whole = index(&search, &rope);
+        let window = MatchIndex::build_lines(&search, &rope, 5..8);
+        let expected = whole
+            .ranges
+            .iter()
+            .filter(|range| (5..8).contains(&rope.char_to_line(range.start)))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(window.ranges, expected);
+        // Numbers in a window are counted from the window's first match.
+        assert_eq!(window.position(&expected[1]), Some(1));
+        assert!(window.ticks.is_empty());
+        // A window past the end of the text is cut short.
+        assert_eq!(
+            MatchIndex::build_lines(&search, &rope, 19..40).ranges.len(),
+            1
+        );
+        assert!(MatchIndex::build_lines(&search, &rope, 30..40)
+            .ranges
+            .is_empty());
+    }
+
+    #[test]
+    fn ticks_are_thinned_to_one_per_bucket() {
+        let rope = Rope::from_str(&"x\n".repeat(5000));
+        let index = index(&regex("x"), &rope);
+        assert_eq!(index.ranges.len(), 5000);
+        assert_eq!(index.ticks.len(), 1000);
+        assert_eq!(index.ticks[0], 0.0);
+    }
+
+    #[test]
+    fn partial_index_answers_only_what_it_has_reached() {
+        let indexed = Indexed {
+            key: SearchKey {
+                doc_id: 1,
+                revision: 0,
+                query: String::new(),
+                options: SearchOptions::default(),
+            },
+            index: MatchIndex {
+                ranges: vec![2..4, 10..12],
+                ticks: Vec::new(),
+            },
+            complete: false,
+        };
+        assert_eq!(indexed.find(5, false), Some(Some(10..12)));
+        assert_eq!(indexed.find(11, true), Some(Some(2..4)));
+        assert_eq!(indexed.find(20, false), None);
+        assert_eq!(indexed.find(20, true), None);
+        assert_eq!(indexed.all(), None);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{
+    document::Selection,
+    paths,
+    save::{self, SaveOptions},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+const SESSION_FILE: &str = "session.json";
+
+/// The tabs and find panel as they were when plainpad last quit, and the
+/// files opened lately.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub tabs: Vec<SessionTab>,
+    pub active: usize,
+    pub find: FindSession,
+    /// Most recent first. Kept even when tabs are not reopened.
+    pub recent: Vec<PathBuf>,
+}
+
+/// A tab backed by a file. Untitled tabs are not part of the session; the
+/// recovery journal covers unsaved text. Only the path is required; a tab
+/// without the rest opens at the top.
+#[derive(Serialize, Deserialize)]
+pub struct SessionTab {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub selection: Selection,
+    #[serde(default)]
+    pub top_line: f64,
+    #[serde(default)]
+    pub scroll_x: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct FindSession {
+    pub open: bool,
+    pub query: String,
+    pub replace: String,
+    pub use_regex: bool,
+    pub match_case: bool,
+    pub whole_word: bool,
+    pub backwards: bool,
+    pub wrap_around: bool,
+    /// Whether search results are docked on the right instead of the bottom.
+    pub results_on_right: bool,
+}
+
+impl Default for FindSession {
+    fn default() -> Self {
+        Self {
+            open: false,
+            query: String::new(),
+            replace: String::new(),
+            use_regex: false,
+            match_case: false,
+            whole_word: false,
+            backwards: false,
+            wrap_around: true,
+            results_on_right: false,
+        }
+    }
+}
+
+impl Session {
+    /// Reads the saved session. A missing or unreadable file gives the
+    /// default, which restores nothing.
+    pub fn load() -> Self {
+        paths::state_dir()
+            .and_then(|dir| fs::read(dir.join(SESSION_FILE)).ok())
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let dir = paths::state_dir()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no local data folder"))?;
+        fs::create_dir_all(&dir)?;
+        let json = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        save::write_atomic(&dir.join(SESSION_FILE), &SaveOptions::default(), |writer| {
+            writer.write_all(&json)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Session {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let session = Session {
+            tabs: vec![SessionTab {
+                path: PathBuf::from("/notes/todo.txt"),
+                selection: Selection { anchor: 4, head: 9 },
+                top_line: 12.5,
+                scroll_x: 30.0,
+            }],
+            active: 0,
+            find: FindSession {
+                open: true,
+                query: "fo+".to_string(),
+                replace: "bar".to_string(),
+                use_regex: true,
+                match_case: true,
+                whole_word: false,
+                backwards: true,
+                wrap_around: false,
+                results_on_right: true,
+            },
+            recent: vec![PathBuf::from("/notes/todo.txt"), PathBuf::from("/a.txt")],
+        };
+        let restored = parse(&serde_json::to_string(&session).unwrap());
+        assert_eq!(restored.tabs.len(), 1);
+        assert_eq!(restored.recent, session.recent);
+        let tab = &restored.tabs[0];
+        assert_eq!(tab.path, PathBuf::from("/notes/todo.txt"));
+        assert_eq!(tab.selection, Selection { anchor: 4, head: 9 });
+        assert_eq!((tab.top_line, tab.scroll_x), (12.5, 30.0));
+        assert!(restored.find.open && restored.find.use_regex && restored.find.match_case);
+        assert!(restored.find.backwards && !restored.find.wrap_around);
+        assert!(restored.find.results_on_right);
+        assert_eq!(
+            (restored.find.query.as_str(), restored.find.replace.as_str()),
+            ("fo+", "bar")
+        );
+    }
+
+    #[test]
+    fn missing_fields_take_their_defaults() {
+        let session = parse("{}");
+        assert!(session.tabs.is_empty());
+        assert_eq!(session.active, 0);
+        assert!(!session.find.open);
+        assert!(session.recent.is_empty());
+
+        let session = parse(r#"{"active": 1, "find": {"query": "todo"}, "extra": true}"#);
+        assert_eq!(session.active, 1);
+        assert_eq!(session.find.query, "todo");
+        assert!(session.find.replace.is_empty() && !session.find.use_regex);
+        assert!(session.find.wrap_around && !session.find.backwards);
+        assert!(!session.find.results_on_right);
+    }
+
+    #[test]
+    fn a_tab_needs_only_its_path() {
+        let session = parse(r#"{"tabs": [{"path": "a.txt"}]}"#);
+        let tab = &session.tabs[0];
+        assert_eq!(tab.path, PathBuf::from("a.txt"));
+        assert_eq!(tab.selection, Selection::default());
+        assert_eq!((tab.top_line, tab.scroll_x), (0.0, 0.0));
+        assert!(serde_json::from_str::<Session>(r#"{"tabs": [{"top_line": 3}]}"#).is_err());
+    }
+}
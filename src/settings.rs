@@ -0,0 +1,423 @@
+use crate::{
+    paths,
+    save::{self, BackupMode, SaveOptions},
+    ui::editor_view::WordWrap,
+};
+use eframe::egui::{FontFamily, FontId};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const SETTINGS_FILE: &str = "settings.toml";
+/// Bumped when a setting changes meaning, so older files can be migrated.
+pub const SETTINGS_VERSION: u32 = 1;
+
+pub const FONT_SIZES: std::ops::RangeInclusive<f32> = 6.0..=72.0;
+pub const TAB_WIDTHS: std::ops::RangeInclusive<usize> = 1..=16;
+pub const WRAP_COLUMNS: std::ops::RangeInclusive<usize> = 10..=1000;
+
+/// Everything the user can change in Preferences, kept in `settings.toml` in
+/// the platform config folder.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub editor: EditorSettings,
+    pub view: ViewSettings,
+    pub files: FileSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EditorSettings {
+    pub font: FontChoice,
+    pub font_size: f32,
+    /// Columns between tab stops.
+    pub tab_width: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewSettings {
+    pub theme: Theme,
+    pub word_wrap: WrapChoice,
+    /// Used when `word_wrap` is `column`.
+    pub wrap_column: usize,
+    pub line_numbers: bool,
+    pub status_bar: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileSettings {
+    /// Offered by Save As for new files, without the dot.
+    pub default_extension: String,
+    pub backup: Backup,
+    /// Where timestamped backup copies go.
+    pub backup_folder: Option<PathBuf>,
+    /// Idle seconds before tabs with a file are saved; 0 turns autosave off.
+    pub autosave_seconds: u64,
+    pub reopen_tabs: bool,
+    /// Undo history is stored for files when their tabs close and picked up
+    /// when they are opened again.
+    pub keep_undo_history: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FontChoice {
+    Monospace,
+    Proportional,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Follows the system where eframe can tell, dark otherwise.
+    System,
+    Light,
+    Dark,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WrapChoice {
+    Off,
+    Window,
+    Column,
+}
+
+/// Whether a copy of the previous file is kept when it is overwritten.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backup {
+    Off,
+    Sibling,
+    Timestamped,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: SETTINGS_VERSION,
+            editor: EditorSettings::default(),
+            view: ViewSettings::default(),
+            files: FileSettings::default(),
+        }
+    }
+}
+
+impl Default for EditorSettings {
+    fn default() -> Self {
+        Self {
+            font: FontChoice::Monospace,
+            font_size: 14.0,
+            tab_width: 4,
+        }
+    }
+}
+
+impl Default for ViewSettings {
+    fn default() -> Self {
+        Self {
+            theme: Theme::System,
+            word_wrap: WrapChoice::Off,
+            wrap_column: 80,
+            line_numbers: false,
+            status_bar: true,
+        }
+    }
+}
+
+impl Default for FileSettings {
+    fn default() -> Self {
+        Self {
+            default_extension: "txt".to_string(),
+            backup: Backup::Off,
+            backup_folder: None,
+            autosave_seconds: 0,
+            reopen_tabs: true,
+            keep_undo_history: false,
+        }
+    }
+}
+
+/// `extension` without a leading dot, if it is a single file extension.
+pub fn valid_extension(extension: &str) -> Option<&str> {
+    let extension = extension.trim_start_matches('.');
+    let invalid = extension.is_empty() || extension.contains(['/', '\\', '.', ' ']);
+    (!invalid).then_some(extension)
+}
+
+impl Settings {
+    pub fn path() -> Option<PathBuf> {
+        paths::config_dir().map(|dir| dir.join(SETTINGS_FILE))
+    }
+
+    /// Reads the settings file. A missing file gives the defaults. A file
+    /// that cannot be read gives the defaults, and values that are out of
+    /// range fall back to their default; each problem is described in the
+    /// returned list.
+    pub fn load() -> (Self, Vec<String>) {
+        let Some(path) = Self::path() else {
+            return (Self::default(), Vec::new());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return (Self::default(), Vec::new())
+            }
+            Err(err) => {
+                return (
+                    Self::default(),
+                    vec![format!("Failed to read {}: {err}", path.display())],
+                )
+            }
+        };
+        Self::parse(&text, &path)
+    }
+
+    /// Reads settings from the text of the file at `path`, which only names
+    /// the file in the problems found.
+    fn parse(text: &str, path: &Path) -> (Self, Vec<String>) {
+        match toml::from_str::<Self>(text) {
+            Ok(settings) => settings.validated(),
+            Err(err) => (
+                Self::default(),
+                vec![format!(
+                    "{} is not valid, so the defaults are used:\n{err}",
+                    path.display()
+                )],
+            ),
+        }
+    }
+
+    /// Replaces values that are out of range with their defaults.
+    fn validated(mut self) -> (Self, Vec<String>) {
+        let defaults = Self::default();
+        let mut problems = Vec::new();
+        if self.version > SETTINGS_VERSION {
+            problems.push(format!(
+                "`version` is {}, but this plainpad understands up to {SETTINGS_VERSION}; settings it does not know are ignored.",
+                self.version
+            ));
+        }
+        self.version = SETTINGS_VERSION;
+        if !FONT_SIZES.contains(&self.editor.font_size) {
+            problems.push(format!(
+                "`editor.font_size` must be between {} and {}; using {}.",
+                FONT_SIZES.start(),
+                FONT_SIZES.end(),
+                defaults.editor.font_size
+            ));
+            self.editor.font_size = defaults.editor.font_size;
+        }
+        if !TAB_WIDTHS.contains(&self.editor.tab_width) {
+            problems.push(format!(
+                "`editor.tab_width` must be between {} and {}; using {}.",
+                TAB_WIDTHS.start(),
+                TAB_WIDTHS.end(),
+                defaults.editor.tab_width
+            ));
+            self.editor.tab_width = defaults.editor.tab_width;
+        }
+        if !WRAP_COLUMNS.contains(&self.view.wrap_column) {
+            problems.push(format!(
+                "`view.wrap_column` must be between {} and {}; using {}.",
+                WRAP_COLUMNS.start(),
+                WRAP_COLUMNS.end(),
+                defaults.view.wrap_column
+            ));
+            self.view.wrap_column = defaults.view.wrap_column;
+        }
+        if let Some(extension) = valid_extension(&self.files.default_extension) {
+            self.files.default_extension = extension.to_string();
+        } else {
+            problems.push(format!(
+                "`files.default_extension` must be a single extension such as \"txt\"; using \"{}\".",
+                defaults.files.default_extension
+            ));
+            self.files.default_extension = defaults.files.default_extension;
+        }
+        if self.files.backup == Backup::Timestamped && self.files.backup_folder.is_none() {
+            problems.push(
+                "`files.backup` is \"timestamped\" but `files.backup_folder` is not set; backups are off."
+                    .to_string(),
+            );
+            self.files.backup = Backup::Off;
+        }
+        (self, problems)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config folder"))?;
+        let body = toml::to_string_pretty(self).map_err(io::Error::other)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        save::write_atomic(&path, &SaveOptions::default(), |writer| {
+            writer
+                .write_all(b"# plainpad settings. Edits are picked up while plainpad runs.\n\n")?;
+            writer.write_all(body.as_bytes())
+        })
+    }
+
+    pub fn font_id(&self) -> FontId {
+        let family = match self.editor.font {
+            FontChoice::Monospace => FontFamily::Monospace,
+            FontChoice::Proportional => FontFamily::Proportional,
+        };
+        FontId::new(self.editor.font_size, family)
+    }
+
+    pub fn word_wrap(&self) -> WordWrap {
+        match self.view.word_wrap {
+            WrapChoice::Off => WordWrap::Off,
+            WrapChoice::Window => WordWrap::WindowEdge,
+            WrapChoice::Column => WordWrap::Column(self.view.wrap_column),
+        }
+    }
+
+    pub fn set_word_wrap(&mut self, wrap: WordWrap) {
+        self.view.word_wrap = match wrap {
+            WordWrap::Off => WrapChoice::Off,
+            WordWrap::WindowEdge => WrapChoice::Window,
+            WordWrap::Column(column) => {
+                self.view.wrap_column = column;
+                WrapChoice::Column
+            }
+        };
+    }
+
+    pub fn autosave_secs(&self) -> Option<u64> {
+        (self.files.autosave_seconds > 0).then_some(self.files.autosave_seconds)
+    }
+
+    pub fn save_options(&self) -> SaveOptions {
+        let backup = match (self.files.backup, &self.files.backup_folder) {
+            (Backup::Sibling, _) => BackupMode::Sibling,
+            (Backup::Timestamped, Some(dir)) => BackupMode::Timestamped(dir.clone()),
+            _ => BackupMode::Off,
+        };
+        SaveOptions { backup }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> (Settings, Vec<String>) {
+        Settings::parse(text, Path::new("/config/settings.toml"))
+    }
+
+    #[test]
+    fn an_empty_file_gives_the_defaults() {
+        let (settings, problems) = parse("");
+        assert_eq!(settings, Settings::default());
+        assert!(problems.is_empty());
+        assert_eq!(settings.editor.font_size, 14.0);
+        assert_eq!(settings.view.wrap_column, 80);
+        assert_eq!(settings.files.default_extension, "txt");
+        assert!(settings.files.reopen_tabs && !settings.files.keep_undo_history);
+    }
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let (settings, problems) = parse("[view]\nline_numbers = true\n");
+        assert!(problems.is_empty());
+        assert!(settings.view.line_numbers);
+        assert_eq!(settings.view.theme, Theme::System);
+        assert_eq!(settings.editor, EditorSettings::default());
+    }
+
+    #[test]
+    fn a_file_that_is_not_valid_gives_the_defaults() {
+        for text in ["[editor\nfont_size = 12", "[editor]\nfont_size = \"big\"\n"] {
+            let (settings, problems) = parse(text);
+            assert_eq!(settings, Settings::default());
+            assert_eq!(problems.len(), 1);
+            assert!(problems[0]
+                .starts_with("/config/settings.toml is not valid, so the defaults are used:\n"));
+        }
+    }
+
+    #[test]
+    fn a_newer_version_is_read_with_a_warning() {
+        let (settings, problems) =
+            parse("version = 7\n[editor]\ntab_width = 8\nligatures = true\n");
+        assert_eq!(settings.version, SETTINGS_VERSION);
+        assert_eq!(settings.editor.tab_width, 8);
+        assert_eq!(
+            problems,
+            [format!(
+                "`version` is 7, but this plainpad understands up to {SETTINGS_VERSION}; settings it does not know are ignored."
+            )]
+        );
+    }
+
+    #[test]
+    fn out_of_range_values_fall_back_one_by_one() {
+        let (settings, problems) = parse(
+            "[editor]\nfont_size = 200.0\ntab_width = 0\n\n\
+             [view]\nwrap_column = 5\nline_numbers = true\n\n\
+             [files]\ndefault_extension = \"tar.gz\"\nautosave_seconds = 30\n",
+        );
+        assert_eq!(
+            problems,
+            [
+                "`editor.font_size` must be between 6 and 72; using 14.",
+                "`editor.tab_width` must be between 1 and 16; using 4.",
+                "`view.wrap_column` must be between 10 and 1000; using 80.",
+                "`files.default_extension` must be a single extension such as \"txt\"; using \"txt\".",
+            ]
+        );
+        assert_eq!(settings.editor, EditorSettings::default());
+        assert_eq!(settings.view.wrap_column, 80);
+        assert_eq!(settings.files.default_extension, "txt");
+        // Valid values next to them are kept.
+        assert!(settings.view.line_numbers);
+        assert_eq!(settings.autosave_secs(), Some(30));
+    }
+
+    #[test]
+    fn the_extension_loses_its_dot() {
+        let (settings, problems) = parse("[files]\ndefault_extension = \".md\"\n");
+        assert!(problems.is_empty());
+        assert_eq!(settings.files.default_extension, "md");
+    }
+
+    #[test]
+    fn timestamped_backups_need_a_folder() {
+        let (settings, problems) = parse("[files]\nbackup = \"timestamped\"\n");
+        assert_eq!(settings.files.backup, Backup::Off);
+        assert_eq!(
+            problems,
+            ["`files.backup` is \"timestamped\" but `files.backup_folder` is not set; backups are off."]
+        );
+
+        let (settings, problems) =
+            parse("[files]\nbackup = \"timestamped\"\nbackup_folder = \"/backups\"\n");
+        assert!(problems.is_empty());
+        assert!(matches!(
+            settings.save_options().backup,
+            BackupMode::Timestamped(dir) if dir == Path::new("/backups")
+        ));
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let mut settings = Settings::default();
+        settings.set_word_wrap(WordWrap::Column(100));
+        settings.files.backup = Backup::Sibling;
+        let text = toml::to_string_pretty(&settings).unwrap();
+        let (parsed, problems) = parse(&text);
+        assert!(problems.is_empty());
+        assert_eq!(parsed, settings);
+        assert_eq!(parsed.word_wrap(), WordWrap::Column(100));
+    }
+}
@@ -1,90 +1,531 @@
-use eframe::egui;
+use crate::{keymap::Keymap, line_ending::LineEnding, settings::Backup, ui::editor_view::WordWrap};
+use eframe::egui::{self, Event, Key, Modifiers};
+use std::fmt;
 
-#[derive(Debug, Clone, Copy)]
+/// A command that can be bound to keys and run from the command palette.
+/// Each has a stable id, which is how `keymap.toml` names it, and a name
+/// for people.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum ShortcutCommand {
     NewTab,
     Open,
     Print,
     Save,
     SaveAs,
+    SaveAll,
+    SetBackup(Backup),
+    SetAutosave(Option<u64>),
+    ToggleRestoreSession,
     CloseTab,
+    CloseAll,
+    CloseTabsLeft,
+    CloseTabsRight,
+    Quit,
+    ForceQuit,
+    Undo,
+    Redo,
+    Cut,
+    Copy,
+    Paste,
+    SelectAll,
+    AddNextOccurrence,
+    SelectAllOccurrences,
+    Find,
+    FindNext,
+    FindPrevious,
+    FindInTabs,
+    FindInFiles,
+    Replace,
+    GoTo,
+    ConvertLineEndings(LineEnding),
+    ToggleReadOnly,
+    ToggleKeepUndoHistory,
+    Preferences,
+    KeyboardShortcuts,
+    CommandPalette,
+    ToggleStatusBar,
+    ToggleLineNumbers,
+    SetWordWrap(WordWrap),
     NextTab,
     PreviousTab,
     SelectTab(usize),
     SelectLastTab,
-    Find,
-    Replace,
-    Quit,
+    CheckForUpdates,
 }
 
-pub fn detect(ctx: &egui::Context) -> Option<ShortcutCommand> {
-    let input = ctx.input(|input| input.clone());
+impl ShortcutCommand {
+    /// Every command, in the order they are listed for the user.
+    pub const ALL: [Self; 62] = [
+        Self::NewTab,
+        Self::Open,
+        Self::Print,
+        Self::Save,
+        Self::SaveAs,
+        Self::SaveAll,
+        Self::SetBackup(Backup::Off),
+        Self::SetBackup(Backup::Sibling),
+        Self::SetBackup(Backup::Timestamped),
+        Self::SetAutosave(None),
+        Self::SetAutosave(Some(5)),
+        Self::SetAutosave(Some(30)),
+        Self::SetAutosave(Some(60)),
+        Self::ToggleRestoreSession,
+        Self::CloseTab,
+        Self::CloseAll,
+        Self::CloseTabsLeft,
+        Self::CloseTabsRight,
+        Self::Quit,
+        Self::ForceQuit,
+        Self::Undo,
+        Self::Redo,
+        Self::Cut,
+        Self::Copy,
+        Self::Paste,
+        Self::SelectAll,
+        Self::AddNextOccurrence,
+        Self::SelectAllOccurrences,
+        Self::Find,
+        Self::FindNext,
+        Self::FindPrevious,
+        Self::FindInTabs,
+        Self::FindInFiles,
+        Self::Replace,
+        Self::GoTo,
+        Self::ConvertLineEndings(LineEnding::Crlf),
+        Self::ConvertLineEndings(LineEnding::Lf),
+        Self::ConvertLineEndings(LineEnding::Cr),
+        Self::ToggleReadOnly,
+        Self::ToggleKeepUndoHistory,
+        Self::Preferences,
+        Self::KeyboardShortcuts,
+        Self::CommandPalette,
+        Self::ToggleStatusBar,
+        Self::ToggleLineNumbers,
+        Self::SetWordWrap(WordWrap::Off),
+        Self::SetWordWrap(WordWrap::WindowEdge),
+        Self::SetWordWrap(WordWrap::Column(80)),
+        Self::SetWordWrap(WordWrap::Column(100)),
+        Self::SetWordWrap(WordWrap::Column(120)),
+        Self::NextTab,
+        Self::PreviousTab,
+        Self::SelectTab(0),
+        Self::SelectTab(1),
+        Self::SelectTab(2),
+        Self::SelectTab(3),
+        Self::SelectTab(4),
+        Self::SelectTab(5),
+        Self::SelectTab(6),
+        Self::SelectTab(7),
+        Self::SelectLastTab,
+        Self::CheckForUpdates,
+    ];
 
-    if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::S) {
-        return Some(ShortcutCommand::SaveAs);
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::NewTab => "file.new_tab",
+            Self::Open => "file.open",
+            Self::Print => "file.print",
+            Self::Save => "file.save",
+            Self::SaveAs => "file.save_as",
+            Self::SaveAll => "file.save_all",
+            Self::SetBackup(Backup::Off) => "file.backup_off",
+            Self::SetBackup(Backup::Sibling) => "file.backup_sibling",
+            Self::SetBackup(Backup::Timestamped) => "file.backup_timestamped",
+            Self::SetAutosave(None) => "file.autosave_off",
+            Self::SetAutosave(Some(5)) => "file.autosave_5",
+            Self::SetAutosave(Some(30)) => "file.autosave_30",
+            Self::SetAutosave(Some(_)) => "file.autosave_60",
+            Self::ToggleRestoreSession => "file.toggle_reopen_tabs",
+            Self::CloseTab => "file.close_tab",
+            Self::CloseAll => "file.close_all",
+            Self::CloseTabsLeft => "file.close_tabs_left",
+            Self::CloseTabsRight => "file.close_tabs_right",
+            Self::Quit => "file.quit",
+            Self::ForceQuit => "file.force_quit",
+            Self::Undo => "edit.undo",
+            Self::Redo => "edit.redo",
+            Self::Cut => "edit.cut",
+            Self::Copy => "edit.copy",
+            Self::Paste => "edit.paste",
+            Self::SelectAll => "edit.select_all",
+            Self::AddNextOccurrence => "edit.add_next_occurrence",
+            Self::SelectAllOccurrences => "edit.select_all_occurrences",
+            Self::Find => "edit.find",
+            Self::FindNext => "edit.find_next",
+            Self::FindPrevious => "edit.find_previous",
+            Self::FindInTabs => "edit.find_in_tabs",
+            Self::FindInFiles => "edit.find_in_files",
+            Self::Replace => "edit.replace",
+            Self::GoTo => "edit.go_to",
+            Self::ConvertLineEndings(LineEnding::Crlf) => "edit.line_endings_crlf",
+            Self::ConvertLineEndings(LineEnding::Lf) => "edit.line_endings_lf",
+            Self::ConvertLineEndings(LineEnding::Cr) => "edit.line_endings_cr",
+            Self::ToggleReadOnly => "edit.toggle_read_only",
+            Self::ToggleKeepUndoHistory => "edit.toggle_keep_undo_history",
+            Self::Preferences => "edit.preferences",
+            Self::KeyboardShortcuts => "edit.keyboard_shortcuts",
+            Self::CommandPalette => "view.command_palette",
+            Self::ToggleStatusBar => "view.toggle_status_bar",
+            Self::ToggleLineNumbers => "view.toggle_line_numbers",
+            Self::SetWordWrap(WordWrap::Off) => "view.word_wrap_off",
+            Self::SetWordWrap(WordWrap::WindowEdge) => "view.word_wrap_window",
+            Self::SetWordWrap(WordWrap::Column(80)) => "view.word_wrap_80",
+            Self::SetWordWrap(WordWrap::Column(100)) => "view.word_wrap_100",
+            Self::SetWordWrap(WordWrap::Column(_)) => "view.word_wrap_120",
+            Self::NextTab => "view.next_tab",
+            Self::PreviousTab => "view.previous_tab",
+            Self::SelectTab(0) => "view.tab_1",
+            Self::SelectTab(1) => "view.tab_2",
+            Self::SelectTab(2) => "view.tab_3",
+            Self::SelectTab(3) => "view.tab_4",
+            Self::SelectTab(4) => "view.tab_5",
+            Self::SelectTab(5) => "view.tab_6",
+            Self::SelectTab(6) => "view.tab_7",
+            Self::SelectTab(_) => "view.tab_8",
+            Self::SelectLastTab => "view.last_tab",
+            Self::CheckForUpdates => "help.check_for_updates",
+        }
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::S) {
-        return Some(ShortcutCommand::Save);
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|command| command.id() == id)
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::N) {
-        return Some(ShortcutCommand::NewTab);
+    pub fn label(self) -> String {
+        let label = match self {
+            Self::NewTab => "New Tab",
+            Self::Open => "Open",
+            Self::Print => "Print",
+            Self::Save => "Save",
+            Self::SaveAs => "Save As",
+            Self::SaveAll => "Save All",
+            Self::SetBackup(Backup::Off) => "Backup on Save: Off",
+            Self::SetBackup(Backup::Sibling) => "Backup on Save: Keep .bak Copy",
+            Self::SetBackup(Backup::Timestamped) => "Backup on Save: Timestamped Copies in Folder",
+            Self::SetAutosave(None) => "Autosave: Off",
+            Self::SetAutosave(Some(secs)) => return format!("Autosave: After {secs} Seconds Idle"),
+            Self::ToggleRestoreSession => "Toggle Reopen Tabs on Launch",
+            Self::CloseTab => "Close Tab",
+            Self::CloseAll => "Close All",
+            Self::CloseTabsLeft => "Close Tabs to the Left",
+            Self::CloseTabsRight => "Close Tabs to the Right",
+            Self::Quit => "Quit",
+            Self::ForceQuit => "Force Quit",
+            Self::Undo => "Undo",
+            Self::Redo => "Redo",
+            Self::Cut => "Cut",
+            Self::Copy => "Copy",
+            Self::Paste => "Paste",
+            Self::SelectAll => "Select All",
+            Self::AddNextOccurrence => "Add Next Occurrence",
+            Self::SelectAllOccurrences => "Select All Occurrences",
+            Self::Find => "Find",
+            Self::FindNext => "Find Next",
+            Self::FindPrevious => "Find Previous",
+            Self::FindInTabs => "Find in Open Tabs",
+            Self::FindInFiles => "Find in Files",
+            Self::Replace => "Replace",
+            Self::GoTo => "Go To",
+            Self::ConvertLineEndings(line_ending) => {
+                return format!("Convert Line Endings: {}", line_ending.menu_label())
+            }
+            Self::ToggleReadOnly => "Toggle Read-Only",
+            Self::ToggleKeepUndoHistory => "Toggle Keep Undo History After Closing",
+            Self::Preferences => "Preferences",
+            Self::KeyboardShortcuts => "Keyboard Shortcuts",
+            Self::CommandPalette => "Command Palette",
+            Self::ToggleStatusBar => "Toggle Status Bar",
+            Self::ToggleLineNumbers => "Toggle Line Numbers",
+            Self::SetWordWrap(WordWrap::Off) => "Word Wrap: Off",
+            Self::SetWordWrap(WordWrap::WindowEdge) => "Word Wrap: At Window Edge",
+            Self::SetWordWrap(WordWrap::Column(column)) => {
+                return format!("Word Wrap: At Column {column}")
+            }
+            Self::NextTab => "Next Tab",
+            Self::PreviousTab => "Previous Tab",
+            Self::SelectTab(index) => return format!("Go to Tab {}", index + 1),
+            Self::SelectLastTab => "Go to Last Tab",
+            Self::CheckForUpdates => "Check for Updates",
+        };
+        label.to_string()
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::O) {
-        return Some(ShortcutCommand::Open);
+    /// The menu the command belongs to, taken from its id.
+    pub fn category(self) -> &'static str {
+        match self.id().split('.').next() {
+            Some("file") => "File",
+            Some("edit") => "Edit",
+            Some("view") => "View",
+            _ => "Help",
+        }
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::P) {
-        return Some(ShortcutCommand::Print);
+    /// Commands that act on text. They go to the editor only when it has
+    /// focus or nothing does, so a focused text field keeps its own undo and
+    /// clipboard keys.
+    fn is_text_command(self) -> bool {
+        matches!(
+            self,
+            Self::Undo | Self::Redo | Self::Cut | Self::Copy | Self::Paste | Self::SelectAll
+        )
     }
+}
 
-    if input.modifiers.command && input.modifiers.shift && input.key_pressed(egui::Key::W) {
-        return Some(ShortcutCommand::Quit);
-    }
+/// One key press with its modifiers. `command` is Ctrl, or Command on
+/// macOS; `mac_ctrl` is the Control key on macOS and never set elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub command: bool,
+    pub mac_ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub key: Key,
+}
 
-    if input.modifiers.command && input.key_pressed(egui::Key::W) {
-        return Some(ShortcutCommand::CloseTab);
+impl KeyChord {
+    /// Ctrl, or Command on macOS, plus `key`.
+    pub fn command(key: Key) -> Self {
+        Self {
+            command: true,
+            mac_ctrl: false,
+            alt: false,
+            shift: false,
+            key,
+        }
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::Tab) {
-        if input.modifiers.shift {
-            return Some(ShortcutCommand::PreviousTab);
+    fn from_modifiers(modifiers: Modifiers, key: Key) -> Self {
+        Self {
+            command: modifiers.command,
+            mac_ctrl: cfg!(target_os = "macos") && modifiers.ctrl,
+            alt: modifiers.alt,
+            shift: modifiers.shift,
+            key,
         }
-        return Some(ShortcutCommand::NextTab);
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::F) {
-        return Some(ShortcutCommand::Find);
+    /// The chord a key press stands for. egui reports Ctrl+X, Ctrl+C and
+    /// Ctrl+V as clipboard events rather than key presses, so those are
+    /// turned back into their chords.
+    pub fn from_event(event: &Event, modifiers: Modifiers) -> Option<Self> {
+        let (key, modifiers) = match event {
+            Event::Key {
+                key,
+                pressed: true,
+                modifiers,
+                ..
+            } => (*key, *modifiers),
+            Event::Cut if modifiers.command => (Key::X, modifiers),
+            Event::Copy if modifiers.command => (Key::C, modifiers),
+            Event::Paste(_) if modifiers.command => (Key::V, modifiers),
+            _ => return None,
+        };
+        Some(Self::from_modifiers(modifiers, key))
     }
 
-    if input.modifiers.command && input.key_pressed(egui::Key::H) {
-        return Some(ShortcutCommand::Replace);
+    /// Whether the chord can start a binding without getting in the way of
+    /// typing.
+    fn can_start(&self) -> bool {
+        let function_key = (Key::F1 as usize..=Key::F35 as usize).contains(&(self.key as usize));
+        self.command || self.mac_ctrl || self.alt || function_key
     }
 
-    if input.modifiers.command {
-        let index = match () {
-            _ if input.key_pressed(egui::Key::Num1) => Some(0),
-            _ if input.key_pressed(egui::Key::Num2) => Some(1),
-            _ if input.key_pressed(egui::Key::Num3) => Some(2),
-            _ if input.key_pressed(egui::Key::Num4) => Some(3),
-            _ if input.key_pressed(egui::Key::Num5) => Some(4),
-            _ if input.key_pressed(egui::Key::Num6) => Some(5),
-            _ if input.key_pressed(egui::Key::Num7) => Some(6),
-            _ if input.key_pressed(egui::Key::Num8) => Some(7),
-            _ if input.key_pressed(egui::Key::Num9) => None,
-            _ => None,
+    fn parse(text: &str) -> Result<Self, String> {
+        let (modifiers, key) = if text == "+" {
+            ("", "+")
+        } else if let Some(modifiers) = text.strip_suffix("++") {
+            (modifiers, "+")
+        } else {
+            text.rsplit_once('+').unwrap_or(("", text))
         };
+        let key = Key::from_name(key).ok_or_else(|| format!("\"{key}\" is not a key"))?;
+        let mut chord = Self {
+            command: false,
+            mac_ctrl: false,
+            alt: false,
+            shift: false,
+            key,
+        };
+        for modifier in modifiers.split('+').filter(|modifier| !modifier.is_empty()) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "cmd" | "command" => chord.command = true,
+                "macctrl" if cfg!(target_os = "macos") => chord.mac_ctrl = true,
+                "macctrl" => chord.command = true,
+                "alt" | "option" | "meta" => chord.alt = true,
+                "shift" => chord.shift = true,
+                _ => return Err(format!("\"{modifier}\" is not a modifier")),
+            }
+        }
+        Ok(chord)
+    }
 
-        if input.key_pressed(egui::Key::Num9) {
-            return Some(ShortcutCommand::SelectLastTab);
+    /// How the chord is shown in menus: symbols on macOS, names elsewhere.
+    pub fn label(&self) -> String {
+        let key = self.key.symbol_or_name();
+        if cfg!(target_os = "macos") {
+            let mut label = String::new();
+            for (held, symbol) in [
+                (self.mac_ctrl, "⌃"),
+                (self.alt, "⌥"),
+                (self.shift, "⇧"),
+                (self.command, "⌘"),
+            ] {
+                if held {
+                    label.push_str(symbol);
+                }
+            }
+            label + key
+        } else {
+            let mut parts = Vec::new();
+            for (held, name) in [
+                (self.command, "Ctrl"),
+                (self.alt, "Alt"),
+                (self.shift, "Shift"),
+            ] {
+                if held {
+                    parts.push(name);
+                }
+            }
+            parts.push(key);
+            parts.join("+")
         }
+    }
+}
 
-        if let Some(index) = index {
-            return Some(ShortcutCommand::SelectTab(index));
+/// How the chord is written in `keymap.toml`, the same on every platform.
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.command, "Ctrl+"),
+            (self.mac_ctrl, "MacCtrl+"),
+            (self.alt, "Alt+"),
+            (self.shift, "Shift+"),
+        ] {
+            if held {
+                f.write_str(name)?;
+            }
         }
+        f.write_str(self.key.name())
     }
+}
 
-    None
+/// A binding: one chord, or two pressed one after the other such as
+/// Ctrl+K Ctrl+S.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeySequence {
+    first: KeyChord,
+    second: Option<KeyChord>,
+}
+
+impl KeySequence {
+    pub fn new(first: KeyChord, second: Option<KeyChord>) -> Result<Self, String> {
+        if !first.can_start() {
+            return Err(format!(
+                "{first} would get in the way of typing; start with Ctrl, Alt or a function key"
+            ));
+        }
+        Ok(Self { first, second })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut chords = text.split_whitespace().map(KeyChord::parse);
+        let first = chords.next().ok_or("no keys given")??;
+        let second = chords.next().transpose()?;
+        if chords.next().is_some() {
+            return Err(format!("\"{text}\" has more than two key presses"));
+        }
+        Self::new(first, second)
+    }
+
+    pub fn first(&self) -> KeyChord {
+        self.first
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.second.is_none()
+    }
+
+    pub fn label(&self) -> String {
+        match self.second {
+            Some(second) => format!("{} {}", self.first.label(), second.label()),
+            None => self.first.label(),
+        }
+    }
+}
+
+impl fmt::Display for KeySequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.second {
+            Some(second) => write!(f, "{} {second}", self.first),
+            None => write!(f, "{}", self.first),
+        }
+    }
+}
+
+/// Takes the key presses bound in `keymap` out of this frame's input and
+/// returns their commands in order, so the editor never sees them.
+///
+/// `pending` holds the first chord of a two-key binding between frames; a
+/// second chord that completes no binding is dropped along with it.
+/// `text_focus` is false while a widget other than the editor has focus,
+/// which leaves the text commands to that widget.
+pub fn detect(
+    ctx: &egui::Context,
+    keymap: &Keymap,
+    pending: &mut Option<KeyChord>,
+    text_focus: bool,
+) -> Vec<ShortcutCommand> {
+    ctx.input_mut(|input| {
+        let mut commands = Vec::new();
+        let mut index = 0;
+        while index < input.events.len() {
+            let event = &input.events[index];
+            let Some(chord) = KeyChord::from_event(event, input.modifiers) else {
+                index += 1;
+                continue;
+            };
+            let found = |sequence: &KeySequence| {
+                keymap
+                    .command_for(sequence)
+                    .filter(|command| text_focus || !command.is_text_command())
+            };
+            if let Some(first) = pending.take() {
+                let sequence = KeySequence {
+                    first,
+                    second: Some(chord),
+                };
+                commands.extend(found(&sequence));
+            } else if keymap.is_prefix(chord) {
+                *pending = Some(chord);
+            } else {
+                let sequence = KeySequence {
+                    first: chord,
+                    second: None,
+                };
+                let Some(command) = found(&sequence) else {
+                    index += 1;
+                    continue;
+                };
+                // The editor and text fields handle clipboard events
+                // themselves.
+                let native = matches!(
+                    (event, command),
+                    (Event::Cut, ShortcutCommand::Cut)
+                        | (Event::Copy, ShortcutCommand::Copy)
+                        | (Event::Paste(_), ShortcutCommand::Paste)
+                );
+                if native {
+                    index += 1;
+                    continue;
+                }
+                commands.push(command);
+            }
+            input.events.remove(index);
+            // A key without Ctrl also types its character.
+            if matches!(input.events.get(index), Some(Event::Text(_))) {
+                input.events.remove(index);
+            }
+        }
+        commands
+    })
 }
@@ -0,0 +1,223 @@
+use eframe::egui::{self, text::LayoutJob, Key, Modifiers, TextFormat};
+use std::cmp::Reverse;
+
+/// One entry the palette can run.
+pub struct PaletteItem<C> {
+    pub label: String,
+    /// Shown on the right: the keys of a command, or where a file is.
+    pub detail: String,
+    pub command: C,
+}
+
+#[derive(Default)]
+pub struct CommandPalette {
+    pub open: bool,
+    query: String,
+    /// Index into the filtered entries.
+    selected: usize,
+    /// Set when the palette opens so the query gets the keyboard focus.
+    focus_input: bool,
+}
+
+impl CommandPalette {
+    pub fn show(&mut self) {
+        self.open = true;
+        self.query.clear();
+        self.selected = 0;
+        self.focus_input = true;
+    }
+}
+
+/// Draws the palette while it is open and returns the command picked with
+/// Enter or a click. Up and Down move through the entries, Escape closes.
+pub fn command_palette<C: Copy>(
+    ctx: &egui::Context,
+    palette: &mut CommandPalette,
+    items: &[PaletteItem<C>],
+) -> Option<C> {
+    let mut matches = items
+        .iter()
+        .enumerate()
+        .filter_map(|(index, item)| {
+            let (score, positions) = fuzzy_match(&palette.query, &item.label)?;
+            Some((index, score, positions))
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by_key(|(index, score, _)| (Reverse(*score), *index));
+
+    let (up, down, enter, escape) = ctx.input_mut(|input| {
+        (
+            input.consume_key(Modifiers::NONE, Key::ArrowUp),
+            input.consume_key(Modifiers::NONE, Key::ArrowDown),
+            input.consume_key(Modifiers::NONE, Key::Enter),
+            input.consume_key(Modifiers::NONE, Key::Escape),
+        )
+    });
+    if escape {
+        palette.open = false;
+        return None;
+    }
+    if down {
+        palette.selected += 1;
+    }
+    if up {
+        palette.selected = palette.selected.saturating_sub(1);
+    }
+    palette.selected = palette.selected.min(matches.len().saturating_sub(1));
+
+    let mut chosen = None;
+    if enter {
+        chosen = matches
+            .get(palette.selected)
+            .map(|(index, _, _)| items[*index].command);
+    }
+
+    egui::Window::new("Command Palette")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 48.0))
+        .fixed_size(egui::vec2(520.0, 0.0))
+        .show(ctx, |ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut palette.query)
+                    .hint_text("Type a command, tab or recent file")
+                    .desired_width(f32::INFINITY),
+            );
+            if palette.focus_input {
+                response.request_focus();
+                palette.focus_input = false;
+            }
+            if response.changed() {
+                palette.selected = 0;
+            }
+            ui.separator();
+
+            egui::ScrollArea::vertical()
+                .max_height(360.0)
+                .show(ui, |ui| {
+                    if matches.is_empty() {
+                        ui.weak("Nothing matches.");
+                    }
+                    for (row, (index, _, positions)) in matches.iter().enumerate() {
+                        let item = &items[*index];
+                        let selected = row == palette.selected;
+                        ui.horizontal(|ui| {
+                            let label = highlighted(ui, &item.label, positions);
+                            let response = ui.selectable_label(selected, label);
+                            if selected && (up || down) {
+                                response.scroll_to_me(None);
+                            }
+                            if response.clicked() {
+                                chosen = Some(item.command);
+                            }
+                            ui.with_layout(
+                                egui::Layout::right_to_left(egui::Align::Center),
+                                |ui| {
+                                    ui.weak(&item.detail);
+                                },
+                            );
+                        });
+                    }
+                });
+        });
+
+    if chosen.is_some() {
+        palette.open = false;
+    }
+    chosen
+}
+
+/// Scores `text` when every character of `query` appears in it in order,
+/// ignoring case and spaces in the query. Runs of characters and characters
+/// at the start of a word score higher. Also returns the char positions
+/// that matched.
+fn fuzzy_match(query: &str, text: &str) -> Option<(i32, Vec<usize>)> {
+    let mut wanted = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .peekable();
+    let mut positions = Vec::new();
+    let mut score = 0;
+    let mut previous = None::<char>;
+    for (index, c) in text.chars().enumerate() {
+        let Some(&next) = wanted.peek() else {
+            break;
+        };
+        if c.to_lowercase().next() == Some(next) {
+            score += 1;
+            if index > 0 && positions.last() == Some(&(index - 1)) {
+                score += 4;
+            }
+            if previous.is_none_or(|previous| !previous.is_alphanumeric()) {
+                score += 3;
+            }
+            positions.push(index);
+            wanted.next();
+        }
+        previous = Some(c);
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+    Some((score, positions))
+}
+
+/// `text` with the matched characters drawn in the strong text color.
+fn highlighted(ui: &egui::Ui, text: &str, positions: &[usize]) -> LayoutJob {
+    let font_id = egui::TextStyle::Button.resolve(ui.style());
+    let normal = TextFormat::simple(font_id.clone(), ui.visuals().text_color());
+    let strong = TextFormat::simple(font_id, ui.visuals().strong_text_color());
+    let mut job = LayoutJob::default();
+    let mut positions = positions.iter().peekable();
+    let mut run = String::new();
+    let mut run_strong = false;
+    for (index, c) in text.chars().enumerate() {
+        let is_strong = positions.next_if_eq(&&index).is_some();
+        if is_strong != run_strong && !run.is_empty() {
+            let format = if run_strong { &strong } else { &normal };
+            job.append(&std::mem::take(&mut run), 0.0, format.clone());
+        }
+        run_strong = is_strong;
+        run.push(c);
+    }
+    let format = if run_strong { strong } else { normal };
+    job.append(&run, 0.0, format);
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(query: &str, text: &str) -> i32 {
+        fuzzy_match(query, text).unwrap().0
+    }
+
+    #[test]
+    fn a_prefix_beats_scattered_letters() {
+        assert_eq!(fuzzy_match("sav", "Save As"), Some((14, vec![0, 1, 2])));
+        assert!(score("se", "Select All") > score("se", "Close Tab"));
+        // Word starts count for more than letters inside words.
+        assert!(score("sa", "Select All") > score("sa", "Close Tab As"));
+        assert_eq!(fuzzy_match("sa", "Select All").unwrap().1, [0, 7]);
+    }
+
+    #[test]
+    fn case_and_spaces_in_the_query_are_ignored() {
+        assert_eq!(
+            fuzzy_match("SAVE", "save as"),
+            fuzzy_match("save", "save as")
+        );
+        assert_eq!(fuzzy_match("s a", "Save As"), fuzzy_match("sa", "Save As"));
+        assert_eq!(fuzzy_match("", "Save"), Some((0, Vec::new())));
+    }
+
+    #[test]
+    fn letters_missing_or_out_of_order_do_not_match() {
+        assert_eq!(fuzzy_match("xyz", "Save As"), None);
+        assert_eq!(fuzzy_match("as", "Sa"), None);
+        assert_eq!(fuzzy_match("save all", "Save As"), None);
+    }
+}
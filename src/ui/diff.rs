@@ -0,0 +1,60 @@
+use eframe::egui;
+use similar::{ChangeTag, TextDiff};
+use std::time::Duration;
+
+/// Lines of a unified diff between the file on disk and the open buffer.
+pub struct DiffLines {
+    lines: Vec<(Option<ChangeTag>, String)>,
+}
+
+impl DiffLines {
+    pub fn new(on_disk: &str, buffer: &str) -> Self {
+        let diff = TextDiff::configure()
+            .timeout(Duration::from_secs(2))
+            .diff_lines(on_disk, buffer);
+        let mut lines = Vec::new();
+        for (index, group) in diff.grouped_ops(3).iter().enumerate() {
+            if index > 0 {
+                lines.push((None, "…".to_string()));
+            }
+            for op in group {
+                for change in diff.iter_changes(op) {
+                    let text = change.value().trim_end_matches('\n').to_string();
+                    lines.push((Some(change.tag()), text));
+                }
+            }
+        }
+        Self { lines }
+    }
+}
+
+/// Paints the diff, laying out only the rows that are scrolled into view.
+pub fn diff_view(ui: &mut egui::Ui, diff: &DiffLines) {
+    if diff.lines.is_empty() {
+        ui.label("The file on disk matches this tab.");
+        return;
+    }
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let row_height = ui.fonts(|fonts| fonts.row_height(&font_id));
+    egui::ScrollArea::both()
+        .auto_shrink([false, false])
+        .max_height(400.0)
+        .show_rows(ui, row_height, diff.lines.len(), |ui, rows| {
+            for (tag, text) in &diff.lines[rows] {
+                let (prefix, color) = match tag {
+                    Some(ChangeTag::Delete) => ("- ", ui.visuals().error_fg_color),
+                    Some(ChangeTag::Insert) => ("+ ", egui::Color32::from_rgb(80, 170, 80)),
+                    Some(ChangeTag::Equal) => ("  ", ui.visuals().text_color()),
+                    None => ("", ui.visuals().weak_text_color()),
+                };
+                ui.add(
+                    egui::Label::new(
+                        egui::RichText::new(format!("{prefix}{text}"))
+                            .font(font_id.clone())
+                            .color(color),
+                    )
+                    .wrap(false),
+                );
+            }
+        });
+}
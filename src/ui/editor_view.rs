@@ -1,54 +1,1240 @@
-use crate::document::Document;
-use eframe::egui;
+use crate::{
+    document::{line_content_len, Block, Document, EditKind, Selection},
+    line_ending,
+};
+use eframe::egui::{self, pos2, text::Galley, Event, FontId, Key, Modifiers, Pos2, Rect, Sense};
+use ropey::Rope;
+use std::{ops::Range, sync::Arc};
 
+const TEXT_MARGIN: f32 = 4.0;
+const SCROLLBAR_WIDTH: f32 = 12.0;
+const MIN_THUMB_LENGTH: f32 = 24.0;
+/// Lines longer than this are laid out one visible window at a time, using the
+/// monospace advance to place the window.
+const LONG_LINE_CHARS: usize = 4096;
+
+/// Transient widget state kept in egui memory between frames.
+#[derive(Clone, Copy, Default)]
+struct EditorState {
+    preferred_x: Option<f32>,
+    /// Line and column an Alt+drag block selection started at.
+    block_anchor: Option<(usize, usize)>,
+    vscroll_grab: Option<f32>,
+    hscroll_grab: Option<f32>,
+}
+
+/// How lines longer than the text area are shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub enum WordWrap {
+    /// One row per line, with horizontal scrolling.
+    #[default]
+    Off,
+    /// Soft wrap at the right edge of the text area.
+    WindowEdge,
+    /// Soft wrap after this many monospace columns.
+    Column(usize),
+}
+
+/// How the editor draws its text.
+pub struct ViewOptions {
+    pub show_line_numbers: bool,
+    pub wrap: WordWrap,
+    pub font_id: FontId,
+    /// Columns between tab stops.
+    pub tab_width: usize,
+}
+
+/// Search matches to paint behind the text.
+#[derive(Default)]
+pub struct MatchHighlights<'a> {
+    /// Sorted, disjoint char ranges.
+    pub ranges: &'a [Range<usize>],
+    /// Index into `ranges` of the match the selection is on.
+    pub current: Option<usize>,
+    /// Fractions of the line count to mark on the vertical scrollbar.
+    pub ticks: &'a [f32],
+}
+
+/// Geometry of the text area for the current frame.
+struct Viewport {
+    text_rect: Rect,
+    font_id: FontId,
+    row_height: f32,
+    char_width: f32,
+    page_rows: usize,
+    /// Width lines are wrapped at, if word wrap is on.
+    wrap_width: Option<f32>,
+    tab_width: usize,
+}
+
+impl Viewport {
+    fn text_left(&self, scroll_x: f32) -> f32 {
+        self.text_rect.left() + TEXT_MARGIN - scroll_x
+    }
+
+    fn layout_line(&self, ui: &egui::Ui, rope: &Rope, line: usize, scroll_x: f32) -> LineLayout {
+        let slice = rope.line(line);
+        let len = line_content_len(slice);
+        // Long lines are never wrapped, so they can keep being laid out one
+        // window at a time.
+        let wrap_width = self.wrap_width.filter(|_| len <= LONG_LINE_CHARS);
+        let (first, last) = if len > LONG_LINE_CHARS {
+            let visible_cols = (self.text_rect.width() / self.char_width) as usize;
+            let first = ((scroll_x / self.char_width) as usize)
+                .saturating_sub(LONG_LINE_CHARS / 4)
+                .min(len);
+            (first, (first + visible_cols + LONG_LINE_CHARS / 2).min(len))
+        } else {
+            (0, len)
+        };
+
+        // Tabs are laid out as spaces up to the next tab stop.
+        let mut text = String::with_capacity(last - first);
+        let mut tab_map = None::<Vec<usize>>;
+        let mut column = first;
+        for (index, ch) in slice.slice(first..last).chars().enumerate() {
+            if ch == '\t' {
+                let map = tab_map.get_or_insert_with(|| (0..=index).collect());
+                let width = self.tab_width - column % self.tab_width;
+                text.extend(std::iter::repeat_n(' ', width));
+                column += width;
+                map.push(map[index] + width);
+            } else {
+                text.push(ch);
+                column += 1;
+                if let Some(map) = &mut tab_map {
+                    map.push(map[index] + 1);
+                }
+            }
+        }
+
+        let color = ui.visuals().text_color();
+        let max_width = wrap_width.unwrap_or(f32::INFINITY);
+        let galley = ui.fonts(|fonts| fonts.layout(text, self.font_id.clone(), color, max_width));
+        let row_starts = galley
+            .rows
+            .iter()
+            .scan(0, |start, row| {
+                let row_start = *start;
+                *start += row.char_count_including_newline();
+                Some(row_start)
+            })
+            .collect();
+        LineLayout {
+            galley,
+            first_char: first,
+            window_len: last - first,
+            len,
+            origin_x: first as f32 * self.char_width,
+            char_width: self.char_width,
+            row_starts,
+            tab_map,
+        }
+    }
+
+    /// How many visual rows `line` takes up.
+    fn rows_in(&self, ui: &egui::Ui, rope: &Rope, line: usize) -> usize {
+        if self.wrap_width.is_none() {
+            return 1;
+        }
+        self.layout_line(ui, rope, line, 0.0).rows()
+    }
+
+    /// The row `delta` visual rows away from `row` of `line`, or `None` when
+    /// that is before the first or after the last row of the document.
+    fn offset_row(
+        &self,
+        ui: &egui::Ui,
+        rope: &Rope,
+        line: usize,
+        row: usize,
+        delta: isize,
+    ) -> Option<(usize, usize)> {
+        let last_line = rope.len_lines().saturating_sub(1);
+        if self.wrap_width.is_none() {
+            let target = line.checked_add_signed(delta)?;
+            return (target <= last_line).then_some((target, 0));
+        }
+        let mut line = line;
+        let mut row = row as isize + delta;
+        loop {
+            if row < 0 {
+                line = line.checked_sub(1)?;
+                row += self.rows_in(ui, rope, line) as isize;
+                continue;
+            }
+            let rows = self.rows_in(ui, rope, line) as isize;
+            if row < rows {
+                return Some((line, row as usize));
+            }
+            if line == last_line {
+                return None;
+            }
+            row -= rows;
+            line += 1;
+        }
+    }
+
+    /// Moves the scroll position `top_line` by `delta` visual rows. Its
+    /// fraction is how much of the top line's rows are scrolled past.
+    fn scroll_rows(&self, ui: &egui::Ui, rope: &Rope, top_line: f64, delta: f64) -> f64 {
+        if self.wrap_width.is_none() {
+            return top_line + delta;
+        }
+        let last_line = rope.len_lines().saturating_sub(1);
+        let line = (top_line.max(0.0).floor() as usize).min(last_line);
+        let rows = self.rows_in(ui, rope, line) as f64;
+        let position = (top_line - line as f64) * rows + delta;
+        let whole = position.floor();
+        match self.offset_row(ui, rope, line, 0, whole as isize) {
+            Some((line, row)) => {
+                let rows = self.rows_in(ui, rope, line) as f64;
+                line as f64 + (row as f64 + position - whole) / rows
+            }
+            None if delta < 0.0 => 0.0,
+            None => rope.len_lines() as f64,
+        }
+    }
+
+    /// The scroll position that shows the last row at the bottom.
+    fn max_top(&self, ui: &egui::Ui, rope: &Rope) -> f64 {
+        let line_count = rope.len_lines();
+        if self.wrap_width.is_none() {
+            return line_count.saturating_sub(self.page_rows) as f64;
+        }
+        let last_line = line_count.saturating_sub(1);
+        let last_row = self.rows_in(ui, rope, last_line) - 1;
+        let back = -(self.page_rows as isize - 1);
+        match self.offset_row(ui, rope, last_line, last_row, back) {
+            Some((line, row)) => line as f64 + row as f64 / self.rows_in(ui, rope, line) as f64,
+            None => 0.0,
+        }
+    }
+
+    /// The line and the row within it at `y`.
+    fn row_at_y(&self, ui: &egui::Ui, rope: &Rope, top_line: f64, y: f32) -> (usize, usize) {
+        let offset = ((y - self.text_rect.top()) / self.row_height) as f64;
+        let position = self.scroll_rows(ui, rope, top_line, offset).max(0.0);
+        let last_line = rope.len_lines().saturating_sub(1);
+        let line = position.floor() as usize;
+        if line > last_line {
+            return (last_line, self.rows_in(ui, rope, last_line) - 1);
+        }
+        let rows = self.rows_in(ui, rope, line);
+        let row = ((position - line as f64) * rows as f64).floor() as usize;
+        (line, row.min(rows - 1))
+    }
+}
+
+/// One logical line laid out for painting and hit-testing. Only
+/// `first_char..first_char + window_len` is in the galley.
+struct LineLayout {
+    galley: Arc<Galley>,
+    first_char: usize,
+    window_len: usize,
+    len: usize,
+    origin_x: f32,
+    char_width: f32,
+    /// Index into the galley text each row starts at. There is one row
+    /// unless the line is wrapped.
+    row_starts: Vec<usize>,
+    /// Where each column of the window starts in the galley text, when tabs
+    /// widened into spaces make the two differ.
+    tab_map: Option<Vec<usize>>,
+}
+
+impl LineLayout {
+    fn rows(&self) -> usize {
+        self.row_starts.len()
+    }
+
+    /// The row `col` is shown on. A column where the line wraps belongs to
+    /// the row it starts.
+    fn row_of(&self, col: usize) -> usize {
+        let rel = col.saturating_sub(self.first_char).min(self.window_len);
+        let index = self.galley_index(rel);
+        self.row_starts
+            .partition_point(|start| *start <= index)
+            .saturating_sub(1)
+    }
+
+    /// Where column `rel` of the window starts in the galley text.
+    fn galley_index(&self, rel: usize) -> usize {
+        match &self.tab_map {
+            Some(map) => map[rel],
+            None => rel,
+        }
+    }
+
+    /// The window column nearest to `index` in the galley text.
+    fn column_of(&self, index: usize) -> usize {
+        let Some(map) = &self.tab_map else {
+            return index;
+        };
+        let rel = map
+            .partition_point(|start| *start <= index)
+            .saturating_sub(1);
+        match map.get(rel + 1) {
+            Some(next) if (index - map[rel]) * 2 >= next - map[rel] && index != map[rel] => rel + 1,
+            _ => rel,
+        }
+    }
+
+    fn x_of(&self, col: usize) -> f32 {
+        if col < self.first_char {
+            return col as f32 * self.char_width;
+        }
+        let rel = col - self.first_char;
+        if rel > self.window_len {
+            let beyond = (rel - self.window_len) as f32 * self.char_width;
+            return self.origin_x + self.row_end_x(self.rows() - 1) + beyond;
+        }
+        let row = self.row_of(col);
+        let index = self.galley_index(rel) - self.row_starts[row];
+        self.origin_x + self.galley.rows[row].x_offset(index)
+    }
+
+    fn row_end_x(&self, row: usize) -> f32 {
+        self.galley.rows[row].rect.max.x
+    }
+
+    fn col_at(&self, x: f32, row: usize) -> usize {
+        if x < self.origin_x {
+            let col = (x / self.char_width).round().max(0.0) as usize;
+            return col.min(self.first_char);
+        }
+        let right = self.origin_x + self.galley.size().x;
+        if x > right && self.first_char + self.window_len < self.len {
+            let beyond = ((x - right) / self.char_width).round() as usize;
+            return (self.first_char + self.window_len + beyond).min(self.len);
+        }
+        let galley_row = &self.galley.rows[row];
+        let mut col = galley_row.char_at(x - self.origin_x);
+        // Past the end of a wrapped row, stay on that row instead of landing
+        // at the start of the next one.
+        if row + 1 < self.rows() && col > 0 && col >= galley_row.char_count_excluding_newline() {
+            col -= 1;
+        }
+        self.first_char
+            + self
+                .column_of(self.row_starts[row] + col)
+                .min(self.window_len)
+    }
+
+    /// Like `col_at`, but past the end of the line it keeps counting in
+    /// monospace columns, for block selections.
+    fn virtual_col_at(&self, x: f32, row: usize) -> usize {
+        let end = self.x_of(self.len);
+        if row + 1 == self.rows() && x > end {
+            return self.len + ((x - end) / self.char_width).round() as usize;
+        }
+        self.col_at(x, row)
+    }
+
+    fn width(&self) -> f32 {
+        self.x_of(self.len)
+            .max(self.origin_x + self.galley.size().x)
+    }
+
+    /// The stretches of rows, as `(row, start_x, end_x)`, that columns
+    /// `start..end` cover.
+    fn spans(&self, start: usize, end: usize) -> Vec<(usize, f32, f32)> {
+        let (first_row, last_row) = (self.row_of(start), self.row_of(end));
+        (first_row..=last_row)
+            .map(|row| {
+                let start_x = if row == first_row {
+                    self.x_of(start)
+                } else {
+                    self.origin_x
+                };
+                let end_x = if row == last_row {
+                    self.x_of(end)
+                } else {
+                    self.origin_x + self.row_end_x(row)
+                };
+                (row, start_x, end_x)
+            })
+            .collect()
+    }
+}
+
+/// Draws the editor for `doc`, laying out and painting only the lines inside
+/// the viewport so the cost of a frame does not grow with the document.
 pub fn editor_view(
     ui: &mut egui::Ui,
     doc: &mut Document,
-    show_line_numbers: bool,
+    options: &ViewOptions,
+    matches: &MatchHighlights,
 ) -> egui::Response {
-    let response = if show_line_numbers {
-        let line_count = doc.text().lines().count().max(1);
+    let id = egui::Id::new("plainpad_editor");
+    let mut state = ui
+        .data_mut(|data| data.get_temp::<EditorState>(id))
+        .unwrap_or_default();
+
+    let font_id = options.font_id.clone();
+    let (row_height, char_width) =
+        ui.fonts(|fonts| (fonts.row_height(&font_id), fonts.glyph_width(&font_id, 'M')));
+
+    let outer = ui.available_rect_before_wrap();
+    ui.allocate_rect(outer, Sense::hover());
+
+    let line_count = doc.rope().len_lines();
+    let gutter_width = if options.show_line_numbers {
         let digits = line_count.to_string().len();
-        let gutter_width = 12.0 + (digits as f32 * 8.0);
-        let line_numbers = (1..=line_count)
-            .map(|line| line.to_string())
-            .collect::<Vec<_>>()
-            .join("\n");
-        let mut editor_response = None;
-
-        ui.horizontal(|ui| {
-            ui.add_sized(
-                [gutter_width, ui.available_height()],
-                egui::Label::new(egui::RichText::new(line_numbers).monospace()).wrap(false),
+        12.0 + digits as f32 * char_width
+    } else {
+        0.0
+    };
+    let gutter_rect = Rect::from_min_max(
+        outer.min,
+        pos2(
+            outer.left() + gutter_width,
+            outer.bottom() - SCROLLBAR_WIDTH,
+        ),
+    );
+    let vbar_rect = Rect::from_min_max(
+        pos2(outer.right() - SCROLLBAR_WIDTH, outer.top()),
+        pos2(outer.right(), outer.bottom() - SCROLLBAR_WIDTH),
+    );
+    let hbar_rect = Rect::from_min_max(
+        pos2(gutter_rect.right(), outer.bottom() - SCROLLBAR_WIDTH),
+        pos2(vbar_rect.left(), outer.bottom()),
+    );
+    let text_rect = Rect::from_min_max(
+        pos2(gutter_rect.right(), outer.top()),
+        pos2(vbar_rect.left(), hbar_rect.top()),
+    );
+    let viewport = Viewport {
+        text_rect,
+        font_id,
+        row_height,
+        char_width,
+        page_rows: ((text_rect.height() / row_height).floor() as usize).max(1),
+        wrap_width: match options.wrap {
+            WordWrap::Off => None,
+            WordWrap::WindowEdge => Some((text_rect.width() - TEXT_MARGIN * 2.0).max(char_width)),
+            WordWrap::Column(columns) => Some(columns.max(1) as f32 * char_width),
+        },
+        tab_width: options.tab_width.max(1),
+    };
+
+    let mut response = ui.interact(text_rect, id, Sense::click_and_drag());
+    ui.memory_mut(|memory| {
+        memory.interested_in_focus(id);
+        memory.set_focus_lock_filter(
+            id,
+            egui::EventFilter {
+                tab: true,
+                horizontal_arrows: true,
+                vertical_arrows: true,
+                // Escape first drops the extra cursors, then the focus.
+                escape: doc.selections().len() > 1,
+            },
+        );
+    });
+    if response.hovered() {
+        ui.ctx().set_cursor_icon(egui::CursorIcon::Text);
+    }
+
+    if ui.rect_contains_pointer(outer) {
+        let delta = ui.input(|input| input.smooth_scroll_delta);
+        if delta != egui::Vec2::ZERO {
+            let top_line = doc.view().top_line;
+            let top_line =
+                viewport.scroll_rows(ui, doc.rope(), top_line, -(delta.y / row_height) as f64);
+            let view = doc.view_mut();
+            view.top_line = top_line;
+            view.scroll_x -= delta.x;
+        }
+    }
+
+    let mut reveal = std::mem::take(&mut doc.view_mut().reveal_cursor);
+
+    if let Some(pos) = response.interact_pointer_pos() {
+        let pressed = ui.input(|input| input.pointer.any_pressed());
+        if pressed || response.drag_started() {
+            response.request_focus();
+        }
+        let index = hit_test(ui, doc, &viewport, pos);
+        let rope = doc.rope();
+        let modifiers = ui.input(|input| input.modifiers);
+        if pressed && response.is_pointer_button_down_on() {
+            state.block_anchor = modifiers
+                .alt
+                .then(|| block_hit_test(ui, doc, &viewport, pos));
+        }
+        if let Some((anchor_line, anchor_col)) = state.block_anchor {
+            let (head_line, head_col) = block_hit_test(ui, doc, &viewport, pos);
+            doc.set_block(Block {
+                anchor_line,
+                anchor_col,
+                head_line,
+                head_col,
+            });
+            if !text_rect.y_range().contains(pos.y) {
+                reveal = true;
+                ui.ctx().request_repaint();
+            }
+        } else if response.double_clicked() {
+            let range = word_range_at(rope, index);
+            doc.set_primary_selection(Selection {
+                anchor: range.start,
+                head: range.end,
+            });
+        } else if pressed && response.is_pointer_button_down_on() {
+            if modifiers.command {
+                doc.add_selection(Selection::cursor(index));
+            } else if modifiers.shift {
+                let anchor = doc.selection().anchor;
+                doc.set_primary_selection(Selection {
+                    anchor,
+                    head: index,
+                });
+            } else {
+                doc.set_selection(Selection::cursor(index));
+            }
+        } else if response.dragged() {
+            let anchor = doc.selection().anchor;
+            doc.set_primary_selection(Selection {
+                anchor,
+                head: index,
+            });
+            if !text_rect.y_range().contains(pos.y) {
+                reveal = true;
+                ui.ctx().request_repaint();
+            }
+        }
+        state.preferred_x = None;
+    }
+
+    if response.has_focus() {
+        let events = ui.input(|input| input.events.clone());
+        for event in events {
+            let outcome = handle_event(ui, doc, &viewport, &mut state, &event);
+            if outcome.edited {
+                response.mark_changed();
+            }
+            reveal |= outcome.moved;
+        }
+    }
+
+    scrollbars(ui, doc, &viewport, &mut state, id, vbar_rect, hbar_rect);
+    scrollbar_ticks(ui, vbar_rect, matches.ticks);
+    clamp_view(ui, doc, &viewport);
+    if reveal {
+        reveal_cursor(ui, doc, &viewport);
+    }
+
+    paint(
+        ui,
+        doc,
+        &viewport,
+        matches,
+        gutter_rect,
+        response.has_focus(),
+    );
+
+    ui.data_mut(|data| data.insert_temp(id, state));
+    response
+}
+
+#[derive(Default)]
+struct EventOutcome {
+    edited: bool,
+    moved: bool,
+}
+
+fn handle_event(
+    ui: &egui::Ui,
+    doc: &mut Document,
+    viewport: &Viewport,
+    state: &mut EditorState,
+    event: &Event,
+) -> EventOutcome {
+    match event {
+        Event::Text(text) if !text.is_empty() && text != "\n" && text != "\r" => {
+            doc.insert(text, EditKind::Typing);
+        }
+        Event::CompositionEnd(text) if !text.is_empty() => {
+            doc.insert(text, EditKind::Typing);
+        }
+        Event::Paste(text) if !text.is_empty() => {
+            paste(ui.ctx(), doc, text);
+        }
+        Event::Copy => {
+            copy_selection(ui, doc);
+            return EventOutcome::default();
+        }
+        Event::Cut => {
+            if !copy_selection(ui, doc) {
+                return EventOutcome::default();
+            }
+            doc.insert("", EditKind::Other);
+        }
+        Event::Key {
+            key,
+            pressed: true,
+            modifiers,
+            ..
+        } => return handle_key(ui, doc, viewport, state, *key, *modifiers),
+        _ => return EventOutcome::default(),
+    }
+    state.preferred_x = None;
+    EventOutcome {
+        edited: true,
+        moved: true,
+    }
+}
+
+fn handle_key(
+    ui: &egui::Ui,
+    doc: &mut Document,
+    viewport: &Viewport,
+    state: &mut EditorState,
+    key: Key,
+    modifiers: Modifiers,
+) -> EventOutcome {
+    let word = word_modifier(modifiers);
+    match key {
+        Key::Enter => return edit_outcome(state, doc.insert("\n", EditKind::Typing)),
+        Key::Tab if !modifiers.command => {
+            return edit_outcome(state, doc.insert("\t", EditKind::Typing))
+        }
+        Key::Backspace => {
+            let edited = doc.edit_selections(EditKind::Deleting, |rope, _, selection| {
+                let head = selection.head;
+                let range = if !selection.is_empty() {
+                    selection.range()
+                } else if word {
+                    previous_word_boundary(rope, head)..head
+                } else {
+                    previous_char(head)..head
+                };
+                (range, String::new())
+            });
+            return edit_outcome(state, edited);
+        }
+        Key::Delete => {
+            let edited = doc.edit_selections(EditKind::Deleting, |rope, _, selection| {
+                let head = selection.head;
+                let range = if !selection.is_empty() {
+                    selection.range()
+                } else if word {
+                    head..next_word_boundary(rope, head)
+                } else {
+                    head..next_char(rope, head)
+                };
+                (range, String::new())
+            });
+            return edit_outcome(state, edited);
+        }
+        Key::Escape if doc.selections().len() > 1 => {
+            doc.set_selection(doc.selection());
+            return EventOutcome {
+                edited: false,
+                moved: true,
+            };
+        }
+        _ => {}
+    }
+
+    let vertical = matches!(
+        key,
+        Key::ArrowUp | Key::ArrowDown | Key::PageUp | Key::PageDown
+    );
+    if !vertical {
+        state.preferred_x = None;
+    }
+    let selections = doc.selections().to_vec();
+    let primary = doc.primary_index();
+    let mut moved = Vec::with_capacity(selections.len());
+    for (index, selection) in selections.into_iter().enumerate() {
+        // Only the primary cursor remembers its column across short lines.
+        let mut own_x = None;
+        let preferred_x = if index == primary {
+            &mut state.preferred_x
+        } else {
+            &mut own_x
+        };
+        let Some(head) = cursor_target(ui, doc, viewport, preferred_x, key, modifiers, selection)
+        else {
+            return EventOutcome::default();
+        };
+        let anchor = if modifiers.shift {
+            selection.anchor
+        } else {
+            head
+        };
+        moved.push(Selection { anchor, head });
+    }
+    doc.set_selections(moved, primary);
+
+    if matches!(key, Key::PageUp | Key::PageDown) {
+        let page = viewport.page_rows as f64;
+        let delta = if key == Key::PageUp { -page } else { page };
+        let top_line = viewport.scroll_rows(ui, doc.rope(), doc.view().top_line, delta);
+        doc.view_mut().top_line = top_line;
+    }
+    EventOutcome {
+        edited: false,
+        moved: true,
+    }
+}
+
+fn word_modifier(modifiers: Modifiers) -> bool {
+    if cfg!(target_os = "macos") {
+        modifiers.alt
+    } else {
+        modifiers.ctrl
+    }
+}
+
+/// Where `key` moves the head of `selection`, or `None` if it is not a
+/// movement key.
+fn cursor_target(
+    ui: &egui::Ui,
+    doc: &Document,
+    viewport: &Viewport,
+    preferred_x: &mut Option<f32>,
+    key: Key,
+    modifiers: Modifiers,
+    selection: Selection,
+) -> Option<usize> {
+    let word = word_modifier(modifiers);
+    let head = selection.head;
+    let rope = doc.rope();
+    let target = match key {
+        Key::ArrowLeft if !modifiers.shift && !selection.is_empty() => selection.range().start,
+        Key::ArrowRight if !modifiers.shift && !selection.is_empty() => selection.range().end,
+        Key::ArrowLeft if word => previous_word_boundary(rope, head),
+        Key::ArrowRight if word => next_word_boundary(rope, head),
+        Key::ArrowLeft => previous_char(head),
+        Key::ArrowRight => next_char(rope, head),
+        Key::ArrowUp | Key::ArrowDown | Key::PageUp | Key::PageDown => {
+            let rows = match key {
+                Key::ArrowUp | Key::ArrowDown => 1,
+                _ => viewport.page_rows,
+            };
+            let up = matches!(key, Key::ArrowUp | Key::PageUp);
+            vertical_target(ui, doc, viewport, preferred_x, head, rows, up)
+        }
+        Key::Home if modifiers.command => 0,
+        Key::End if modifiers.command => rope.len_chars(),
+        Key::Home => smart_home(rope, head),
+        Key::End => {
+            let line = rope.char_to_line(head);
+            rope.line_to_char(line) + line_content_len(rope.line(line))
+        }
+        _ => return None,
+    };
+    Some(target)
+}
+
+fn edit_outcome(state: &mut EditorState, edited: bool) -> EventOutcome {
+    state.preferred_x = None;
+    EventOutcome {
+        edited,
+        moved: true,
+    }
+}
+
+/// Puts the selected text of every cursor on the clipboard. Returns false
+/// when nothing is selected.
+fn copy_selection(ui: &egui::Ui, doc: &Document) -> bool {
+    let text = doc.selected_text();
+    if text.is_empty() {
+        return false;
+    }
+    remember_copy(ui.ctx(), doc, &text);
+    ui.ctx().output_mut(|output| output.copied_text = text);
+    true
+}
+
+fn block_clipboard_id() -> egui::Id {
+    egui::Id::new("plainpad_block_clipboard")
+}
+
+/// Notes whether `text`, just put on the clipboard from `doc`, was copied
+/// from a block selection, so pasting it back is done column-wise.
+pub fn remember_copy(ctx: &egui::Context, doc: &Document, text: &str) {
+    ctx.data_mut(|data| {
+        if doc.block().is_some() {
+            data.insert_temp(block_clipboard_id(), text.to_string());
+        } else {
+            data.remove::<String>(block_clipboard_id());
+        }
+    });
+}
+
+/// Pastes `text` at every cursor, or column-wise at a lone cursor when it
+/// is a block copied from a block selection.
+pub fn paste(ctx: &egui::Context, doc: &mut Document, text: &str) -> bool {
+    let text = line_ending::normalize_str(text);
+    let copied_block = ctx.data(|data| data.get_temp::<String>(block_clipboard_id()));
+    let is_block = copied_block.is_some_and(|block| line_ending::normalize_str(&block) == text);
+    if is_block && doc.selections().len() == 1 && doc.selection().is_empty() {
+        return doc.insert_block(&text.split('\n').collect::<Vec<_>>());
+    }
+    doc.insert(&text, EditKind::Other)
+}
+
+fn vertical_target(
+    ui: &egui::Ui,
+    doc: &Document,
+    viewport: &Viewport,
+    preferred_x: &mut Option<f32>,
+    head: usize,
+    rows: usize,
+    up: bool,
+) -> usize {
+    let rope = doc.rope();
+    let scroll_x = doc.view().scroll_x;
+    let line = rope.char_to_line(head);
+    let col = head - rope.line_to_char(line);
+    let layout = viewport.layout_line(ui, rope, line, scroll_x);
+    let preferred_x = *preferred_x.get_or_insert_with(|| layout.x_of(col));
+
+    let delta = if up { -(rows as isize) } else { rows as isize };
+    match viewport.offset_row(ui, rope, line, layout.row_of(col), delta) {
+        Some((target_line, row)) => {
+            let layout = viewport.layout_line(ui, rope, target_line, scroll_x);
+            rope.line_to_char(target_line) + layout.col_at(preferred_x, row)
+        }
+        None if up => 0,
+        None => rope.len_chars(),
+    }
+}
+
+/// The line and column under `pos`, counting columns past the end of the
+/// line.
+fn block_hit_test(ui: &egui::Ui, doc: &Document, viewport: &Viewport, pos: Pos2) -> (usize, usize) {
+    let rope = doc.rope();
+    let view = doc.view();
+    let (line, row) = viewport.row_at_y(ui, rope, view.top_line, pos.y);
+    let layout = viewport.layout_line(ui, rope, line, view.scroll_x);
+    let x = pos.x - viewport.text_left(view.scroll_x);
+    (line, layout.virtual_col_at(x, row))
+}
+
+fn hit_test(ui: &egui::Ui, doc: &Document, viewport: &Viewport, pos: Pos2) -> usize {
+    let rope = doc.rope();
+    let view = doc.view();
+    let (line, row) = viewport.row_at_y(ui, rope, view.top_line, pos.y);
+    let layout = viewport.layout_line(ui, rope, line, view.scroll_x);
+    let x = pos.x - viewport.text_left(view.scroll_x);
+    rope.line_to_char(line) + layout.col_at(x, row)
+}
+
+fn clamp_view(ui: &egui::Ui, doc: &mut Document, viewport: &Viewport) {
+    let max_top = viewport.max_top(ui, doc.rope());
+    let view = doc.view_mut();
+    view.top_line = view.top_line.clamp(0.0, max_top);
+    view.scroll_x = view.scroll_x.max(0.0);
+}
+
+fn reveal_cursor(ui: &egui::Ui, doc: &mut Document, viewport: &Viewport) {
+    let rope = doc.rope();
+    let head = doc.selection().head;
+    let line = rope.char_to_line(head);
+    let col = head - rope.line_to_char(line);
+    let scroll_x = doc.view().scroll_x;
+    let layout = viewport.layout_line(ui, rope, line, scroll_x);
+    let cursor_x = layout.x_of(col);
+    // The scroll position that puts the cursor's row at the top, and the
+    // one that puts it at the bottom.
+    let at_top = line as f64 + layout.row_of(col) as f64 / layout.rows() as f64;
+    let at_bottom = viewport.scroll_rows(ui, rope, at_top, -(viewport.page_rows as f64 - 1.0));
+
+    let view = doc.view_mut();
+    if at_top < view.top_line {
+        view.top_line = at_top;
+    } else if at_bottom > view.top_line {
+        view.top_line = at_bottom;
+    }
+
+    let visible_width = viewport.text_rect.width() - TEXT_MARGIN * 2.0;
+    if cursor_x < view.scroll_x {
+        view.scroll_x = (cursor_x - viewport.char_width * 4.0).max(0.0);
+    } else if cursor_x > view.scroll_x + visible_width {
+        view.scroll_x = cursor_x - visible_width + viewport.char_width * 4.0;
+    }
+}
+
+fn scrollbars(
+    ui: &egui::Ui,
+    doc: &mut Document,
+    viewport: &Viewport,
+    state: &mut EditorState,
+    id: egui::Id,
+    vbar_rect: Rect,
+    hbar_rect: Rect,
+) {
+    let line_count = doc.rope().len_lines();
+    let max_top = viewport.max_top(ui, doc.rope());
+    let page = viewport.page_rows as f64;
+    let top_line = doc.view().top_line.clamp(0.0, max_top);
+    let vthumb = thumb_span(vbar_rect.y_range(), top_line, max_top, page);
+    let vresponse = ui.interact(vbar_rect, id.with("vscroll"), Sense::click_and_drag());
+    if let Some(fraction) = drag_scrollbar(
+        &vresponse,
+        &mut state.vscroll_grab,
+        vbar_rect.y_range(),
+        vthumb,
+        |pos| pos.y,
+    ) {
+        doc.view_mut().top_line = fraction * max_top;
+    }
+
+    let rope = doc.rope();
+    let view = doc.view();
+    let visible_width = viewport.text_rect.width();
+    // The cached longest line keeps the range steady while scrolling; the
+    // visible lines are measured too because wide glyphs outgrow the advance.
+    let first_line = view.top_line.max(0.0) as usize;
+    let last_line = (first_line + viewport.page_rows).min(line_count);
+    let widest = (first_line..last_line)
+        .map(|line| viewport.layout_line(ui, rope, line, view.scroll_x).width())
+        .fold(doc.longest_line() as f32 * viewport.char_width, f32::max);
+    let content_width = (widest + TEXT_MARGIN * 2.0).max(view.scroll_x + visible_width);
+    let max_x = (content_width - visible_width).max(0.0) as f64;
+    let hthumb = thumb_span(
+        hbar_rect.x_range(),
+        view.scroll_x as f64,
+        max_x,
+        visible_width as f64,
+    );
+    let hresponse = ui.interact(hbar_rect, id.with("hscroll"), Sense::click_and_drag());
+    if let Some(fraction) = drag_scrollbar(
+        &hresponse,
+        &mut state.hscroll_grab,
+        hbar_rect.x_range(),
+        hthumb,
+        |pos| pos.x,
+    ) {
+        doc.view_mut().scroll_x = (fraction * max_x) as f32;
+    }
+
+    let visuals = ui.visuals();
+    let painter = ui.painter();
+    let track_color = visuals.extreme_bg_color;
+    let thumb_color = |response: &egui::Response| {
+        if response.is_pointer_button_down_on() {
+            visuals.widgets.active.bg_fill
+        } else if response.hovered() {
+            visuals.widgets.hovered.bg_fill
+        } else {
+            visuals.widgets.inactive.bg_fill
+        }
+    };
+    painter.rect_filled(vbar_rect, 0.0, track_color);
+    painter.rect_filled(hbar_rect, 0.0, track_color);
+    let vthumb_rect = Rect::from_x_y_ranges(vbar_rect.shrink(2.0).x_range(), vthumb);
+    let hthumb_rect = Rect::from_x_y_ranges(hthumb, hbar_rect.shrink(2.0).y_range());
+    painter.rect_filled(vthumb_rect, 3.0, thumb_color(&vresponse));
+    painter.rect_filled(hthumb_rect, 3.0, thumb_color(&hresponse));
+}
+
+/// Marks where search matches are along the vertical scrollbar.
+fn scrollbar_ticks(ui: &egui::Ui, vbar_rect: Rect, ticks: &[f32]) {
+    let painter = ui.painter();
+    let color = ui.visuals().warn_fg_color.gamma_multiply(0.8);
+    for fraction in ticks {
+        let y = vbar_rect.top() + fraction * (vbar_rect.height() - 2.0);
+        let tick = Rect::from_x_y_ranges(vbar_rect.x_range(), y..=y + 2.0);
+        painter.rect_filled(tick, 0.0, color);
+    }
+}
+
+/// Where the thumb sits along `track` for a scroll `offset` out of `max`.
+fn thumb_span(track: egui::Rangef, offset: f64, max: f64, page: f64) -> egui::Rangef {
+    let length = track.span();
+    if max <= 0.0 {
+        return track;
+    }
+    let thumb = ((length as f64 * page / (max + page)) as f32)
+        .max(MIN_THUMB_LENGTH)
+        .min(length);
+    let start = track.min + ((offset / max) as f32) * (length - thumb);
+    egui::Rangef::new(start, start + thumb)
+}
+
+/// Turns pointer interaction on a scrollbar into a scroll fraction in `0..=1`.
+fn drag_scrollbar(
+    response: &egui::Response,
+    grab: &mut Option<f32>,
+    track: egui::Rangef,
+    thumb: egui::Rangef,
+    axis: impl Fn(Pos2) -> f32,
+) -> Option<f64> {
+    if !response.is_pointer_button_down_on() {
+        *grab = None;
+        return None;
+    }
+    let pointer = axis(response.interact_pointer_pos()?);
+    let grab = *grab.get_or_insert_with(|| {
+        if thumb.contains(pointer) {
+            pointer - thumb.min
+        } else {
+            thumb.span() / 2.0
+        }
+    });
+    let free = track.span() - thumb.span();
+    if free <= 0.0 {
+        return Some(0.0);
+    }
+    Some((((pointer - grab - track.min) / free).clamp(0.0, 1.0)) as f64)
+}
+
+fn paint(
+    ui: &egui::Ui,
+    doc: &Document,
+    viewport: &Viewport,
+    matches: &MatchHighlights,
+    gutter_rect: Rect,
+    has_focus: bool,
+) {
+    let visuals = ui.visuals();
+    let rope = doc.rope();
+    let view = doc.view();
+    let text_rect = viewport.text_rect;
+    let text_painter = ui.painter_at(text_rect);
+    text_painter.rect_filled(text_rect, 0.0, visuals.extreme_bg_color);
+
+    let line_count = rope.len_lines();
+    let first_line = (view.top_line.floor() as usize).min(line_count.saturating_sub(1));
+    let selections = doc.selections();
+    let primary = doc.selection();
+    let block = doc.block();
+    let left = viewport.text_left(view.scroll_x);
+    let match_fill = visuals.warn_fg_color.gamma_multiply(0.25);
+    let current_fill = visuals.warn_fg_color.gamma_multiply(0.55);
+    let current_stroke = egui::Stroke::new(1.5, visuals.warn_fg_color);
+
+    let gutter_painter = ui.painter_at(gutter_rect);
+    if gutter_rect.width() > 0.0 {
+        gutter_painter.rect_filled(gutter_rect, 0.0, visuals.faint_bg_color);
+    }
+
+    let row_height = viewport.row_height;
+    let mut y = text_rect.top();
+    for line in first_line..line_count {
+        if y > text_rect.bottom() {
+            break;
+        }
+        let layout = viewport.layout_line(ui, rope, line, view.scroll_x);
+        let height = layout.rows() as f32 * row_height;
+        if line == first_line {
+            y -= (view.top_line - first_line as f64) as f32 * height;
+        }
+        let line_start = rope.line_to_char(line);
+        let line_end = line_start + layout.len;
+
+        // Selections are sorted and disjoint, so their ends are sorted too.
+        let first = selections.partition_point(|selection| selection.range().end < line_start);
+        let on_line = selections[first..]
+            .iter()
+            .take_while(|selection| selection.range().start <= line_end);
+        // A block is painted as a rectangle, also past the end of short lines.
+        let block = block.filter(|block| block.lines().contains(&line));
+        let mut highlights = Vec::new();
+        let mut found = Vec::new();
+        let mut cursors = Vec::new();
+        let first_match = matches
+            .ranges
+            .partition_point(|range| range.end <= line_start);
+        for (index, range) in matches.ranges.iter().enumerate().skip(first_match) {
+            if range.start > line_end {
+                break;
+            }
+            let start_col = range.start.max(line_start) - line_start;
+            let end_col = range.end.min(line_end) - line_start;
+            let mut spans = layout.spans(start_col, end_col);
+            if range.end > line_end {
+                if let Some((_, _, end_x)) = spans.last_mut() {
+                    *end_x += viewport.char_width * 0.5;
+                }
+            }
+            let is_current = matches.current == Some(index);
+            found.extend(spans.into_iter().map(|span| (span, is_current)));
+        }
+        if let Some(block) = block {
+            let columns = block.columns();
+            if !columns.is_empty() {
+                highlights.extend(layout.spans(columns.start, columns.end));
+            }
+            let head = block.head_col;
+            cursors.push((
+                layout.row_of(head),
+                layout.x_of(head),
+                line == block.head_line,
+            ));
+        } else {
+            for selection in on_line {
+                let selected = selection.range();
+                if !selected.is_empty() && selected.end > line_start {
+                    let start_col = selected.start.max(line_start) - line_start;
+                    let end_col = selected.end.min(line_end) - line_start;
+                    let mut spans = layout.spans(start_col, end_col);
+                    if selected.end > line_end {
+                        if let Some((_, _, end_x)) = spans.last_mut() {
+                            *end_x += viewport.char_width * 0.5;
+                        }
+                    }
+                    highlights.extend(spans);
+                }
+                if (line_start..=line_end).contains(&selection.head) {
+                    let col = selection.head - line_start;
+                    cursors.push((layout.row_of(col), layout.x_of(col), *selection == primary));
+                }
+            }
+        }
+
+        let span_rect = |(row, start_x, end_x): (usize, f32, f32)| {
+            let row_y = y + row as f32 * row_height;
+            Rect::from_min_max(
+                pos2(left + start_x, row_y),
+                pos2(left + end_x, row_y + row_height),
+            )
+        };
+        for &(span, is_current) in &found {
+            let fill = if is_current { current_fill } else { match_fill };
+            text_painter.rect_filled(span_rect(span), 0.0, fill);
+        }
+        for span in highlights {
+            text_painter.rect_filled(span_rect(span), 0.0, visuals.selection.bg_fill);
+        }
+        // The current match is usually also the selection, so it gets an
+        // outline that shows through.
+        for &(span, _) in found.iter().filter(|(_, is_current)| *is_current) {
+            text_painter.rect_stroke(span_rect(span), 0.0, current_stroke);
+        }
+
+        text_painter.galley(
+            pos2(left + layout.origin_x, y),
+            layout.galley.clone(),
+            visuals.text_color(),
+        );
+
+        for (row, x, is_primary) in cursors {
+            if !has_focus {
+                break;
+            }
+            let x = left + x;
+            let row_y = y + row as f32 * row_height;
+            let cursor_rect = Rect::from_min_max(pos2(x, row_y), pos2(x + 1.0, row_y + row_height));
+            text_painter.line_segment(
+                [cursor_rect.center_top(), cursor_rect.center_bottom()],
+                visuals.text_cursor,
             );
-            editor_response = Some(
-                ui.add(
-                    egui::TextEdit::multiline(doc.text_mut())
-                        .id_source("plainpad_editor")
-                        .font(egui::TextStyle::Monospace)
-                        .desired_rows(24)
-                        .lock_focus(true)
-                        .desired_width(f32::INFINITY),
-                ),
+            if is_primary {
+                ui.ctx().output_mut(|output| {
+                    output.ime = Some(egui::output::IMEOutput {
+                        rect: text_rect,
+                        cursor_rect,
+                    });
+                });
+            }
+        }
+
+        // The number sits next to the first row the galley laid out.
+        if gutter_rect.width() > 0.0 {
+            let first_row_y = layout.galley.rows.first().map_or(0.0, |row| row.min_y());
+            gutter_painter.text(
+                pos2(gutter_rect.right() - 6.0, y + first_row_y),
+                egui::Align2::RIGHT_TOP,
+                (line + 1).to_string(),
+                viewport.font_id.clone(),
+                visuals.weak_text_color(),
             );
-        });
+        }
+        y += height;
+    }
+}
+
+fn previous_char(index: usize) -> usize {
+    index.saturating_sub(1)
+}
+
+fn next_char(rope: &Rope, index: usize) -> usize {
+    (index + 1).min(rope.len_chars())
+}
 
-        editor_response
-            .unwrap_or_else(|| ui.allocate_response(ui.available_size(), egui::Sense::hover()))
+#[derive(PartialEq, Eq)]
+enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+fn char_class(ch: char) -> CharClass {
+    if ch.is_whitespace() {
+        CharClass::Whitespace
+    } else if ch.is_alphanumeric() || ch == '_' {
+        CharClass::Word
     } else {
-        ui.add(
-            egui::TextEdit::multiline(doc.text_mut())
-                .id_source("plainpad_editor")
-                .font(egui::TextStyle::Monospace)
-                .desired_rows(24)
-                .lock_focus(true)
-                .desired_width(f32::INFINITY),
-        )
-    };
+        CharClass::Punctuation
+    }
+}
 
-    if response.changed() {
-        doc.sync_rope();
+fn previous_word_boundary(rope: &Rope, index: usize) -> usize {
+    let mut index = index;
+    while index > 0 && char_class(rope.char(index - 1)) == CharClass::Whitespace {
+        index -= 1;
+    }
+    if index > 0 {
+        let class = char_class(rope.char(index - 1));
+        while index > 0 && char_class(rope.char(index - 1)) == class {
+            index -= 1;
+        }
     }
+    index
+}
 
-    response
+fn next_word_boundary(rope: &Rope, index: usize) -> usize {
+    let len = rope.len_chars();
+    let mut index = index;
+    if index < len {
+        let class = char_class(rope.char(index));
+        if class != CharClass::Whitespace {
+            while index < len && char_class(rope.char(index)) == class {
+                index += 1;
+            }
+        }
+    }
+    while index < len && char_class(rope.char(index)) == CharClass::Whitespace {
+        index += 1;
+    }
+    index
+}
+
+pub fn word_range_at(rope: &Rope, index: usize) -> std::ops::Range<usize> {
+    let len = rope.len_chars();
+    if index >= len {
+        return index..index;
+    }
+    let class = char_class(rope.char(index));
+    let mut start = index;
+    while start > 0 && char_class(rope.char(start - 1)) == class {
+        start -= 1;
+    }
+    let mut end = index;
+    while end < len && char_class(rope.char(end)) == class {
+        end += 1;
+    }
+    start..end
+}
+
+fn smart_home(rope: &Rope, index: usize) -> usize {
+    let line = rope.char_to_line(index);
+    let line_start = rope.line_to_char(line);
+    let indent = rope
+        .line(line)
+        .chars()
+        .take_while(|ch| *ch == ' ' || *ch == '\t')
+        .count();
+    if index == line_start + indent {
+        line_start
+    } else {
+        line_start + indent
+    }
 }
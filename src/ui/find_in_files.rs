@@ -0,0 +1,153 @@
+use crate::search::SearchOptions;
+use eframe::egui;
+use std::path::Path;
+
+pub struct FindInFilesDialog {
+    pub open: bool,
+    pub root: String,
+    /// Comma-separated globs, such as `*.rs, *.toml`.
+    pub include: String,
+    pub exclude: String,
+    /// Files larger than this are skipped.
+    pub max_size_mb: u64,
+    pub skip_binary: bool,
+    pub use_gitignore: bool,
+    pub error: Option<String>,
+    /// Set when the dialog opens so the query gets the keyboard focus.
+    focus_input: bool,
+}
+
+impl Default for FindInFilesDialog {
+    fn default() -> Self {
+        Self {
+            open: false,
+            root: String::new(),
+            include: String::new(),
+            exclude: String::new(),
+            max_size_mb: 10,
+            skip_binary: true,
+            use_gitignore: true,
+            error: None,
+            focus_input: false,
+        }
+    }
+}
+
+impl FindInFilesDialog {
+    /// Opens the dialog, starting in `folder` when no folder was picked yet.
+    pub fn show(&mut self, folder: Option<&Path>) {
+        self.open = true;
+        self.error = None;
+        self.focus_input = true;
+        if let (true, Some(folder)) = (self.root.is_empty(), folder) {
+            self.root = folder.display().to_string();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FindInFilesAction {
+    Find,
+    /// Preview replacing the matches before writing any file.
+    Replace,
+    ChooseFolder,
+}
+
+/// Draws the dialog. The query, replacement and options are the find
+/// panel's, so both search the same way.
+pub fn find_in_files_dialog(
+    ctx: &egui::Context,
+    dialog: &mut FindInFilesDialog,
+    query: &mut String,
+    replacement: &mut String,
+    options: &mut SearchOptions,
+) -> Option<FindInFilesAction> {
+    let mut action = None;
+    let mut open = dialog.open;
+    egui::Window::new("Find in Files")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            egui::Grid::new("find_in_files_fields")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Find:");
+                    let response = ui.text_edit_singleline(query);
+                    if std::mem::take(&mut dialog.focus_input) {
+                        response.request_focus();
+                    }
+                    if response.lost_focus()
+                        && ui.input(|input| input.key_pressed(egui::Key::Enter))
+                    {
+                        action = Some(FindInFilesAction::Find);
+                    }
+                    ui.end_row();
+
+                    ui.label("Replace:");
+                    ui.text_edit_singleline(replacement);
+                    ui.end_row();
+
+                    ui.label("Folder:");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut dialog.root);
+                        if ui.button("Browse...").clicked() {
+                            action = Some(FindInFilesAction::ChooseFolder);
+                        }
+                    });
+                    ui.end_row();
+
+                    ui.label("Include:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut dialog.include).hint_text("*.rs, *.toml"),
+                    )
+                    .on_hover_text("Leave empty to search every file");
+                    ui.end_row();
+
+                    ui.label("Exclude:");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut dialog.exclude)
+                            .hint_text("target, *.min.js"),
+                    );
+                    ui.end_row();
+
+                    ui.label("Skip files over:");
+                    ui.add(
+                        egui::DragValue::new(&mut dialog.max_size_mb)
+                            .clamp_range(1..=4096)
+                            .suffix(" MB"),
+                    );
+                    ui.end_row();
+                });
+            ui.weak("A glob without / matches names at any depth; separate globs with commas.");
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut options.match_case, "Match case");
+                ui.checkbox(&mut options.whole_word, "Whole word");
+                ui.checkbox(&mut options.use_regex, "Use regex");
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut dialog.skip_binary, "Skip binary files");
+                ui.checkbox(&mut dialog.use_gitignore, "Skip .gitignore'd files");
+            });
+
+            if let Some(error) = &dialog.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Find All").clicked() {
+                    action = Some(FindInFilesAction::Find);
+                }
+                if ui
+                    .button("Replace...")
+                    .on_hover_text("Preview the replacements before any file is written")
+                    .clicked()
+                {
+                    action = Some(FindInFilesAction::Replace);
+                }
+            });
+        });
+    dialog.open = open;
+    action
+}
@@ -0,0 +1,209 @@
+use crate::document::line_column_index;
+use eframe::egui;
+use ropey::Rope;
+
+/// Where the Go To dialog was asked to put the cursor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GoToTarget {
+    /// 1-based line and column.
+    Line(usize, usize),
+    /// How far down the document, from 0 to 100.
+    Percent(f64),
+    /// Byte offset into the text as UTF-8.
+    Byte(usize),
+}
+
+impl GoToTarget {
+    /// Accepts `120`, `120:5`, `50%` and `b1024`.
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        let invalid =
+            || format!("`{input}` is not a line, line:column, percentage or byte offset.");
+        if let Some(percent) = input.strip_suffix('%') {
+            let percent = percent.trim().parse::<f64>().map_err(|_| invalid())?;
+            if !(0.0..=100.0).contains(&percent) {
+                return Err("A percentage must be between 0 and 100.".to_string());
+            }
+            return Ok(Self::Percent(percent));
+        }
+        if let Some(offset) = input.strip_prefix(['b', 'B']) {
+            let offset = offset.trim().parse().map_err(|_| invalid())?;
+            return Ok(Self::Byte(offset));
+        }
+        let (line, column) = match input.split_once(':') {
+            Some((line, column)) => (line, Some(column)),
+            None => (input, None),
+        };
+        let line = line.trim().parse().map_err(|_| invalid())?;
+        let column = match column {
+            Some(column) => column.trim().parse().map_err(|_| invalid())?,
+            None => 1,
+        };
+        Ok(Self::Line(line, column))
+    }
+
+    /// The char index the target points at in `rope`, clamped to the text.
+    /// A percentage goes to the start of its line, and a byte offset inside a
+    /// multibyte char to the start of that char.
+    pub fn char_index(self, rope: &Rope) -> usize {
+        match self {
+            Self::Line(line, column) => line_column_index(rope, line, column),
+            Self::Percent(percent) => {
+                let last_line = rope.len_lines() - 1;
+                let line = (last_line as f64 * percent.clamp(0.0, 100.0) / 100.0).round();
+                rope.line_to_char(line as usize)
+            }
+            Self::Byte(offset) => rope.byte_to_char(offset.min(rope.len_bytes())),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct GoToDialog {
+    pub open: bool,
+    pub input: String,
+    pub error: Option<String>,
+    /// Set when the dialog opens so the input gets the keyboard focus.
+    pub focus_input: bool,
+}
+
+impl GoToDialog {
+    pub fn show(&mut self) {
+        self.open = true;
+        self.error = None;
+        self.focus_input = true;
+    }
+}
+
+/// Draws the dialog. Returns the target once a valid one is entered.
+/// `current` is the cursor's line and column and `line_count` the number of
+/// lines, shown as a hint.
+pub fn go_to_dialog(
+    ctx: &egui::Context,
+    dialog: &mut GoToDialog,
+    current: (usize, usize),
+    line_count: usize,
+) -> Option<GoToTarget> {
+    let mut target = None;
+    let mut open = dialog.open;
+    let mut close = false;
+    egui::Window::new("Go To")
+        .open(&mut open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.label(format!(
+                "Line {} of {line_count}, column {}",
+                current.0, current.1
+            ));
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut dialog.input)
+                    .hint_text("line, line:col, 50% or b1024"),
+            );
+            if std::mem::take(&mut dialog.focus_input) {
+                response.request_focus();
+            }
+            let submitted =
+                response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+            if ui.input(|input| input.key_pressed(egui::Key::Escape)) {
+                close = true;
+            }
+
+            if let Some(error) = &dialog.error {
+                ui.colored_label(ui.visuals().error_fg_color, error);
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Go").clicked() || submitted {
+                    match GoToTarget::parse(&dialog.input) {
+                        Ok(parsed) => {
+                            target = Some(parsed);
+                            close = true;
+                        }
+                        Err(error) => {
+                            dialog.error = Some(error);
+                            response.request_focus();
+                        }
+                    }
+                }
+                if ui.button("Cancel").clicked() {
+                    close = true;
+                }
+            });
+        });
+    dialog.open = open && !close;
+    target
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(input: &str, text: &str) -> usize {
+        GoToTarget::parse(input)
+            .unwrap()
+            .char_index(&Rope::from_str(text))
+    }
+
+    #[test]
+    fn parses_each_form() {
+        assert_eq!(GoToTarget::parse("120"), Ok(GoToTarget::Line(120, 1)));
+        assert_eq!(GoToTarget::parse(" 12 : 5 "), Ok(GoToTarget::Line(12, 5)));
+        assert_eq!(GoToTarget::parse("50%"), Ok(GoToTarget::Percent(50.0)));
+        assert_eq!(GoToTarget::parse("b1024"), Ok(GoToTarget::Byte(1024)));
+        assert_eq!(GoToTarget::parse("B 7"), Ok(GoToTarget::Byte(7)));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_read() {
+        for input in ["", "abc", "12:", ":5", "-3", "b", "b-1", "%"] {
+            assert_eq!(
+                GoToTarget::parse(input),
+                Err(format!(
+                    "`{}` is not a line, line:column, percentage or byte offset.",
+                    input.trim()
+                ))
+            );
+        }
+        for input in ["101%", "-1%"] {
+            assert_eq!(
+                GoToTarget::parse(input),
+                Err("A percentage must be between 0 and 100.".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn lines_and_columns_are_clamped() {
+        let text = "one\ntwo\nthree";
+        assert_eq!(index("2:2", text), 5);
+        // Line and column 0 mean the first.
+        assert_eq!(index("0", text), 0);
+        assert_eq!(index("2:0", text), 4);
+        // Past the end of a line, or of the text.
+        assert_eq!(index("1:99", text), 3);
+        assert_eq!(index("99", text), 8);
+        assert_eq!(index("99:99", text), 13);
+    }
+
+    #[test]
+    fn percentages_go_to_the_start_of_a_line() {
+        let text = "a\nb\nc\nd\ne";
+        assert_eq!(index("0%", text), 0);
+        assert_eq!(index("50%", text), 4);
+        assert_eq!(index("100%", text), 8);
+        assert_eq!(index("100%", ""), 0);
+    }
+
+    #[test]
+    fn byte_offsets_land_on_whole_chars() {
+        let text = "aé€b";
+        assert_eq!(index("b0", text), 0);
+        assert_eq!(index("b1", text), 1);
+        // Inside `é` and inside `€`.
+        assert_eq!(index("b2", text), 1);
+        assert_eq!(index("b5", text), 2);
+        assert_eq!(index("b6", text), 3);
+        assert_eq!(index("b99", text), 4);
+    }
+}
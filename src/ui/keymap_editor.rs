@@ -0,0 +1,259 @@
+use crate::{
+    keymap::{Keymap, Preset},
+    shortcuts::{KeyChord, KeySequence, ShortcutCommand},
+};
+use eframe::egui::{self, Event, Key};
+
+#[derive(Default)]
+pub struct KeymapEditor {
+    pub open: bool,
+    filter: String,
+    recording: Option<Recording>,
+}
+
+/// Keys pressed so far for a new binding of `command`.
+struct Recording {
+    command: ShortcutCommand,
+    chords: Vec<KeyChord>,
+    error: Option<String>,
+}
+
+enum RecordingEnd {
+    Add(KeySequence),
+    Cancel,
+}
+
+#[derive(Default)]
+pub struct KeymapEditorAction {
+    /// The keymap after a change made in the window.
+    pub changed: Option<Keymap>,
+    pub open_keymap_file: bool,
+}
+
+impl KeymapEditor {
+    /// Whether key presses should go to the binding being recorded instead
+    /// of running shortcuts.
+    pub fn is_recording(&self) -> bool {
+        self.open && self.recording.is_some()
+    }
+
+    /// Takes this frame's key presses for the binding being recorded, before
+    /// the shortcuts or the editor see them. A third press starts over, and
+    /// Escape stops recording.
+    pub fn record_keys(&mut self, ctx: &egui::Context) {
+        let Some(recording) = &mut self.recording else {
+            return;
+        };
+        let mut cancel = false;
+        ctx.input_mut(|input| {
+            let modifiers = input.modifiers;
+            input.events.retain(|event| {
+                if let Event::Key {
+                    key: Key::Escape,
+                    pressed: true,
+                    modifiers,
+                    ..
+                } = event
+                {
+                    cancel |= modifiers.is_none();
+                }
+                if matches!(event, Event::Key { repeat: true, .. }) || cancel {
+                    return false;
+                }
+                let Some(chord) = KeyChord::from_event(event, modifiers) else {
+                    return !matches!(event, Event::Text(_));
+                };
+                if recording.chords.len() == 2 {
+                    recording.chords.clear();
+                }
+                recording.chords.push(chord);
+                recording.error = None;
+                false
+            });
+        });
+        if cancel {
+            self.recording = None;
+        }
+    }
+}
+
+/// Draws the Keyboard Shortcuts window while it is open. Every change applies
+/// right away.
+pub fn keymap_editor_window(
+    ctx: &egui::Context,
+    editor: &mut KeymapEditor,
+    keymap: &Keymap,
+) -> KeymapEditorAction {
+    let mut action = KeymapEditorAction::default();
+    let mut edited = keymap.clone();
+    let conflicts = keymap.conflicts();
+
+    egui::Window::new("Keyboard Shortcuts")
+        .open(&mut editor.open)
+        .collapsible(false)
+        .default_width(560.0)
+        .show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Preset");
+                for preset in Preset::ALL {
+                    if ui
+                        .selectable_label(keymap.preset() == preset, preset.label())
+                        .clicked()
+                    {
+                        edited.set_preset(preset);
+                    }
+                }
+            });
+            ui.add(
+                egui::TextEdit::singleline(&mut editor.filter)
+                    .hint_text("Filter by command or keys")
+                    .desired_width(f32::INFINITY),
+            );
+            if !conflicts.is_empty() {
+                let count = conflicts.len();
+                let noun = if count == 1 { "conflict" } else { "conflicts" };
+                ui.colored_label(ui.visuals().error_fg_color, format!("{count} {noun}"));
+            }
+            ui.separator();
+
+            let filter = editor.filter.trim().to_lowercase();
+            egui::ScrollArea::vertical()
+                .max_height(420.0)
+                .show(ui, |ui| {
+                    egui::Grid::new("keymap_bindings")
+                        .num_columns(3)
+                        .striped(true)
+                        .show(ui, |ui| {
+                            for command in ShortcutCommand::ALL {
+                                let bindings = keymap.bindings(command);
+                                if !matches_filter(&filter, command, bindings) {
+                                    continue;
+                                }
+                                ui.label(format!("{}: {}", command.category(), command.label()))
+                                    .on_hover_text(command.id());
+                                ui.vertical(|ui| {
+                                    for (index, sequence) in bindings.iter().enumerate() {
+                                        ui.horizontal(|ui| {
+                                            ui.label(sequence.label());
+                                            if ui
+                                                .small_button("✖")
+                                                .on_hover_text("Remove")
+                                                .clicked()
+                                            {
+                                                let mut sequences = bindings.to_vec();
+                                                sequences.remove(index);
+                                                edited.set_bindings(command, sequences);
+                                            }
+                                        });
+                                    }
+                                    if let Some(recording) = editor
+                                        .recording
+                                        .as_mut()
+                                        .filter(|recording| recording.command == command)
+                                    {
+                                        match recording_row(ui, recording) {
+                                            Some(RecordingEnd::Add(sequence)) => {
+                                                let mut sequences = bindings.to_vec();
+                                                if !sequences.contains(&sequence) {
+                                                    sequences.push(sequence);
+                                                }
+                                                edited.set_bindings(command, sequences);
+                                                editor.recording = None;
+                                            }
+                                            Some(RecordingEnd::Cancel) => editor.recording = None,
+                                            None => {}
+                                        }
+                                    }
+                                    for conflict in conflicts
+                                        .iter()
+                                        .filter(|conflict| conflict.commands.contains(&command))
+                                    {
+                                        ui.colored_label(
+                                            ui.visuals().error_fg_color,
+                                            conflict.describe(),
+                                        );
+                                    }
+                                });
+                                ui.horizontal(|ui| {
+                                    if ui.button("Add Keys").clicked() {
+                                        editor.recording = Some(Recording {
+                                            command,
+                                            chords: Vec::new(),
+                                            error: None,
+                                        });
+                                    }
+                                    if keymap.is_changed(command) && ui.button("Reset").clicked() {
+                                        edited.reset(command);
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                        });
+                });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Open Keymap File").clicked() {
+                    action.open_keymap_file = true;
+                }
+                if ui.button("Reset All to Preset").clicked() {
+                    edited.reset_all();
+                }
+            });
+        });
+
+    if !editor.open {
+        editor.recording = None;
+    }
+    if edited != *keymap {
+        action.changed = Some(edited);
+    }
+    action
+}
+
+/// Shows the keys recorded so far with Add and Cancel.
+fn recording_row(ui: &mut egui::Ui, recording: &mut Recording) -> Option<RecordingEnd> {
+    let mut end = None;
+    ui.horizontal(|ui| {
+        let keys = match recording.chords.as_slice() {
+            [] => "Press keys...".to_string(),
+            [first] => format!("{} ...", first.label()),
+            [first, second, ..] => format!("{} {}", first.label(), second.label()),
+        };
+        ui.strong(keys);
+        let ready = !recording.chords.is_empty();
+        if ui.add_enabled(ready, egui::Button::new("Add")).clicked() {
+            let first = recording.chords[0];
+            match KeySequence::new(first, recording.chords.get(1).copied()) {
+                Ok(sequence) => end = Some(RecordingEnd::Add(sequence)),
+                Err(err) => {
+                    recording.chords.clear();
+                    recording.error = Some(err);
+                }
+            }
+        }
+        if ui.button("Cancel").clicked() {
+            end = Some(RecordingEnd::Cancel);
+        }
+    });
+    if let Some(error) = &recording.error {
+        ui.colored_label(ui.visuals().error_fg_color, error);
+    }
+    end
+}
+
+/// Whether a command's name, id or keys contain the lowercase `filter`.
+fn matches_filter(filter: &str, command: ShortcutCommand, bindings: &[KeySequence]) -> bool {
+    if filter.is_empty() {
+        return true;
+    }
+    let name = format!("{}: {}", command.category(), command.label());
+    [name, command.id().to_string()]
+        .into_iter()
+        .chain(
+            bindings
+                .iter()
+                .flat_map(|sequence| [sequence.label(), sequence.to_string()]),
+        )
+        .any(|text| text.to_lowercase().contains(filter))
+}
@@ -1,3 +1,11 @@
+use crate::{
+    encoding::{self, TextEncoding},
+    keymap::Keymap,
+    line_ending::LineEnding,
+    settings::Backup,
+    shortcuts::{KeyChord, ShortcutCommand},
+    ui::editor_view::WordWrap,
+};
 use eframe::egui;
 
 #[derive(Debug, Clone, Copy)]
@@ -8,6 +16,15 @@ pub enum MenuAction {
     Save,
     SaveAs,
     SaveAll,
+    SaveWithEncoding(TextEncoding),
+    SetBackup(Backup),
+    SetAutosave(Option<u64>),
+    ToggleRestoreSession(bool),
+    ToggleKeepUndoHistory(bool),
+    Preferences,
+    KeyboardShortcuts,
+    CommandPalette,
+    ReopenWithEncoding(TextEncoding),
     CloseAll,
     CloseTab,
     CloseTabsLeft,
@@ -16,56 +33,154 @@ pub enum MenuAction {
     Undo,
     Redo,
     Find,
+    FindNext,
+    FindPrevious,
+    FindInTabs,
+    FindInFiles,
     Replace,
+    GoTo,
     Cut,
     Copy,
     Paste,
     SelectAll,
+    AddNextOccurrence,
+    SelectAllOccurrences,
+    ConvertLineEndings(LineEnding),
+    ToggleReadOnly(bool),
     ToggleStatusBar(bool),
     ToggleLineNumbers(bool),
+    SetWordWrap(WordWrap),
     Quit,
     CheckForUpdates,
 }
 
-pub fn menu_bar(
-    ui: &mut egui::Ui,
-    show_status_bar: bool,
-    show_line_numbers: bool,
-) -> Option<MenuAction> {
+/// What the menus need to know about the app to render checkmarks and
+/// enabled states.
+pub struct MenuState {
+    pub show_status_bar: bool,
+    pub show_line_numbers: bool,
+    pub word_wrap: WordWrap,
+    pub encoding: TextEncoding,
+    pub can_reopen: bool,
+    pub can_undo: bool,
+    pub can_redo: bool,
+    pub line_ending: LineEnding,
+    pub read_only: bool,
+    pub backup: Backup,
+    /// Idle seconds before tabs with a file are saved, if autosave is on.
+    pub autosave_secs: Option<u64>,
+    pub restore_session: bool,
+    pub keep_undo_history: bool,
+}
+
+/// Draws the menu bar, with each item's keys taken from `keymap`.
+pub fn menu_bar(ui: &mut egui::Ui, state: &MenuState, keymap: &Keymap) -> Option<MenuAction> {
     let mut action = None;
+    let keys = |label: &str, command: ShortcutCommand| match keymap.accelerator(command) {
+        Some(accelerator) => format!("{label}\t{accelerator}"),
+        None => label.to_string(),
+    };
 
     egui::menu::bar(ui, |ui| {
         ui.menu_button("File", |ui| {
-            if ui.button("New Tab\tCtrl+N").clicked() {
+            if ui
+                .button(keys("New Tab", ShortcutCommand::NewTab))
+                .clicked()
+            {
                 action = Some(MenuAction::NewTab);
                 ui.close_menu();
             }
-            if ui.button("Open...\tCtrl+O").clicked() {
+            if ui.button(keys("Open...", ShortcutCommand::Open)).clicked() {
                 action = Some(MenuAction::Open);
                 ui.close_menu();
             }
-            if ui.button("Print...\tCtrl+P").clicked() {
+            if ui
+                .button(keys("Print...", ShortcutCommand::Print))
+                .clicked()
+            {
                 action = Some(MenuAction::Print);
                 ui.close_menu();
             }
-            if ui.button("Save\tCtrl+S").clicked() {
+            if ui.button(keys("Save", ShortcutCommand::Save)).clicked() {
                 action = Some(MenuAction::Save);
                 ui.close_menu();
             }
-            if ui.button("Save As...\tCtrl+Shift+S").clicked() {
+            if ui
+                .button(keys("Save As...", ShortcutCommand::SaveAs))
+                .clicked()
+            {
                 action = Some(MenuAction::SaveAs);
                 ui.close_menu();
             }
-            if ui.button("Save All").clicked() {
+            if ui
+                .button(keys("Save All", ShortcutCommand::SaveAll))
+                .clicked()
+            {
                 action = Some(MenuAction::SaveAll);
                 ui.close_menu();
             }
+            ui.menu_button("Save with Encoding", |ui| {
+                if let Some(choice) = encoding_menu(ui, state.encoding) {
+                    action = Some(MenuAction::SaveWithEncoding(choice));
+                }
+            });
+            ui.menu_button("Backup on Save", |ui| {
+                let choices = [
+                    (Backup::Off, "Off"),
+                    (Backup::Sibling, "Keep .bak Copy"),
+                    (Backup::Timestamped, "Timestamped Copies in Folder..."),
+                ];
+                for (choice, label) in choices {
+                    if ui.selectable_label(choice == state.backup, label).clicked() {
+                        action = Some(MenuAction::SetBackup(choice));
+                        ui.close_menu();
+                    }
+                }
+            });
+            ui.menu_button("Autosave", |ui| {
+                let choices = [
+                    (None, "Off"),
+                    (Some(5), "After 5 Seconds Idle"),
+                    (Some(30), "After 30 Seconds Idle"),
+                    (Some(60), "After 1 Minute Idle"),
+                ];
+                for (choice, label) in choices {
+                    if ui
+                        .selectable_label(choice == state.autosave_secs, label)
+                        .clicked()
+                    {
+                        action = Some(MenuAction::SetAutosave(choice));
+                        ui.close_menu();
+                    }
+                }
+            });
+            let mut restore_session = state.restore_session;
+            if ui
+                .checkbox(&mut restore_session, "Reopen Tabs on Launch")
+                .clicked()
+            {
+                action = Some(MenuAction::ToggleRestoreSession(restore_session));
+                ui.close_menu();
+            }
+            ui.add_enabled_ui(state.can_reopen, |ui| {
+                ui.menu_button("Reopen with Encoding", |ui| {
+                    if let Some(choice) = encoding_menu(ui, state.encoding) {
+                        action = Some(MenuAction::ReopenWithEncoding(choice));
+                    }
+                });
+            });
             ui.separator();
-            if ui.button("Close All").clicked() {
+            if ui
+                .button(keys("Close All", ShortcutCommand::CloseAll))
+                .clicked()
+            {
                 action = Some(MenuAction::CloseAll);
                 ui.close_menu();
             }
-            if ui.button("Close Tab\tCtrl+W").clicked() {
+            if ui
+                .button(keys("Close Tab", ShortcutCommand::CloseTab))
+                .clicked()
+            {
                 action = Some(MenuAction::CloseTab);
                 ui.close_menu();
             }
@@ -77,7 +192,7 @@ pub fn menu_bar(
                 action = Some(MenuAction::CloseTabsRight);
                 ui.close_menu();
             }
-            if ui.button("Quit\tCtrl+Shift+W").clicked() {
+            if ui.button(keys("Quit", ShortcutCommand::Quit)).clicked() {
                 action = Some(MenuAction::Quit);
                 ui.close_menu();
             }
@@ -87,56 +202,227 @@ pub fn menu_bar(
             }
         });
         ui.menu_button("Edit", |ui| {
-            if ui.button("Undo\tCtrl+Z").clicked() {
+            if ui
+                .add_enabled(
+                    state.can_undo,
+                    egui::Button::new(keys("Undo", ShortcutCommand::Undo)),
+                )
+                .clicked()
+            {
                 action = Some(MenuAction::Undo);
                 ui.close_menu();
             }
-            if ui.button("Redo\tCtrl+Y").clicked() {
+            if ui
+                .add_enabled(
+                    state.can_redo,
+                    egui::Button::new(keys("Redo", ShortcutCommand::Redo)),
+                )
+                .clicked()
+            {
                 action = Some(MenuAction::Redo);
                 ui.close_menu();
             }
+            let mut keep_undo_history = state.keep_undo_history;
+            if ui
+                .checkbox(&mut keep_undo_history, "Keep Undo History After Closing")
+                .clicked()
+            {
+                action = Some(MenuAction::ToggleKeepUndoHistory(keep_undo_history));
+            }
             ui.separator();
-            if ui.button("Find...\tCtrl+F").clicked() {
+            if ui.button(keys("Find...", ShortcutCommand::Find)).clicked() {
                 action = Some(MenuAction::Find);
                 ui.close_menu();
             }
-            if ui.button("Replace...\tCtrl+H").clicked() {
+            if ui
+                .button(keys("Find Next", ShortcutCommand::FindNext))
+                .clicked()
+            {
+                action = Some(MenuAction::FindNext);
+                ui.close_menu();
+            }
+            if ui
+                .button(keys("Find Previous", ShortcutCommand::FindPrevious))
+                .clicked()
+            {
+                action = Some(MenuAction::FindPrevious);
+                ui.close_menu();
+            }
+            if ui
+                .button(keys("Find in Open Tabs", ShortcutCommand::FindInTabs))
+                .clicked()
+            {
+                action = Some(MenuAction::FindInTabs);
+                ui.close_menu();
+            }
+            if ui
+                .button(keys("Find in Files...", ShortcutCommand::FindInFiles))
+                .clicked()
+            {
+                action = Some(MenuAction::FindInFiles);
+                ui.close_menu();
+            }
+            if ui
+                .button(keys("Replace...", ShortcutCommand::Replace))
+                .clicked()
+            {
                 action = Some(MenuAction::Replace);
                 ui.close_menu();
             }
+            if ui.button(keys("Go To...", ShortcutCommand::GoTo)).clicked() {
+                action = Some(MenuAction::GoTo);
+                ui.close_menu();
+            }
             ui.separator();
-            if ui.button("Cut\tCtrl+X").clicked() {
+            if ui
+                .button(clipboard_keys(
+                    "Cut",
+                    keymap,
+                    ShortcutCommand::Cut,
+                    egui::Key::X,
+                ))
+                .clicked()
+            {
                 action = Some(MenuAction::Cut);
                 ui.close_menu();
             }
-            if ui.button("Copy\tCtrl+C").clicked() {
+            if ui
+                .button(clipboard_keys(
+                    "Copy",
+                    keymap,
+                    ShortcutCommand::Copy,
+                    egui::Key::C,
+                ))
+                .clicked()
+            {
                 action = Some(MenuAction::Copy);
                 ui.close_menu();
             }
-            if ui.button("Paste\tCtrl+V").clicked() {
+            if ui
+                .button(clipboard_keys(
+                    "Paste",
+                    keymap,
+                    ShortcutCommand::Paste,
+                    egui::Key::V,
+                ))
+                .clicked()
+            {
                 action = Some(MenuAction::Paste);
                 ui.close_menu();
             }
             ui.separator();
-            if ui.button("Select All\tCtrl+A").clicked() {
+            if ui
+                .button(keys("Select All", ShortcutCommand::SelectAll))
+                .clicked()
+            {
                 action = Some(MenuAction::SelectAll);
                 ui.close_menu();
             }
+            if ui
+                .button(keys(
+                    "Add Next Occurrence",
+                    ShortcutCommand::AddNextOccurrence,
+                ))
+                .clicked()
+            {
+                action = Some(MenuAction::AddNextOccurrence);
+                ui.close_menu();
+            }
+            if ui
+                .button(keys(
+                    "Select All Occurrences",
+                    ShortcutCommand::SelectAllOccurrences,
+                ))
+                .clicked()
+            {
+                action = Some(MenuAction::SelectAllOccurrences);
+                ui.close_menu();
+            }
+            ui.separator();
+            ui.menu_button("Convert Line Endings", |ui| {
+                for line_ending in LineEnding::ALL {
+                    if ui
+                        .selectable_label(
+                            line_ending == state.line_ending,
+                            line_ending.menu_label(),
+                        )
+                        .clicked()
+                    {
+                        action = Some(MenuAction::ConvertLineEndings(line_ending));
+                        ui.close_menu();
+                    }
+                }
+            });
+            let mut read_only = state.read_only;
+            if ui.checkbox(&mut read_only, "Read-Only").clicked() {
+                action = Some(MenuAction::ToggleReadOnly(read_only));
+                ui.close_menu();
+            }
+            ui.separator();
+            if ui
+                .button(keys("Preferences...", ShortcutCommand::Preferences))
+                .clicked()
+            {
+                action = Some(MenuAction::Preferences);
+                ui.close_menu();
+            }
+            if ui
+                .button(keys(
+                    "Keyboard Shortcuts...",
+                    ShortcutCommand::KeyboardShortcuts,
+                ))
+                .clicked()
+            {
+                action = Some(MenuAction::KeyboardShortcuts);
+                ui.close_menu();
+            }
         });
         ui.menu_button("View", |ui| {
-            let mut status_bar = show_status_bar;
+            if ui
+                .button(keys("Command Palette...", ShortcutCommand::CommandPalette))
+                .clicked()
+            {
+                action = Some(MenuAction::CommandPalette);
+                ui.close_menu();
+            }
+            ui.separator();
+            let mut status_bar = state.show_status_bar;
             if ui.checkbox(&mut status_bar, "Status Bar").clicked() {
                 action = Some(MenuAction::ToggleStatusBar(status_bar));
                 ui.close_menu();
             }
-            let mut line_numbers = show_line_numbers;
+            let mut line_numbers = state.show_line_numbers;
             if ui.checkbox(&mut line_numbers, "Line Numbers").clicked() {
                 action = Some(MenuAction::ToggleLineNumbers(line_numbers));
                 ui.close_menu();
             }
+            ui.menu_button("Word Wrap", |ui| {
+                let choices = [
+                    (WordWrap::Off, "Off"),
+                    (WordWrap::WindowEdge, "At Window Edge"),
+                    (WordWrap::Column(80), "At Column 80"),
+                    (WordWrap::Column(100), "At Column 100"),
+                    (WordWrap::Column(120), "At Column 120"),
+                ];
+                for (choice, label) in choices {
+                    if ui
+                        .selectable_label(choice == state.word_wrap, label)
+                        .clicked()
+                    {
+                        action = Some(MenuAction::SetWordWrap(choice));
+                        ui.close_menu();
+                    }
+                }
+            });
         });
         ui.menu_button("Help", |ui| {
-            if ui.button("Check for Updates...").clicked() {
+            if ui
+                .button(keys(
+                    "Check for Updates...",
+                    ShortcutCommand::CheckForUpdates,
+                ))
+                .clicked()
+            {
                 action = Some(MenuAction::CheckForUpdates);
                 ui.close_menu();
             }
@@ -145,3 +431,31 @@ pub fn menu_bar(
 
     action
 }
+
+/// Cut, Copy and Paste also run from the system shortcuts, so the menu shows
+/// those when the keymap leaves the command unbound.
+fn clipboard_keys(
+    label: &str,
+    keymap: &Keymap,
+    command: ShortcutCommand,
+    system_key: egui::Key,
+) -> String {
+    let accelerator = keymap
+        .accelerator(command)
+        .unwrap_or_else(|| KeyChord::command(system_key).label());
+    format!("{label}\t{accelerator}")
+}
+
+fn encoding_menu(ui: &mut egui::Ui, current: TextEncoding) -> Option<TextEncoding> {
+    let mut chosen = None;
+    for choice in encoding::choices() {
+        if ui
+            .selectable_label(choice == current, choice.label())
+            .clicked()
+        {
+            chosen = Some(choice);
+            ui.close_menu();
+        }
+    }
+    chosen
+}
@@ -1,3 +1,10 @@
+pub mod command_palette;
+pub mod diff;
 pub mod editor_view;
+pub mod find_in_files;
+pub mod go_to;
+pub mod keymap_editor;
 pub mod menu;
+pub mod preferences;
+pub mod results_panel;
 pub mod tabs;
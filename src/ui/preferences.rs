@@ -0,0 +1,178 @@
+use crate::settings::{
+    self, Backup, FontChoice, Settings, Theme, WrapChoice, FONT_SIZES, TAB_WIDTHS, WRAP_COLUMNS,
+};
+use eframe::egui;
+
+#[derive(Default)]
+pub struct PreferencesDialog {
+    pub open: bool,
+    /// The default extension as typed, which may not be valid yet.
+    extension: String,
+}
+
+#[derive(Default)]
+pub struct PreferencesAction {
+    /// The settings after a change made in the window.
+    pub changed: Option<Settings>,
+    pub choose_backup_folder: bool,
+    pub open_settings_file: bool,
+}
+
+/// Draws the Preferences window while it is open. Every change applies right
+/// away.
+pub fn preferences_window(
+    ctx: &egui::Context,
+    dialog: &mut PreferencesDialog,
+    settings: &Settings,
+) -> PreferencesAction {
+    let mut action = PreferencesAction::default();
+    let mut edited = settings.clone();
+
+    egui::Window::new("Preferences")
+        .open(&mut dialog.open)
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            ui.heading("Editor");
+            egui::Grid::new("preferences_editor")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Font");
+                    ui.horizontal(|ui| {
+                        let font = &mut edited.editor.font;
+                        ui.selectable_value(font, FontChoice::Monospace, "Monospace");
+                        ui.selectable_value(font, FontChoice::Proportional, "Proportional");
+                    });
+                    ui.end_row();
+
+                    ui.label("Font size");
+                    ui.add(
+                        egui::DragValue::new(&mut edited.editor.font_size)
+                            .clamp_range(FONT_SIZES)
+                            .speed(0.5),
+                    );
+                    ui.end_row();
+
+                    ui.label("Tab width");
+                    ui.add(
+                        egui::DragValue::new(&mut edited.editor.tab_width).clamp_range(TAB_WIDTHS),
+                    );
+                    ui.end_row();
+                });
+
+            ui.separator();
+            ui.heading("View");
+            egui::Grid::new("preferences_view")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Theme");
+                    ui.horizontal(|ui| {
+                        let theme = &mut edited.view.theme;
+                        ui.selectable_value(theme, Theme::System, "System");
+                        ui.selectable_value(theme, Theme::Light, "Light");
+                        ui.selectable_value(theme, Theme::Dark, "Dark");
+                    });
+                    ui.end_row();
+
+                    ui.label("Word wrap");
+                    ui.horizontal(|ui| {
+                        let wrap = &mut edited.view.word_wrap;
+                        ui.selectable_value(wrap, WrapChoice::Off, "Off");
+                        ui.selectable_value(wrap, WrapChoice::Window, "Window Edge");
+                        ui.selectable_value(wrap, WrapChoice::Column, "Column");
+                        ui.add_enabled(
+                            *wrap == WrapChoice::Column,
+                            egui::DragValue::new(&mut edited.view.wrap_column)
+                                .clamp_range(WRAP_COLUMNS),
+                        );
+                    });
+                    ui.end_row();
+
+                    ui.label("Show");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut edited.view.line_numbers, "Line Numbers");
+                        ui.checkbox(&mut edited.view.status_bar, "Status Bar");
+                    });
+                    ui.end_row();
+                });
+
+            ui.separator();
+            ui.heading("Saving");
+            egui::Grid::new("preferences_files")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Default extension");
+                    let response = ui
+                        .add(egui::TextEdit::singleline(&mut dialog.extension).desired_width(80.0));
+                    // A half-typed extension is not applied until it is valid.
+                    if !response.has_focus() {
+                        dialog.extension = settings.files.default_extension.clone();
+                    } else if let Some(extension) = settings::valid_extension(&dialog.extension) {
+                        edited.files.default_extension = extension.to_string();
+                    }
+                    ui.end_row();
+
+                    ui.label("Backup on save");
+                    ui.horizontal(|ui| {
+                        let backup = &mut edited.files.backup;
+                        ui.selectable_value(backup, Backup::Off, "Off");
+                        ui.selectable_value(backup, Backup::Sibling, ".bak Copy");
+                        if ui
+                            .selectable_label(*backup == Backup::Timestamped, "Timestamped")
+                            .clicked()
+                        {
+                            action.choose_backup_folder = true;
+                        }
+                    });
+                    ui.end_row();
+
+                    if let Some(folder) = &settings.files.backup_folder {
+                        ui.label("Backup folder");
+                        ui.horizontal(|ui| {
+                            ui.label(folder.display().to_string());
+                            if ui.button("Change...").clicked() {
+                                action.choose_backup_folder = true;
+                            }
+                        });
+                        ui.end_row();
+                    }
+
+                    ui.label("Autosave");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut edited.files.autosave_seconds)
+                                .clamp_range(0..=3600)
+                                .suffix(" s"),
+                        );
+                        ui.weak("idle, 0 is off");
+                    });
+                    ui.end_row();
+
+                    ui.label("On launch");
+                    ui.checkbox(&mut edited.files.reopen_tabs, "Reopen Tabs");
+                    ui.end_row();
+
+                    ui.label("Undo");
+                    ui.checkbox(
+                        &mut edited.files.keep_undo_history,
+                        "Keep History After Closing",
+                    );
+                    ui.end_row();
+                });
+
+            ui.separator();
+            ui.horizontal(|ui| {
+                if ui.button("Open Settings File").clicked() {
+                    action.open_settings_file = true;
+                }
+                if ui.button("Reset to Defaults").clicked() {
+                    edited = Settings::default();
+                }
+            });
+        });
+
+    if edited != *settings {
+        action.changed = Some(edited);
+    }
+    action
+}
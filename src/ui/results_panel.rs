@@ -0,0 +1,273 @@
+use crate::multi_search::{Hit, ResultGroup, Run, Source, Update};
+use eframe::egui::{self, text::LayoutJob, Stroke, TextFormat};
+use std::{collections::HashSet, ops::Range};
+
+#[derive(Default)]
+pub struct ResultsPanel {
+    pub open: bool,
+    /// Docked on the right instead of along the bottom.
+    pub on_right: bool,
+    title: String,
+    groups: Vec<ResultGroup>,
+    /// Indexes of the groups whose hits are hidden.
+    collapsed: HashSet<usize>,
+    run: Option<Run>,
+    truncated: bool,
+    /// Whether the results preview a replacement, with a checkbox per group.
+    preview: bool,
+    /// Indexes of the groups left out of the replacement.
+    unchecked: HashSet<usize>,
+    /// Shown instead of the results, such as the outcome of a replacement.
+    notice: Option<String>,
+}
+
+pub enum ResultsAction {
+    /// A result was clicked: go there and select the match.
+    Open {
+        source: Source,
+        /// Zero-based line and char column.
+        line: usize,
+        column: usize,
+        len: usize,
+    },
+    /// Replace was clicked in a preview: the groups still checked, each with
+    /// the char ranges of the matches it shows.
+    Replace(Vec<(Source, Vec<Range<usize>>)>),
+}
+
+impl ResultsPanel {
+    /// Shows the results of a new search, stopping the one before.
+    pub fn start(&mut self, title: String, run: Run) {
+        self.open = true;
+        self.title = title;
+        self.groups.clear();
+        self.collapsed.clear();
+        self.run = Some(run);
+        self.truncated = false;
+        self.preview = false;
+        self.unchecked.clear();
+        self.notice = None;
+    }
+
+    /// Like `start`, for a search whose hits carry their replacements.
+    pub fn start_preview(&mut self, title: String, run: Run) {
+        self.start(title, run);
+        self.preview = true;
+    }
+
+    /// Replaces the results with a message.
+    pub fn show_notice(&mut self, notice: String) {
+        self.groups.clear();
+        self.run = None;
+        self.preview = false;
+        self.notice = Some(notice);
+    }
+
+    /// Takes in what the running search found since the last frame.
+    pub fn poll(&mut self) {
+        let Some(run) = &self.run else {
+            return;
+        };
+        for update in run.poll() {
+            match update {
+                Update::Group(group) => self.groups.push(group),
+                Update::Done { truncated } => {
+                    self.truncated = truncated;
+                    self.run = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn summary(&self) -> String {
+        let matches: usize = self.groups.iter().map(|group| group.hits.len()).sum();
+        let places = self.groups.len();
+        let match_noun = if matches == 1 { "match" } else { "matches" };
+        let place_noun = if places == 1 { "place" } else { "places" };
+        let mut summary = format!("{matches} {match_noun} in {places} {place_noun}");
+        if self.run.is_some() {
+            summary.push_str(", searching...");
+        } else if self.truncated {
+            summary.push_str(", stopped at the match limit");
+        }
+        summary
+    }
+}
+
+/// Draws the results docked at the bottom or on the right while they are
+/// open. Must run before the central panel. Returns the result clicked.
+pub fn results_panel(ctx: &egui::Context, panel: &mut ResultsPanel) -> Option<ResultsAction> {
+    if !panel.open {
+        return None;
+    }
+    let mut action = None;
+    let mut contents = |ui: &mut egui::Ui, panel: &mut ResultsPanel| {
+        ui.horizontal(|ui| {
+            ui.strong(&panel.title);
+            match &panel.notice {
+                Some(notice) => ui.label(notice),
+                None => ui.label(panel.summary()),
+            };
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                if ui.small_button("✖").on_hover_text("Close").clicked() {
+                    panel.open = false;
+                    panel.run = None;
+                }
+                let (dock_label, dock_hint) = if panel.on_right {
+                    ("⬇", "Dock at the bottom")
+                } else {
+                    ("➡", "Dock on the right")
+                };
+                if ui
+                    .small_button(dock_label)
+                    .on_hover_text(dock_hint)
+                    .clicked()
+                {
+                    panel.on_right = !panel.on_right;
+                }
+                if panel.preview {
+                    let checked = (0..panel.groups.len())
+                        .filter(|group| !panel.unchecked.contains(group))
+                        .collect::<Vec<_>>();
+                    let noun = if checked.len() == 1 { "File" } else { "Files" };
+                    let button = egui::Button::new(format!("Replace in {} {noun}", checked.len()));
+                    let mut response =
+                        ui.add_enabled(panel.run.is_none() && !checked.is_empty(), button);
+                    if panel.truncated {
+                        response = response.on_hover_text(
+                            "Only the matches listed are replaced; the search stopped at the match limit",
+                        );
+                    }
+                    if response.clicked() {
+                        let groups = checked
+                            .into_iter()
+                            .map(|group| {
+                                let group = &panel.groups[group];
+                                let ranges = group.hits.iter().map(|hit| hit.range.clone());
+                                (group.source.clone(), ranges.collect())
+                            })
+                            .collect();
+                        action = Some(ResultsAction::Replace(groups));
+                    }
+                }
+            });
+        });
+        ui.separator();
+        // One row per group heading and per visible hit, so only the rows on
+        // screen are laid out even with thousands of results.
+        let rows = panel
+            .groups
+            .iter()
+            .enumerate()
+            .flat_map(|(group, results)| {
+                let hits = if panel.collapsed.contains(&group) {
+                    0
+                } else {
+                    results.hits.len()
+                };
+                std::iter::once((group, None)).chain((0..hits).map(move |hit| (group, Some(hit))))
+            })
+            .collect::<Vec<_>>();
+        let row_height = ui
+            .text_style_height(&egui::TextStyle::Monospace)
+            .max(ui.spacing().interact_size.y);
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .show_rows(ui, row_height, rows.len(), |ui, visible| {
+                for &(group_index, hit_index) in &rows[visible] {
+                    let group = &panel.groups[group_index];
+                    let Some(hit_index) = hit_index else {
+                        let open = !panel.collapsed.contains(&group_index);
+                        let arrow = if open { "⏷" } else { "⏵" };
+                        let heading = format!("{arrow} {} ({})", group.title, group.hits.len());
+                        ui.horizontal(|ui| {
+                            if panel.preview {
+                                let mut checked = !panel.unchecked.contains(&group_index);
+                                if ui.checkbox(&mut checked, "").changed() {
+                                    if checked {
+                                        panel.unchecked.remove(&group_index);
+                                    } else {
+                                        panel.unchecked.insert(group_index);
+                                    }
+                                }
+                            }
+                            if ui
+                                .selectable_label(false, egui::RichText::new(heading).strong())
+                                .clicked()
+                            {
+                                if open {
+                                    panel.collapsed.insert(group_index);
+                                } else {
+                                    panel.collapsed.remove(&group_index);
+                                }
+                            }
+                        });
+                        continue;
+                    };
+                    let hit = &group.hits[hit_index];
+                    let response =
+                        ui.add(egui::Label::new(hit_line(ui, hit)).sense(egui::Sense::click()));
+                    if response.hovered() {
+                        ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+                    }
+                    if response.clicked() {
+                        action = Some(ResultsAction::Open {
+                            source: group.source.clone(),
+                            line: hit.line,
+                            column: hit.column,
+                            len: hit.len,
+                        });
+                    }
+                }
+            });
+    };
+
+    if panel.on_right {
+        egui::SidePanel::right("search_results")
+            .resizable(true)
+            .default_width(360.0)
+            .show(ctx, |ui| contents(ui, panel));
+    } else {
+        egui::TopBottomPanel::bottom("search_results")
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui| contents(ui, panel));
+    }
+    action
+}
+
+/// "12: context" with the match drawn strong on a highlight. In a preview
+/// the match is struck through and followed by its replacement.
+fn hit_line(ui: &egui::Ui, hit: &Hit) -> LayoutJob {
+    let font_id = egui::TextStyle::Monospace.resolve(ui.style());
+    let visuals = ui.visuals();
+    let weak = TextFormat::simple(font_id.clone(), visuals.weak_text_color());
+    let normal = TextFormat::simple(font_id.clone(), visuals.text_color());
+    let strong = TextFormat {
+        background: visuals.warn_fg_color.gamma_multiply(0.3),
+        ..TextFormat::simple(font_id, visuals.strong_text_color())
+    };
+    let context = &hit.context;
+    let highlight = hit.highlight.clone();
+    let mut job = LayoutJob::default();
+    job.append(&format!("{:>6}: ", hit.line + 1), 0.0, weak);
+    job.append(&context[..highlight.start], 0.0, normal.clone());
+    match &hit.replacement {
+        Some(replacement) => {
+            let removed = TextFormat {
+                strikethrough: Stroke::new(1.0, visuals.text_color()),
+                ..strong.clone()
+            };
+            let added = TextFormat {
+                background: visuals.selection.bg_fill,
+                ..strong
+            };
+            job.append(&context[highlight.clone()], 0.0, removed);
+            job.append(replacement, 0.0, added);
+        }
+        None => job.append(&context[highlight.clone()], 0.0, strong),
+    }
+    job.append(&context[highlight.end..], 0.0, normal);
+    job
+}
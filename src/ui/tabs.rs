@@ -17,6 +17,9 @@ pub fn tab_bar(ui: &mut egui::Ui, documents: &[Document], active: usize) -> TabA
             if doc.is_dirty() {
                 title.push_str(" *");
             }
+            if doc.is_missing_on_disk() {
+                title.push_str(" (deleted)");
+            }
 
             if ui.selectable_label(index == active, title).clicked() {
                 action.select = Some(index);
@@ -0,0 +1,168 @@
+use eframe::egui;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs, hash,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::SystemTime,
+};
+
+/// What a file looked like on disk when it was last opened or saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiskState {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl DiskState {
+    pub fn new(meta: &fs::Metadata, hash: u64) -> Self {
+        Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            hash,
+        }
+    }
+
+    /// Cheap check against fresh metadata; the hash is only needed when the
+    /// size or time moved.
+    pub fn matches_metadata(&self, meta: &fs::Metadata) -> bool {
+        self.len == meta.len() && self.modified == meta.modified().ok()
+    }
+}
+
+/// How a file on disk compares with what a document last read or wrote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskChange {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+/// 64-bit FNV-1a. Stable across runs and builds, which `DefaultHasher` is not.
+#[derive(Clone)]
+pub struct ContentHasher(u64);
+
+impl Default for ContentHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl hash::Hasher for ContentHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::default();
+    hash::Hasher::write(&mut hasher, bytes);
+    hash::Hasher::finish(&hasher)
+}
+
+pub fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut hasher = HashingWriter::new(io::sink());
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finish())
+}
+
+/// Passes bytes through to `inner` while hashing them, so a save knows the
+/// hash of what it wrote without reading the file back.
+pub struct HashingWriter<W> {
+    inner: W,
+    hasher: ContentHasher,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: ContentHasher::default(),
+        }
+    }
+
+    pub fn finish(&self) -> u64 {
+        hash::Hasher::finish(&self.hasher)
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        hash::Hasher::write(&mut self.hasher, &buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Watches the folders of open files and reports paths that something else
+/// touched. The folders are watched rather than the files so editors that
+/// save by renaming a new file into place are still noticed.
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new(ctx: &egui::Context) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let ctx = ctx.clone();
+        let watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            if let Ok(event) = result {
+                for path in event.paths {
+                    let _ = sender.send(path);
+                }
+                ctx.request_repaint();
+            }
+        })?;
+        Ok(Self {
+            watcher,
+            events,
+            dirs: HashSet::new(),
+        })
+    }
+
+    /// Starts watching the folders of `paths` and stops watching folders no
+    /// open file lives in anymore.
+    pub fn sync<'a>(&mut self, paths: impl Iterator<Item = &'a PathBuf>) {
+        let wanted = paths
+            .filter_map(|path| path.parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .collect::<HashSet<_>>();
+        for dir in self.dirs.difference(&wanted) {
+            let _ = self.watcher.unwatch(dir);
+        }
+        self.dirs.retain(|dir| wanted.contains(dir));
+        for dir in wanted {
+            if !self.dirs.contains(&dir)
+                && self
+                    .watcher
+                    .watch(&dir, RecursiveMode::NonRecursive)
+                    .is_ok()
+            {
+                self.dirs.insert(dir);
+            }
+        }
+    }
+
+    /// Paths reported since the last call, without duplicates.
+    pub fn changed(&self) -> HashSet<PathBuf> {
+        self.events.try_iter().collect()
+    }
+}